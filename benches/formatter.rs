@@ -4,6 +4,7 @@
 //! precise measurement and optimization of the formatting logic.
 
 use criterion::{Criterion, Throughput, black_box, criterion_group, criterion_main};
+use ruuvitag_listener::measurement::Format;
 use ruuvitag_listener::{
     AliasMap, InfluxDbFormatter, MacAddress, Measurement, OutputFormatter, resolve_name,
 };
@@ -16,10 +17,16 @@ const TEST_MAC: MacAddress = MacAddress([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
 fn v5_measurement() -> Measurement {
     Measurement {
         mac: TEST_MAC,
+        format: Format::V5,
+        adapter: None,
+        phy: None,
         timestamp: SystemTime::UNIX_EPOCH,
         temperature: Some(24.30),
         humidity: Some(53.49),
         pressure: Some(100044.0),
+        pressure_sea_level: None,
+        pressure_trend_3h: None,
+        pressure_tendency: None,
         battery: Some(2.977),
         tx_power: Some(4),
         movement_counter: Some(66),
@@ -33,6 +40,7 @@ fn v5_measurement() -> Measurement {
         voc_index: None,
         nox_index: None,
         luminosity: None,
+        tag_id: None,
     }
 }
 
@@ -40,10 +48,16 @@ fn v5_measurement() -> Measurement {
 fn v6_measurement() -> Measurement {
     Measurement {
         mac: TEST_MAC,
+        format: Format::V6,
+        adapter: None,
+        phy: None,
         timestamp: SystemTime::UNIX_EPOCH,
         temperature: Some(23.12),
         humidity: Some(55.68),
         pressure: Some(100798.0),
+        pressure_sea_level: None,
+        pressure_trend_3h: None,
+        pressure_tendency: None,
         battery: None,
         tx_power: None,
         movement_counter: None,
@@ -57,6 +71,7 @@ fn v6_measurement() -> Measurement {
         voc_index: Some(100.0),
         nox_index: Some(1.0),
         luminosity: Some(25.5),
+        tag_id: None,
     }
 }
 
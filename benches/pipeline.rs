@@ -6,9 +6,17 @@
 
 use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
 use ruuvitag_listener::app::{Options, Scanner, run_with_io};
-use ruuvitag_listener::{Backend, MacAddress, MeasurementResult, ScanError, decode_ruuvi_data};
+use ruuvitag_listener::output::TimestampPrecision;
+use ruuvitag_listener::output::fields::MetaField;
+use ruuvitag_listener::output::influxdb::IntegerMode;
+use ruuvitag_listener::output::units::UnitSystem;
+use ruuvitag_listener::scanner::params::{CodedPhy, OwnAddressType};
+use ruuvitag_listener::{
+    Backend, MacAddress, MeasurementResult, ScanConfig, ScanError, decode_ruuvi_data,
+};
 use std::future::Future;
 use std::pin::Pin;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
 
@@ -63,7 +71,7 @@ impl Scanner for FakeScanner {
     fn start_scan(
         &self,
         _backend: Backend,
-        _verbose: bool,
+        _config: ScanConfig,
     ) -> Pin<
         Box<dyn Future<Output = Result<mpsc::Receiver<MeasurementResult>, ScanError>> + Send + '_>,
     > {
@@ -83,10 +91,51 @@ impl Scanner for FakeScanner {
 fn default_options() -> Options {
     Options {
         influxdb_measurement: "ruuvi_measurement".to_string(),
+        influxdb_tags: vec![MetaField::Mac, MetaField::Name],
+        influxdb_meta_fields: vec![],
+        influxdb_integers: IntegerMode::Float,
+        timestamp_precision: TimestampPrecision::Ns,
+        field_renames: vec![],
+        field_drops: vec![],
+        field_includes: vec![],
         aliases: vec![],
         verbose: false,
         throttle: None,
+        altitude: None,
+        tag_altitudes: vec![],
+        pressure_trend: false,
+        units: UnitSystem::Si,
+        unit_overrides: vec![],
+        unit_suffix: false,
         backend: Backend::Bluer,
+        adapters: vec![],
+        keep_duplicates: false,
+        scan_interval: Duration::from_millis(200),
+        scan_window: Duration::from_millis(200),
+        scan_type: None,
+        own_address_type: OwnAddressType::Public,
+        filter_duplicates: false,
+        coded_phy: CodedPhy::Auto,
+        scan_watchdog: None,
+        scan_watchdog_retries: 3,
+        record: None,
+        input: None,
+        replay_realtime: false,
+        hci_dump: None,
+        hci_dump_format: Default::default(),
+        mqtt_host: "localhost".to_string(),
+        mqtt_port: 1883,
+        mqtt_topic: "ruuvi/#".to_string(),
+        mqtt_username: None,
+        mqtt_password: None,
+        gateway_url: None,
+        gateway_token: None,
+        gateway_interval: Duration::from_secs(10),
+        simulate_tags: 10,
+        simulate_interval: Duration::from_secs(1),
+        simulate_loss: 0.0,
+        simulate_corrupt: 0.0,
+        simulate_seed: None,
     }
 }
 
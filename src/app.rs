@@ -4,17 +4,30 @@
 //! so it can be tested deterministically.
 
 use crate::alias::{Alias, AliasMap};
+use crate::capture::Recorder;
+use crate::hci_dump::{DumpFormat, DumpWriter};
 use crate::mac_address::MacAddress;
 use crate::measurement::{Format, Measurement};
-use crate::output::OutputFormatter;
-use crate::output::influxdb::InfluxDbFormatter;
-use crate::scanner::{Backend, MeasurementResult, ScanError};
+use crate::output::fields::{FieldMapping, FieldRename, MetaField};
+use crate::output::influxdb::{InfluxDbFormatter, IntegerMode};
+use crate::output::units::{UnitOverride, UnitSystem, Units};
+use crate::output::{OutputFormatter, TimestampPrecision};
+use crate::pressure::{PressureProcessor, TagAltitude};
+#[cfg(feature = "gateway")]
+use crate::scanner::gateway::http::GatewayConfig;
+#[cfg(feature = "mqtt")]
+use crate::scanner::mqtt::MqttConfig;
+use crate::scanner::params::{CodedPhy, OwnAddressType, ScanParameters, ScanType};
+use crate::scanner::simulate::SimulateConfig;
+use crate::scanner::{AdapterId, Backend, MeasurementResult, ScanConfig, ScanError, ScanTasks};
 use crate::throttle::Throttle;
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
 use std::collections::HashSet;
 use std::future::Future;
 use std::io;
 use std::io::Write;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::time::Duration;
 use thiserror::Error;
@@ -28,6 +41,51 @@ pub struct Options {
     #[arg(long, default_value = "ruuvi_measurement")]
     pub influxdb_measurement: String,
 
+    /// Metadata to write as InfluxDB tags, as a comma-separated list.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "mac,name",
+        value_name = "LIST"
+    )]
+    pub influxdb_tags: Vec<MetaField>,
+
+    /// Metadata to write as InfluxDB string fields, as a comma-separated list.
+    /// Metadata also listed in --influxdb-tags is written only as a tag.
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "LIST")]
+    pub influxdb_meta_fields: Vec<MetaField>,
+
+    /// How to write integer fields (tx_power, movement_counter,
+    /// measurement_sequence_number, tag_id). `float` keeps compatibility with
+    /// existing buckets; `integer` uses the `i` suffix; `unsigned` writes
+    /// counters and the tag ID with the `u` suffix.
+    #[arg(long, default_value_t, value_enum)]
+    pub influxdb_integers: IntegerMode,
+
+    /// Precision of output timestamps. Timestamps are rounded to the nearest
+    /// unit; `none` omits them so the receiver assigns one.
+    #[arg(long, default_value = "ns", value_enum)]
+    pub timestamp_precision: TimestampPrecision,
+
+    /// Rename an output field. Names refer to fields as they would be written,
+    /// including any unit suffix.
+    /// Format: --field-rename battery_potential=battery
+    #[arg(
+        long = "field-rename",
+        value_parser = crate::output::fields::parse_field_rename,
+        value_name = "FIELD=NAME"
+    )]
+    pub field_renames: Vec<FieldRename>,
+
+    /// Do not write the given fields, as a comma-separated list.
+    #[arg(long = "field-drop", value_delimiter = ',', value_name = "LIST")]
+    pub field_drops: Vec<String>,
+
+    /// Write only the given fields, as a comma-separated list.
+    #[arg(long = "field-include", value_delimiter = ',', value_name = "LIST")]
+    pub field_includes: Vec<String>,
+
     /// Specify human-readable alias for RuuviTag id.
     /// Format: --alias DE:AD:BE:EF:00:00=Sauna
     #[arg(long = "alias", value_parser = crate::alias::parse_alias, value_name = "ALIAS")]
@@ -43,9 +101,222 @@ pub struct Options {
     #[arg(long, value_parser = crate::throttle::parse_duration)]
     pub throttle: Option<Duration>,
 
+    /// Station altitude in meters, used to reduce pressure to sea level
+    /// (adds the `pressure_sea_level` field).
+    #[arg(long, value_name = "METERS", allow_hyphen_values = true)]
+    pub altitude: Option<f64>,
+
+    /// Specify altitude in meters for a single RuuviTag, overriding --altitude.
+    /// Format: --tag-altitude DE:AD:BE:EF:00:00=120
+    #[arg(
+        long = "tag-altitude",
+        value_parser = crate::pressure::parse_tag_altitude,
+        value_name = "ALTITUDE"
+    )]
+    pub tag_altitudes: Vec<TagAltitude>,
+
+    /// Track the three-hour pressure tendency per tag
+    /// (adds the `pressure_trend_3h` and `pressure_tendency` fields).
+    #[arg(long)]
+    pub pressure_trend: bool,
+
+    /// Unit system for temperature, pressure and humidity fields.
+    /// `si` writes °C, kPa and %; `metric-hpa` writes pressure in hPa;
+    /// `imperial` writes °F and inHg.
+    #[arg(long, default_value_t, value_enum)]
+    pub units: UnitSystem,
+
+    /// Override the unit of a single field, taking precedence over --units.
+    /// Format: --unit temperature=k, --unit pressure_sea_level=mmhg,
+    /// --unit humidity=ratio (written as `humidity_ratio`)
+    #[arg(
+        long = "unit",
+        value_parser = crate::output::units::parse_unit_override,
+        value_name = "FIELD=UNIT"
+    )]
+    pub unit_overrides: Vec<UnitOverride>,
+
+    /// Append the unit to field names, e.g. `temperature_c` or `pressure_hpa`.
+    #[arg(long)]
+    pub unit_suffix: bool,
+
     /// Bluetooth scanner backend to use
     #[arg(long, default_value_t, value_enum)]
     pub backend: Backend,
+
+    /// Bluetooth adapter to scan with, by name (hci1) or controller address.
+    /// Repeat to scan with several adapters at once.
+    /// Defaults to the system's default adapter.
+    #[arg(long = "adapter", value_name = "ADAPTER")]
+    pub adapters: Vec<AdapterId>,
+
+    /// When scanning with several adapters, write every copy of an
+    /// advertisement instead of only the first one received.
+    #[arg(long)]
+    pub keep_duplicates: bool,
+
+    /// Time between the starts of consecutive scan windows (HCI backend).
+    /// Accepts duration with suffix: 100ms, 1s.
+    #[arg(long, value_parser = crate::throttle::parse_duration, default_value = "200ms", value_name = "DURATION")]
+    pub scan_interval: Duration,
+
+    /// How long the controller listens during each scan interval (HCI backend).
+    /// Must not exceed the scan interval.
+    #[arg(long, value_parser = crate::throttle::parse_duration, default_value = "200ms", value_name = "DURATION")]
+    pub scan_window: Duration,
+
+    /// Scan passively or actively, sending scan requests. Defaults to passive
+    /// with the HCI backend and active with the BlueZ backend, which scans
+    /// passively with an advertisement monitor.
+    #[arg(long, value_enum)]
+    pub scan_type: Option<ScanType>,
+
+    /// Address type used for scan requests when scanning actively (HCI backend)
+    #[arg(long, default_value_t, value_enum)]
+    pub own_address_type: OwnAddressType,
+
+    /// Let the controller drop repeated advertisements of the same tag (HCI
+    /// backend). Many controllers then report each tag only once per scan.
+    #[arg(long)]
+    pub filter_duplicates: bool,
+
+    /// Also scan on the LE Coded PHY for long-range advertisements (HCI
+    /// backend). `auto` does so if the controller supports it.
+    #[arg(long, default_value_t, value_enum)]
+    pub coded_phy: CodedPhy,
+
+    /// Restart scanning if no RuuviTag advertisements arrive for this long.
+    /// Accepts duration with suffix: 30s, 2m, 1h.
+    #[arg(long, value_parser = crate::throttle::parse_duration, value_name = "DURATION")]
+    pub scan_watchdog: Option<Duration>,
+
+    /// Number of watchdog restarts without advertisements before exiting
+    /// with an error.
+    #[arg(long, default_value_t = 3, value_name = "N")]
+    pub scan_watchdog_retries: u32,
+
+    /// Record every received RuuviTag frame, including frames that fail to
+    /// decode, to this file as JSON lines. Appends if the file exists.
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Capture to replay with `--backend replay`, as written by --record, or
+    /// btsnoop/pcap file to replay with `--backend hci-dump`. With
+    /// `--backend hex`, the file or FIFO to read lines from, `tcp://HOST:PORT`
+    /// to read them from a TCP connection, or `-` for stdin (the default).
+    #[arg(
+        long,
+        value_name = "FILE",
+        required_if_eq_any([("backend", "replay"), ("backend", "hci-dump")])
+    )]
+    pub input: Option<PathBuf>,
+
+    /// Replay the capture with the spacing it was recorded with, instead of
+    /// as fast as possible.
+    #[arg(long)]
+    pub replay_realtime: bool,
+
+    /// Write the advertising report events read by the HCI backend to this
+    /// file, for Wireshark. Replaces the file if it exists.
+    #[arg(long, value_name = "FILE")]
+    pub hci_dump: Option<PathBuf>,
+
+    /// Format of the --hci-dump file
+    #[arg(long, default_value_t, value_enum)]
+    pub hci_dump_format: DumpFormat,
+
+    /// MQTT broker to subscribe to with `--backend mqtt`
+    #[arg(long, default_value = "localhost", value_name = "HOST")]
+    pub mqtt_host: String,
+
+    /// Port of the MQTT broker
+    #[arg(long, default_value_t = 1883, value_name = "PORT")]
+    pub mqtt_port: u16,
+
+    /// Topic filter the Ruuvi Gateways publish to
+    #[arg(long, default_value = "ruuvi/#", value_name = "TOPIC")]
+    pub mqtt_topic: String,
+
+    /// User name to log in to the MQTT broker with
+    #[arg(long, value_name = "USER", requires = "mqtt_password")]
+    pub mqtt_username: Option<String>,
+
+    /// Password to log in to the MQTT broker with. Prefer passing it in the
+    /// environment, where other users cannot see it.
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        requires = "mqtt_username",
+        env = "RUUVI_MQTT_PASSWORD",
+        hide_env_values = true
+    )]
+    pub mqtt_password: Option<String>,
+
+    /// Ruuvi Gateway to poll with `--backend gateway`, e.g. http://10.0.0.5
+    #[arg(long, value_name = "URL", required_if_eq("backend", "gateway"))]
+    pub gateway_url: Option<String>,
+
+    /// Bearer token for gateways that require authentication. Prefer passing
+    /// it in the environment, where other users cannot see it.
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        env = "RUUVI_GATEWAY_TOKEN",
+        hide_env_values = true
+    )]
+    pub gateway_token: Option<String>,
+
+    /// Time between polls of the gateway.
+    /// Accepts duration with suffix: 5s, 1m.
+    #[arg(long, value_parser = crate::throttle::parse_duration, default_value = "10s", value_name = "DURATION")]
+    pub gateway_interval: Duration,
+
+    /// Number of virtual tags to make up with `--backend simulate`
+    #[arg(long, default_value_t = 10, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub simulate_tags: u16,
+
+    /// Time between two frames of each virtual tag, or 0s for as fast as
+    /// they are processed. Accepts duration with suffix: 500ms, 1s.
+    #[arg(long, value_parser = crate::throttle::parse_duration, default_value = "1s", value_name = "DURATION")]
+    pub simulate_interval: Duration,
+
+    /// Share of simulated frames to drop, e.g. 0.05 or 5%
+    #[arg(long, value_parser = crate::scanner::simulate::parse_share, default_value = "0", value_name = "SHARE")]
+    pub simulate_loss: f64,
+
+    /// Share of simulated frames to corrupt so that they fail to decode,
+    /// e.g. 0.01 or 1%
+    #[arg(long, value_parser = crate::scanner::simulate::parse_share, default_value = "0", value_name = "SHARE")]
+    pub simulate_corrupt: f64,
+
+    /// Seed for the simulated values, to repeat a simulation
+    #[arg(long, value_name = "SEED")]
+    pub simulate_seed: Option<u64>,
+}
+
+impl Options {
+    /// Check the options that depend on each other, which clap cannot check
+    /// one at a time: no two fields may be written under the same name.
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        self.formatter()
+            .check_field_mapping()
+            .map_err(|e| Options::command().error(ErrorKind::ArgumentConflict, e))
+    }
+
+    /// The formatter writing measurements as selected by the output options.
+    fn formatter(&self) -> InfluxDbFormatter {
+        InfluxDbFormatter::new(self.influxdb_measurement.clone())
+            .with_units(Units::new(self.units, &self.unit_overrides))
+            .with_unit_suffix(self.unit_suffix)
+            .with_meta(&self.influxdb_tags, &self.influxdb_meta_fields)
+            .with_integers(self.influxdb_integers)
+            .with_timestamp_precision(self.timestamp_precision)
+            .with_field_mapping(FieldMapping::new(
+                &self.field_renames,
+                &self.field_drops,
+                &self.field_includes,
+            ))
+    }
 }
 
 /// Errors returned by the core run loop.
@@ -62,7 +333,7 @@ pub trait Scanner: Send + Sync {
     fn start_scan(
         &self,
         backend: Backend,
-        verbose: bool,
+        config: ScanConfig,
     ) -> Pin<
         Box<dyn Future<Output = Result<mpsc::Receiver<MeasurementResult>, ScanError>> + Send + '_>,
     >;
@@ -76,11 +347,11 @@ impl Scanner for RealScanner {
     fn start_scan(
         &self,
         backend: Backend,
-        verbose: bool,
+        config: ScanConfig,
     ) -> Pin<
         Box<dyn Future<Output = Result<mpsc::Receiver<MeasurementResult>, ScanError>> + Send + '_>,
     > {
-        Box::pin(async move { crate::scanner::start_scan(backend, verbose).await })
+        Box::pin(async move { crate::scanner::start_scan(backend, &config).await })
     }
 }
 
@@ -90,7 +361,8 @@ impl Scanner for RealScanner {
 /// Data format 6 exists only for Bluetooth 4 compatibility and is a strict
 /// subset of E1. Once a device has produced an E1 advertisement, its V6 frames
 /// carry no additional data, so they are dropped. E1 frames record the device
-/// in `e1_devices`; V5 is an unrelated lineage and is never suppressed.
+/// in `e1_devices`; formats 2 to 5 are an unrelated lineage and are never
+/// suppressed.
 ///
 /// Returns `true` if the measurement should be dropped.
 fn is_redundant_v6(e1_devices: &mut HashSet<MacAddress>, measurement: &Measurement) -> bool {
@@ -100,7 +372,7 @@ fn is_redundant_v6(e1_devices: &mut HashSet<MacAddress>, measurement: &Measureme
             false
         }
         Format::V6 => e1_devices.contains(&measurement.mac),
-        Format::V5 => false,
+        Format::V2 | Format::V3 | Format::V4 | Format::V5 => false,
     }
 }
 
@@ -111,12 +383,19 @@ fn write_measurement(
     out: &mut dyn Write,
 ) -> io::Result<()> {
     let line = formatter.format(measurement, name);
+    if line.is_empty() {
+        return Ok(());
+    }
     writeln!(out, "{line}")
 }
 
+/// How long shutdown waits for the backends to stop scanning.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Run the core processing loop, writing formatted output to `out` and verbose errors to `err`.
 ///
-/// - On successful measurements, it optionally applies throttling, formats them, and writes a line to `out`.
+/// - On successful measurements, it optionally derives pressure fields and applies throttling,
+///   formats them, and writes a line to `out`.
 /// - On decode errors, it writes the error to `err` only when `options.verbose` is true.
 pub async fn run_with_io(
     options: Options,
@@ -124,27 +403,128 @@ pub async fn run_with_io(
     out: &mut dyn Write,
     err: &mut dyn Write,
 ) -> Result<(), RunError> {
+    run_until(options, scanner, out, err, std::future::pending()).await
+}
+
+/// Run the core processing loop like [`run_with_io`] until `shutdown` completes.
+///
+/// On shutdown, the measurement stream is dropped, which makes the backends
+/// stop scanning. After waiting up to `SHUTDOWN_TIMEOUT` for them to finish,
+/// `out` is flushed and `Ok` is returned.
+pub async fn run_until(
+    options: Options,
+    scanner: &dyn Scanner,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), RunError> {
+    let mut shutdown = std::pin::pin!(shutdown);
     let aliases: AliasMap = crate::alias::to_map(&options.aliases);
-    let formatter = InfluxDbFormatter::new(options.influxdb_measurement);
+    let formatter = options.formatter();
 
     // Create throttle if interval is specified
     let mut throttle = options.throttle.map(Throttle::new);
 
+    // Only derive pressure fields when asked to, so the output is unchanged otherwise
+    let mut pressure =
+        (options.altitude.is_some() || !options.tag_altitudes.is_empty() || options.pressure_trend)
+            .then(|| {
+                PressureProcessor::new(
+                    options.altitude,
+                    &options.tag_altitudes,
+                    options.pressure_trend,
+                )
+            });
+
     // Devices seen emitting E1, whose redundant V6 frames we drop.
     let mut e1_devices: HashSet<MacAddress> = HashSet::new();
 
-    let mut measurements = scanner.start_scan(options.backend, options.verbose).await?;
+    let recorder = options
+        .record
+        .as_deref()
+        .map(Recorder::create)
+        .transpose()?;
+    let hci_dump = options
+        .hci_dump
+        .as_deref()
+        .map(|path| DumpWriter::create(path, options.hci_dump_format))
+        .transpose()?;
+
+    // Backends report errors that end scanning for good, e.g. the watchdog
+    // giving up, separately from the measurement stream
+    let (fatal_tx, mut fatal_rx) = mpsc::unbounded_channel();
+    let config = ScanConfig {
+        verbose: options.verbose,
+        adapters: options.adapters,
+        keep_duplicates: options.keep_duplicates,
+        scan: ScanParameters {
+            interval: options.scan_interval,
+            window: options.scan_window,
+            scan_type: options.scan_type,
+            own_address_type: options.own_address_type,
+            filter_duplicates: options.filter_duplicates,
+            coded_phy: options.coded_phy,
+        },
+        watchdog: options.scan_watchdog,
+        watchdog_retries: options.scan_watchdog_retries,
+        fatal_errors: Some(fatal_tx),
+        recorder,
+        hci_dump,
+        input: options.input,
+        replay_realtime: options.replay_realtime,
+        #[cfg(feature = "mqtt")]
+        mqtt: MqttConfig {
+            host: options.mqtt_host,
+            port: options.mqtt_port,
+            topic: options.mqtt_topic,
+            credentials: options.mqtt_username.zip(options.mqtt_password),
+        },
+        #[cfg(feature = "gateway")]
+        gateway: GatewayConfig {
+            url: options.gateway_url.unwrap_or_default(),
+            token: options.gateway_token,
+            interval: options.gateway_interval,
+        },
+        simulate: SimulateConfig {
+            tags: options.simulate_tags,
+            interval: options.simulate_interval,
+            loss: options.simulate_loss,
+            corrupt: options.simulate_corrupt,
+            seed: options.simulate_seed,
+        },
+        tasks: ScanTasks::default(),
+    };
+    let tasks = config.tasks.clone();
+    let mut measurements = scanner.start_scan(options.backend, config).await?;
 
-    while let Some(result) = measurements.recv().await {
+    let mut interrupted = false;
+    loop {
+        let result = tokio::select! {
+            biased;
+            Some(fatal) = fatal_rx.recv() => return Err(fatal.into()),
+            () = &mut shutdown => {
+                interrupted = true;
+                break;
+            }
+            result = measurements.recv() => match result {
+                Some(result) => result,
+                None => break,
+            },
+        };
         match result {
-            Ok(measurement) => {
+            Ok(mut measurement) => {
                 if is_redundant_v6(&mut e1_devices, &measurement) {
                     continue;
                 }
 
-                let should_emit = throttle
-                    .as_mut()
-                    .is_none_or(|t: &mut Throttle| t.should_emit(measurement.mac));
+                // Pressure history must see every sample, so process before throttling
+                if let Some(p) = pressure.as_mut() {
+                    p.process(&mut measurement);
+                }
+
+                let should_emit = throttle.as_mut().is_none_or(|t: &mut Throttle| {
+                    t.should_emit(measurement.mac, measurement.timestamp)
+                });
 
                 if should_emit {
                     let name = crate::alias::resolve_name(&measurement.mac, &aliases);
@@ -159,7 +539,19 @@ pub async fn run_with_io(
         }
     }
 
-    Ok(())
+    if interrupted {
+        drop(measurements);
+        let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, tasks.join()).await;
+        out.flush()?;
+        return Ok(());
+    }
+
+    out.flush()?;
+    // A fatal error may have been reported just before the stream ended
+    match fatal_rx.try_recv() {
+        Ok(fatal) => Err(fatal.into()),
+        Err(_) => Ok(()),
+    }
 }
 
 #[cfg(test)]
@@ -167,7 +559,9 @@ mod tests {
     use super::*;
     use crate::mac_address::MacAddress;
     use crate::scanner::DecodeError;
-    use std::sync::Mutex;
+    use crate::test_utils::{TEST_MAC, TempFile, base_measurement};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
     use std::time::SystemTime;
 
     #[derive(Debug)]
@@ -187,7 +581,7 @@ mod tests {
         fn start_scan(
             &self,
             _backend: Backend,
-            _verbose: bool,
+            _config: ScanConfig,
         ) -> Pin<
             Box<
                 dyn Future<Output = Result<mpsc::Receiver<MeasurementResult>, ScanError>>
@@ -209,6 +603,57 @@ mod tests {
         }
     }
 
+    fn default_options() -> Options {
+        Options {
+            influxdb_measurement: "ruuvi_measurement".to_string(),
+            influxdb_tags: vec![MetaField::Mac, MetaField::Name],
+            influxdb_meta_fields: vec![],
+            influxdb_integers: IntegerMode::Float,
+            timestamp_precision: TimestampPrecision::Ns,
+            field_renames: vec![],
+            field_drops: vec![],
+            field_includes: vec![],
+            aliases: vec![],
+            verbose: false,
+            throttle: None,
+            altitude: None,
+            tag_altitudes: vec![],
+            pressure_trend: false,
+            units: UnitSystem::Si,
+            unit_overrides: vec![],
+            unit_suffix: false,
+            backend: Backend::Bluer,
+            adapters: vec![],
+            keep_duplicates: false,
+            scan_interval: Duration::from_millis(200),
+            scan_window: Duration::from_millis(200),
+            scan_type: None,
+            own_address_type: OwnAddressType::Public,
+            filter_duplicates: false,
+            coded_phy: CodedPhy::Auto,
+            scan_watchdog: None,
+            scan_watchdog_retries: 3,
+            record: None,
+            input: None,
+            replay_realtime: false,
+            hci_dump: None,
+            hci_dump_format: DumpFormat::Btsnoop,
+            mqtt_host: "localhost".to_string(),
+            mqtt_port: 1883,
+            mqtt_topic: "ruuvi/#".to_string(),
+            mqtt_username: None,
+            mqtt_password: None,
+            gateway_url: None,
+            gateway_token: None,
+            gateway_interval: Duration::from_secs(10),
+            simulate_tags: 10,
+            simulate_interval: Duration::from_secs(1),
+            simulate_loss: 0.0,
+            simulate_corrupt: 0.0,
+            simulate_seed: None,
+        }
+    }
+
     fn measurement(mac: MacAddress, timestamp: SystemTime) -> Measurement {
         measurement_with_format(mac, timestamp, Format::V5)
     }
@@ -221,10 +666,15 @@ mod tests {
         Measurement {
             mac,
             format,
+            adapter: None,
+            phy: None,
             timestamp,
             temperature: Some(25.5),
             humidity: Some(60.0),
             pressure: Some(101_325.0),
+            pressure_sea_level: None,
+            pressure_trend_3h: None,
+            pressure_tendency: None,
             battery: Some(3.0),
             tx_power: Some(4),
             movement_counter: Some(10),
@@ -238,6 +688,7 @@ mod tests {
             voc_index: None,
             nox_index: None,
             luminosity: None,
+            tag_id: None,
         }
     }
 
@@ -248,13 +699,7 @@ mod tests {
         let m = measurement(mac, timestamp);
 
         let scanner = FakeScanner::new(vec![Ok(m)]);
-        let options = Options {
-            influxdb_measurement: "ruuvi_measurement".to_string(),
-            aliases: vec![],
-            verbose: false,
-            throttle: None,
-            backend: Backend::Bluer,
-        };
+        let options = default_options();
 
         let mut out = Vec::<u8>::new();
         let mut err = Vec::<u8>::new();
@@ -280,11 +725,8 @@ mod tests {
 
         let scanner = FakeScanner::new(vec![Ok(m1), Ok(m2)]);
         let options = Options {
-            influxdb_measurement: "ruuvi_measurement".to_string(),
-            aliases: vec![],
-            verbose: false,
             throttle: Some(Duration::from_secs(3600)),
-            backend: Backend::Bluer,
+            ..default_options()
         };
 
         let mut out = Vec::<u8>::new();
@@ -298,6 +740,275 @@ mod tests {
         assert_eq!(out.lines().count(), 1);
     }
 
+    #[tokio::test]
+    async fn run_adds_pressure_fields_only_when_configured() {
+        let mac = MacAddress([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let scanner = FakeScanner::new(vec![Ok(measurement(mac, timestamp))]);
+
+        let mut out = Vec::<u8>::new();
+        let mut err = Vec::<u8>::new();
+        run_with_io(default_options(), &scanner, &mut out, &mut err)
+            .await
+            .unwrap();
+        assert!(
+            !String::from_utf8(out)
+                .unwrap()
+                .contains("pressure_sea_level=")
+        );
+
+        let options = Options {
+            altitude: Some(0.0),
+            ..default_options()
+        };
+        let mut out = Vec::<u8>::new();
+        run_with_io(options, &scanner, &mut out, &mut err)
+            .await
+            .unwrap();
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("pressure_sea_level=101.325")
+        );
+    }
+
+    /// A scanner whose backend reports a fatal error while keeping the
+    /// measurement stream open.
+    struct FailingScanner;
+
+    impl Scanner for FailingScanner {
+        fn start_scan(
+            &self,
+            _backend: Backend,
+            config: ScanConfig,
+        ) -> Pin<
+            Box<
+                dyn Future<Output = Result<mpsc::Receiver<MeasurementResult>, ScanError>>
+                    + Send
+                    + '_,
+            >,
+        > {
+            Box::pin(async move {
+                let (tx, rx) = mpsc::channel::<MeasurementResult>(1);
+                tokio::spawn(async move {
+                    config.report_fatal(ScanError::NoData {
+                        adapter: "hci0".to_string(),
+                        idle: Duration::from_secs(120),
+                        restarts: 3,
+                    });
+                    // Keep the stream open; only the fatal error ends the run
+                    tx.closed().await;
+                });
+                Ok(rx)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn run_returns_fatal_scan_errors() {
+        let mut out = Vec::<u8>::new();
+        let mut err = Vec::<u8>::new();
+        let result = run_with_io(default_options(), &FailingScanner, &mut out, &mut err).await;
+
+        assert!(matches!(
+            result,
+            Err(RunError::Scan(ScanError::NoData { restarts: 3, .. }))
+        ));
+    }
+
+    /// A scanner whose backend sends one measurement and then scans until the
+    /// stream is dropped, recording that it stopped.
+    struct StoppableScanner {
+        stopped: Arc<AtomicBool>,
+    }
+
+    impl Scanner for StoppableScanner {
+        fn start_scan(
+            &self,
+            _backend: Backend,
+            config: ScanConfig,
+        ) -> Pin<
+            Box<
+                dyn Future<Output = Result<mpsc::Receiver<MeasurementResult>, ScanError>>
+                    + Send
+                    + '_,
+            >,
+        > {
+            let stopped = self.stopped.clone();
+            Box::pin(async move {
+                let (tx, rx) = mpsc::channel::<MeasurementResult>(1);
+                config.tasks.spawn(async move {
+                    let measurement = base_measurement(TEST_MAC, SystemTime::UNIX_EPOCH);
+                    let _ = tx.send(Ok(measurement)).await;
+                    tx.closed().await;
+                    stopped.store(true, Ordering::SeqCst);
+                });
+                Ok(rx)
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_until_stops_scanning_on_shutdown() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let scanner = StoppableScanner {
+            stopped: stopped.clone(),
+        };
+        let mut out = Vec::<u8>::new();
+        let mut err = Vec::<u8>::new();
+        let shutdown = tokio::time::sleep(Duration::from_secs(1));
+
+        let result = run_until(default_options(), &scanner, &mut out, &mut err, shutdown).await;
+
+        assert!(result.is_ok());
+        assert!(stopped.load(Ordering::SeqCst));
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn options_parse_scan_watchdog() {
+        let options = Options::try_parse_from(["ruuvitag-listener"]).unwrap();
+        assert_eq!(options.scan_watchdog, None);
+
+        let options =
+            Options::try_parse_from(["ruuvitag-listener", "--scan-watchdog", "2m"]).unwrap();
+        assert_eq!(options.scan_watchdog, Some(Duration::from_secs(120)));
+        assert_eq!(options.scan_watchdog_retries, 3);
+    }
+
+    #[tokio::test]
+    async fn run_replays_capture_through_pipeline() {
+        let path = TempFile::new("app-replay.jsonl");
+        std::fs::write(
+            &path,
+            concat!(
+                r#"{"timestamp":1000000000,"mac":"D4:E9:1A:3F:D8:C7","data":"0512fc5394c37c0004fffc040cac364200cdcbb8334c884f"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        let options = Options::try_parse_from([
+            "ruuvitag-listener".as_ref(),
+            "--backend".as_ref(),
+            "replay".as_ref(),
+            "--input".as_ref(),
+            path.as_os_str(),
+            "--alias".as_ref(),
+            "D4:E9:1A:3F:D8:C7=Sauna".as_ref(),
+        ])
+        .unwrap();
+
+        let mut out = Vec::<u8>::new();
+        let mut err = Vec::<u8>::new();
+        run_with_io(options, &RealScanner, &mut out, &mut err)
+            .await
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("name=Sauna"));
+        assert!(out.contains("temperature=24.3"));
+        assert!(out.ends_with(" 1000000000\n"));
+    }
+
+    #[test]
+    fn options_reject_colliding_field_renames() {
+        let parse = |args: &[&str]| {
+            let options = Options::try_parse_from(["ruuvitag-listener"].iter().chain(args));
+            options.unwrap().validate()
+        };
+
+        assert!(parse(&["--field-rename", "temperature=temp"]).is_ok());
+        let err = parse(&["--field-rename", "temperature=humidity"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        // The unit suffix changes the names renames refer to and collide with
+        assert!(parse(&["--unit-suffix", "--field-rename", "temperature_c=humidity"]).is_ok());
+        assert!(parse(&["--unit-suffix", "--field-rename", "temperature_c=co2"]).is_err());
+    }
+
+    #[test]
+    fn options_require_input_for_replay() {
+        assert!(Options::try_parse_from(["ruuvitag-listener", "--backend", "replay"]).is_err());
+    }
+
+    #[test]
+    fn options_take_secrets_from_the_environment() {
+        let command = Options::command();
+        let env = |id: &str| {
+            let arg = command.get_arguments().find(|arg| arg.get_id() == id);
+            arg.and_then(|arg| arg.get_env()).map(|env| env.to_owned())
+        };
+
+        assert_eq!(env("mqtt_password"), Some("RUUVI_MQTT_PASSWORD".into()));
+        assert_eq!(env("gateway_token"), Some("RUUVI_GATEWAY_TOKEN".into()));
+    }
+
+    #[test]
+    fn options_parse_scan_parameters() {
+        let options = Options::try_parse_from([
+            "ruuvitag-listener",
+            "--scan-interval",
+            "100ms",
+            "--scan-window",
+            "50ms",
+            "--scan-type",
+            "active",
+            "--own-address-type",
+            "random",
+            "--coded-phy",
+            "off",
+        ])
+        .unwrap();
+        assert_eq!(options.scan_interval, Duration::from_millis(100));
+        assert_eq!(options.scan_window, Duration::from_millis(50));
+        assert_eq!(options.scan_type, Some(ScanType::Active));
+        assert_eq!(options.own_address_type, OwnAddressType::Random);
+        assert!(!options.filter_duplicates);
+        assert_eq!(options.coded_phy, CodedPhy::Off);
+    }
+
+    #[tokio::test]
+    async fn run_skips_measurements_without_selected_fields() {
+        let mac = MacAddress([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let scanner = FakeScanner::new(vec![Ok(measurement(mac, timestamp))]);
+
+        let options = Options {
+            field_includes: vec!["co2".to_string()],
+            ..default_options()
+        };
+        let mut out = Vec::<u8>::new();
+        let mut err = Vec::<u8>::new();
+        run_with_io(options, &scanner, &mut out, &mut err)
+            .await
+            .unwrap();
+
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn options_parse_tag_and_field_lists() {
+        let options = Options::try_parse_from(["ruuvitag-listener"]).unwrap();
+        assert_eq!(options.influxdb_tags, vec![MetaField::Mac, MetaField::Name]);
+        assert!(options.influxdb_meta_fields.is_empty());
+
+        let options = Options::try_parse_from([
+            "ruuvitag-listener",
+            "--influxdb-tags",
+            "name,format",
+            "--influxdb-meta-fields",
+            "mac",
+            "--field-drop",
+            "tx_power,movement_counter",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.influxdb_tags,
+            vec![MetaField::Name, MetaField::Format]
+        );
+        assert_eq!(options.influxdb_meta_fields, vec![MetaField::Mac]);
+        assert_eq!(options.field_drops, vec!["tx_power", "movement_counter"]);
+    }
+
     #[test]
     fn is_redundant_v6_drops_v6_only_after_e1_seen_for_same_device() {
         let mac = MacAddress([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
@@ -347,13 +1058,7 @@ mod tests {
             Ok(measurement_with_format(mac, ts, Format::E1)),
             Ok(measurement_with_format(mac, ts, Format::V6)),
         ]);
-        let options = Options {
-            influxdb_measurement: "ruuvi_measurement".to_string(),
-            aliases: vec![],
-            verbose: false,
-            throttle: None,
-            backend: Backend::Bluer,
-        };
+        let options = default_options();
 
         let mut out = Vec::<u8>::new();
         let mut err = Vec::<u8>::new();
@@ -371,13 +1076,7 @@ mod tests {
             "bad packet".to_string(),
         ))]);
 
-        let base = Options {
-            influxdb_measurement: "ruuvi_measurement".to_string(),
-            aliases: vec![],
-            verbose: false,
-            throttle: None,
-            backend: Backend::Bluer,
-        };
+        let base = default_options();
 
         // non-verbose: nothing written
         let mut out = Vec::<u8>::new();
@@ -0,0 +1,266 @@
+//! Recording of raw RuuviTag advertisements to a capture file.
+//!
+//! With `--record`, every RuuviTag data payload a backend receives is
+//! written to a file as one JSON object per line, together with the outcome of
+//! decoding it. Frames that fail to decode are recorded too, so a capture holds
+//! the exact bytes needed to report a misbehaving tag:
+//!
+//! ```text
+//! {"timestamp":1700000000123456789,"mac":"D4:E9:1A:3F:D8:C7","adapter":"hci0","rssi":-61,"phy":"1M","data":"0512fc5394c37c0004fffc040cac364200cdcbb8334c884f","format":"5"}
+//! {"timestamp":1700000001123456789,"mac":"D4:E9:1A:3F:D8:C7","adapter":"hci0","rssi":-60,"phy":"1M","data":"0512fc","error":"Decoder error: ..."}
+//! ```
+//!
+//! `timestamp` is in nanoseconds since the Unix epoch and `data` is the
+//! manufacturer data after the Ruuvi company ID, hex encoded. For data formats 2
+//! and 4, which tags broadcast as an Eddystone URL, `data` is instead the bytes
+//! decoded from the URL (see [`eddystone`](crate::scanner::eddystone)); either
+//! kind starts with its data format and replays the same way. `adapter`, `rssi`
+//! and `phy` are left out when the backend does not know them.
+
+use crate::event::{self, Event};
+use crate::scanner::{Frame, MeasurementResult};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One line of a capture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// When the frame was received, in nanoseconds since the Unix epoch
+    pub timestamp: u64,
+    /// Address of the tag, e.g. `D4:E9:1A:3F:D8:C7`
+    pub mac: String,
+    /// Adapter that received the frame, e.g. `hci0`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<String>,
+    /// Received signal strength in dBm
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i16>,
+    /// PHY the frame was received on, e.g. `1M` or `coded`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phy: Option<String>,
+    /// Manufacturer data after the Ruuvi company ID, or the data decoded from
+    /// the Eddystone URL of formats 2 and 4, hex encoded
+    pub data: String,
+    /// Data format the frame was decoded as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Why decoding the frame failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Record {
+    /// Describe `frame`, received at `timestamp`, and the result of decoding it.
+    pub fn new(frame: &Frame<'_>, result: &MeasurementResult, timestamp: SystemTime) -> Self {
+        let (format, error) = match result {
+            Ok(measurement) => (Some(measurement.format.to_string()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Self {
+            timestamp: timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_nanos() as u64),
+            mac: frame.mac.to_string(),
+            adapter: frame.adapter.map(|adapter| adapter.to_string()),
+            rssi: frame.rssi,
+            phy: frame.phy.map(|phy| phy.to_string()),
+            data: to_hex(frame.data),
+            format,
+            error,
+        }
+    }
+}
+
+/// Encode `data` as lowercase hex.
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decode hex as written by [`to_hex`], accepting either case.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Writes received frames to a capture file.
+///
+/// Clones share the same file, so every backend and adapter records into one
+/// capture. Each line is written out as soon as it is complete, so the capture
+/// is usable even if the listener is killed.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Mutex<State>>,
+}
+
+struct State {
+    out: LineWriter<Box<dyn Write + Send>>,
+    failed: bool,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// Record into the file at `path`, appending if it exists.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        Ok(Self::new(file))
+    }
+
+    /// Record into `out`.
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(State {
+                out: LineWriter::new(Box::new(out)),
+                failed: false,
+            })),
+        }
+    }
+
+    /// Record `frame` and the result of decoding it.
+    ///
+    /// Recording never interrupts scanning. If writing fails, e.g. because the
+    /// disk is full, a `record_failed` event is written to stderr and
+    /// recording stops.
+    pub fn record(&self, frame: &Frame<'_>, result: &MeasurementResult) {
+        let record = Record::new(frame, result, SystemTime::now());
+        let mut state = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if state.failed {
+            return;
+        }
+        if let Err(e) = write_record(&mut state.out, &record) {
+            state.failed = true;
+            event::report(&Event::RecordFailed {
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+fn write_record(out: &mut impl Write, record: &Record) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    out.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::Phy;
+    use crate::scanner::DecodeError;
+    use crate::test_utils::{SharedBuffer, TEST_MAC, base_measurement};
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame<'a>(adapter: Option<&'a Arc<str>>, data: &'a [u8]) -> Frame<'a> {
+        Frame {
+            mac: TEST_MAC,
+            adapter,
+            rssi: Some(-61),
+            phy: Some(Phy::Le1M),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_record_decoded_frame() {
+        let adapter: Arc<str> = Arc::from("hci0");
+        let timestamp = UNIX_EPOCH + std::time::Duration::from_nanos(1_700_000_000_123_456_789);
+        let record = Record::new(
+            &frame(Some(&adapter), &[0x05, 0x12, 0xfc]),
+            &Ok(base_measurement(TEST_MAC, UNIX_EPOCH)),
+            timestamp,
+        );
+
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"timestamp":1700000000123456789,"mac":"AA:BB:CC:DD:EE:FF","adapter":"hci0","rssi":-61,"phy":"1M","data":"0512fc","format":"5"}"#
+        );
+    }
+
+    #[test]
+    fn test_record_failed_frame_without_metadata() {
+        let frame = Frame {
+            rssi: None,
+            phy: None,
+            ..frame(None, &[0x07])
+        };
+        let error = DecodeError::UnsupportedFormat("7".into());
+        let record = Record::new(&frame, &Err(error), UNIX_EPOCH);
+
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"timestamp":0,"mac":"AA:BB:CC:DD:EE:FF","data":"07","error":"Unsupported format: 7"}"#
+        );
+        let parsed: Record =
+            serde_json::from_str(&serde_json::to_string(&record).unwrap()).unwrap();
+        assert_eq!(parsed, record);
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        assert_eq!(to_hex(&[0x05, 0x12, 0xfc]), "0512fc");
+        assert_eq!(from_hex("0512FC"), Some(vec![0x05, 0x12, 0xfc]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("051"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_recorder_writes_one_line_per_frame() {
+        let buffer = SharedBuffer::default();
+        let recorder = Recorder::new(buffer.clone());
+        let data = [0x05, 0x12];
+
+        recorder.record(
+            &frame(None, &data),
+            &Ok(base_measurement(TEST_MAC, UNIX_EPOCH)),
+        );
+        recorder.clone().record(
+            &frame(None, &data),
+            &Err(DecodeError::InvalidData("x".into())),
+        );
+
+        let written = String::from_utf8(buffer.contents()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(r#""format":"5""#));
+        assert!(lines[1].contains(r#""error":"Invalid data: x""#));
+    }
+
+    #[test]
+    fn test_recorder_stops_after_write_error() {
+        let recorder = Recorder::new(FailingWriter);
+        recorder.record(
+            &frame(None, &[0x05]),
+            &Ok(base_measurement(TEST_MAC, UNIX_EPOCH)),
+        );
+        assert!(recorder.inner.lock().unwrap().failed);
+    }
+}
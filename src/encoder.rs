@@ -0,0 +1,545 @@
+//! Encoding of measurements into RuuviTag manufacturer data.
+//!
+//! The inverse of [`decode_ruuvi_data`](crate::scanner::decode_ruuvi_data),
+//! for simulators, test fixtures and gateways that re-broadcast measurements.
+//! Values are rounded to the resolution of the data format and limited to its
+//! range. Missing values, and values that are not a number, are encoded as the
+//! "not available" value of the field. Fields the data format does not carry,
+//! such as derived pressures, are left out.
+
+use crate::measurement::{Format, Measurement};
+use std::ops::RangeInclusive;
+
+/// Encode `measurement` in its data format, as the manufacturer data after
+/// the Ruuvi company ID.
+///
+/// Data format 6 only carries the last three bytes of the MAC address and the
+/// lowest byte of the measurement sequence. Data formats 2 to 4 have no "not
+/// available" values, so missing values are encoded as zero. Formats 2 and 4
+/// are encoded as the data in their Eddystone URL, as returned by
+/// [`eddystone::ruuvi_data`](crate::scanner::eddystone::ruuvi_data).
+///
+/// # Example
+///
+/// ```
+/// use ruuvitag_listener::{MacAddress, decode_ruuvi_data, encode_ruuvi_data};
+///
+/// let data = [
+///     0x05, 0x12, 0xFC, 0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04, 0xFF, 0xFC, 0x04, 0x0C, 0xAC, 0x36,
+///     0x42, 0x00, 0xCD, 0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F,
+/// ];
+/// let mac: MacAddress = "CB:B8:33:4C:88:4F".parse().unwrap();
+/// let measurement = decode_ruuvi_data(mac, &data).unwrap();
+/// assert_eq!(encode_ruuvi_data(&measurement), data);
+/// ```
+pub fn encode_ruuvi_data(measurement: &Measurement) -> Vec<u8> {
+    match measurement.format {
+        Format::V2 | Format::V4 => encode_url(measurement),
+        Format::V3 => encode_v3(measurement),
+        Format::V5 => encode_v5(measurement),
+        Format::V6 => encode_v6(measurement),
+        Format::E1 => encode_e1(measurement),
+    }
+}
+
+/// `value` in units of `resolution`, limited to `range`, or `invalid` if the
+/// value is missing.
+fn quantize(value: Option<f64>, resolution: f64, range: RangeInclusive<i64>, invalid: i64) -> i64 {
+    value
+        .filter(|value| !value.is_nan())
+        .map_or(invalid, |value| {
+            ((value / resolution).round() as i64).clamp(*range.start(), *range.end())
+        })
+}
+
+/// Temperature in 0.005 °C steps, as in all formats.
+fn temperature(measurement: &Measurement) -> [u8; 2] {
+    (quantize(measurement.temperature, 0.005, -32767..=32767, -32768) as i16).to_be_bytes()
+}
+
+/// Pressure in Pa above 50000 Pa, as in all formats.
+fn pressure(measurement: &Measurement) -> [u8; 2] {
+    let pressure = measurement.pressure.map(|pressure| pressure - 50_000.0);
+    (quantize(pressure, 1.0, 0..=65534, 65535) as u16).to_be_bytes()
+}
+
+/// An unsigned 16-bit field whose "not available" value is 65535.
+fn u16_field(value: Option<f64>, resolution: f64, max: u16) -> [u8; 2] {
+    (quantize(value, resolution, 0..=i64::from(max), 65535) as u16).to_be_bytes()
+}
+
+/// A 9-bit air quality index, or 511 if not available.
+fn index(value: Option<f64>) -> u16 {
+    quantize(value, 1.0, 0..=500, 511) as u16
+}
+
+/// The flags byte holding the lowest bits of the VOC and NOx indexes.
+fn index_flags(voc: u16, nox: u16) -> u8 {
+    ((nox & 1) << 7 | (voc & 1) << 6) as u8
+}
+
+/// The acceleration axes in g, each `None` if the acceleration is missing.
+fn acceleration(measurement: &Measurement) -> [Option<f64>; 3] {
+    match measurement.acceleration {
+        Some((x, y, z)) => [Some(x), Some(y), Some(z)],
+        None => [None; 3],
+    }
+}
+
+/// Humidity, temperature and pressure as laid out in data formats 2 to 4.
+fn rawv1_weather(measurement: &Measurement) -> [u8; 5] {
+    let humidity = quantize(measurement.humidity, 0.5, 0..=255, 0) as u8;
+    // Whole degrees with a sign bit, then hundredths
+    let temperature = quantize(measurement.temperature, 0.01, -12799..=12799, 0);
+    let sign = if temperature < 0 { 0x80 } else { 0 };
+    let temperature = temperature.unsigned_abs();
+    let pressure = measurement.pressure.map(|pressure| pressure - 50_000.0);
+    let [pressure_high, pressure_low] =
+        (quantize(pressure, 1.0, 0..=65535, 0) as u16).to_be_bytes();
+    [
+        humidity,
+        sign | (temperature / 100) as u8,
+        (temperature % 100) as u8,
+        pressure_high,
+        pressure_low,
+    ]
+}
+
+fn encode_url(measurement: &Measurement) -> Vec<u8> {
+    let mut data = vec![if measurement.format == Format::V2 {
+        2
+    } else {
+        4
+    }];
+    data.extend(rawv1_weather(measurement));
+    if measurement.format == Format::V4 {
+        // Only the upper six bits fit in the URL
+        data.push(measurement.tag_id.unwrap_or(0) & 0xFC);
+    }
+    data
+}
+
+fn encode_v3(measurement: &Measurement) -> Vec<u8> {
+    let mut data = vec![3];
+    data.extend(rawv1_weather(measurement));
+    for axis in acceleration(measurement) {
+        data.extend((quantize(axis, 0.001, -32768..=32767, 0) as i16).to_be_bytes());
+    }
+    data.extend((quantize(measurement.battery, 0.001, 0..=65535, 0) as u16).to_be_bytes());
+    data
+}
+
+fn encode_v5(measurement: &Measurement) -> Vec<u8> {
+    let mut data = vec![5];
+    data.extend(temperature(measurement));
+    data.extend(u16_field(measurement.humidity, 0.0025, 65534));
+    data.extend(pressure(measurement));
+    for axis in acceleration(measurement) {
+        data.extend((quantize(axis, 0.001, -32767..=32767, -32768) as i16).to_be_bytes());
+    }
+    // Battery voltage above 1.6 V in the upper 11 bits, TX power above
+    // -40 dBm in 2 dBm steps in the lower 5
+    let battery = measurement.battery.map(|battery| battery - 1.6);
+    let battery = quantize(battery, 0.001, 0..=2046, 2047) as u16;
+    let tx_power = measurement
+        .tx_power
+        .map(|tx_power| f64::from(tx_power) + 40.0);
+    let tx_power = quantize(tx_power, 2.0, 0..=30, 31) as u16;
+    data.extend((battery << 5 | tx_power).to_be_bytes());
+    let movement_counter = measurement.movement_counter.map(f64::from);
+    data.push(quantize(movement_counter, 1.0, 0..=254, 255) as u8);
+    let sequence = measurement.measurement_sequence.map(f64::from);
+    data.extend(u16_field(sequence, 1.0, 65534));
+    data.extend(measurement.mac.0);
+    data
+}
+
+fn encode_v6(measurement: &Measurement) -> Vec<u8> {
+    let voc = index(measurement.voc_index);
+    let nox = index(measurement.nox_index);
+    let mut data = vec![6];
+    data.extend(temperature(measurement));
+    data.extend(u16_field(measurement.humidity, 0.0025, 40000));
+    data.extend(pressure(measurement));
+    data.extend(u16_field(measurement.pm2_5, 0.1, 10000));
+    data.extend(u16_field(measurement.co2, 1.0, 40000));
+    data.push((voc >> 1) as u8);
+    data.push((nox >> 1) as u8);
+    data.push(luminosity_code(measurement.luminosity));
+    // Reserved
+    data.push(0xFF);
+    // The sequence has no "not available" value
+    data.push(measurement.measurement_sequence.unwrap_or_default() as u8);
+    data.push(index_flags(voc, nox));
+    data.extend(&measurement.mac.0[3..]);
+    data
+}
+
+/// The logarithmic code of `luminosity` in lux used by data format 6, or 255
+/// if not available.
+fn luminosity_code(luminosity: Option<f64>) -> u8 {
+    // Codes 0 to 254 span 0 to 65535 lux
+    let delta = 65_536f64.ln() / 254.0;
+    let code = luminosity.map(|lux| (lux.max(0.0) + 1.0).ln() / delta);
+    quantize(code, 1.0, 0..=254, 255) as u8
+}
+
+fn encode_e1(measurement: &Measurement) -> Vec<u8> {
+    let voc = index(measurement.voc_index);
+    let nox = index(measurement.nox_index);
+    let mut data = vec![0xE1];
+    data.extend(temperature(measurement));
+    data.extend(u16_field(measurement.humidity, 0.0025, 65534));
+    data.extend(pressure(measurement));
+    for pm in [
+        measurement.pm1_0,
+        measurement.pm2_5,
+        measurement.pm4_0,
+        measurement.pm10_0,
+    ] {
+        data.extend(u16_field(pm, 0.1, 65534));
+    }
+    data.extend(u16_field(measurement.co2, 1.0, 65534));
+    data.push((voc >> 1) as u8);
+    data.push((nox >> 1) as u8);
+    let luminosity = quantize(measurement.luminosity, 0.01, 0..=0xFF_FFFE, 0xFF_FFFF) as u32;
+    data.extend(&luminosity.to_be_bytes()[1..]);
+    // Reserved
+    data.extend([0; 3]);
+    let sequence = measurement.measurement_sequence.map(f64::from);
+    let sequence = quantize(sequence, 1.0, 0..=0xFF_FFFE, 0xFF_FFFF) as u32;
+    data.extend(&sequence.to_be_bytes()[1..]);
+    data.push(index_flags(voc, nox));
+    // Reserved
+    data.extend([0; 5]);
+    data.extend(measurement.mac.0);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::from_hex;
+    use crate::scanner::decode_ruuvi_data;
+    use crate::test_utils::{TEST_MAC, base_measurement};
+    use proptest::option;
+    use proptest::prelude::*;
+    use std::time::UNIX_EPOCH;
+
+    /// Whether `decoded` is within `tolerance` of `original`, or both are
+    /// missing.
+    fn close(decoded: Option<f64>, original: Option<f64>, tolerance: f64) -> bool {
+        match (decoded, original) {
+            (Some(decoded), Some(original)) => (decoded - original).abs() <= tolerance,
+            (decoded, original) => decoded.is_none() && original.is_none(),
+        }
+    }
+
+    /// Check that the fields of all formats survived the round trip.
+    fn assert_common(decoded: &Measurement, original: &Measurement) -> Result<(), TestCaseError> {
+        prop_assert_eq!(decoded.format, original.format);
+        prop_assert!(close(
+            decoded.temperature,
+            original.temperature,
+            0.0025 + 1e-9
+        ));
+        prop_assert!(close(decoded.humidity, original.humidity, 0.00125 + 1e-9));
+        prop_assert!(close(decoded.pressure, original.pressure, 0.5 + 1e-6));
+        Ok(())
+    }
+
+    fn measurement(format: Format) -> Measurement {
+        Measurement {
+            format,
+            ..base_measurement(TEST_MAC, UNIX_EPOCH)
+        }
+    }
+
+    fn common() -> impl Strategy<Value = (Option<f64>, Option<f64>, Option<f64>)> {
+        (
+            option::of(-163.835..163.835f64),
+            option::of(0.0..100.0f64),
+            option::of(50_000.0..115_534.0f64),
+        )
+    }
+
+    /// Data format 3 has no "not available" values, so all are present.
+    fn v3() -> impl Strategy<Value = Measurement> {
+        let acceleration = (-32.768..32.767f64, -32.768..32.767f64, -32.768..32.767f64);
+        (
+            -127.99..127.99f64,
+            0.0..127.5f64,
+            50_000.0..115_535.0f64,
+            acceleration,
+            0.0..65.535f64,
+        )
+            .prop_map(|(temperature, humidity, pressure, acceleration, battery)| {
+                Measurement {
+                    temperature: Some(temperature),
+                    humidity: Some(humidity),
+                    pressure: Some(pressure),
+                    acceleration: Some(acceleration),
+                    battery: Some(battery),
+                    ..measurement(Format::V3)
+                }
+            })
+    }
+
+    /// Data formats 2 and 4, with the tag ID as it survives the URL.
+    fn url() -> impl Strategy<Value = Measurement> {
+        (
+            prop_oneof![Just(Format::V2), Just(Format::V4)],
+            -127.99..127.99f64,
+            0.0..127.5f64,
+            50_000.0..115_535.0f64,
+            (0u8..64).prop_map(|tag_id| tag_id << 2),
+        )
+            .prop_map(
+                |(format, temperature, humidity, pressure, tag_id)| Measurement {
+                    temperature: Some(temperature),
+                    humidity: Some(humidity),
+                    pressure: Some(pressure),
+                    tag_id: (format == Format::V4).then_some(tag_id),
+                    ..measurement(format)
+                },
+            )
+    }
+
+    fn v5() -> impl Strategy<Value = Measurement> {
+        let acceleration = (-32.767..32.767f64, -32.767..32.767f64, -32.767..32.767f64);
+        (
+            common(),
+            option::of(acceleration),
+            option::of(1.6..3.646f64),
+            option::of((-20i8..=10).prop_map(|steps| steps * 2)),
+            option::of(0u32..=254),
+            option::of(0u32..=65534),
+        )
+            .prop_map(
+                |(
+                    (temperature, humidity, pressure),
+                    acceleration,
+                    battery,
+                    tx_power,
+                    movement_counter,
+                    measurement_sequence,
+                )| Measurement {
+                    temperature,
+                    humidity,
+                    pressure,
+                    acceleration,
+                    battery,
+                    tx_power,
+                    movement_counter,
+                    measurement_sequence,
+                    ..measurement(Format::V5)
+                },
+            )
+    }
+
+    fn v6() -> impl Strategy<Value = Measurement> {
+        (
+            common(),
+            option::of(0.0..1000.0f64),
+            option::of((0u16..=40000).prop_map(f64::from)),
+            option::of((0u16..=500).prop_map(f64::from)),
+            option::of((0u16..=500).prop_map(f64::from)),
+            option::of(0.0..65535.0f64),
+            0u32..=255,
+        )
+            .prop_map(
+                |(
+                    (temperature, humidity, pressure),
+                    pm2_5,
+                    co2,
+                    voc_index,
+                    nox_index,
+                    luminosity,
+                    sequence,
+                )| Measurement {
+                    temperature,
+                    humidity,
+                    pressure,
+                    pm2_5,
+                    co2,
+                    voc_index,
+                    nox_index,
+                    luminosity,
+                    measurement_sequence: Some(sequence),
+                    ..measurement(Format::V6)
+                },
+            )
+    }
+
+    fn e1() -> impl Strategy<Value = Measurement> {
+        let pm = || option::of(0.0..6553.4f64);
+        (
+            common(),
+            (pm(), pm(), pm(), pm()),
+            option::of((0u16..=65534).prop_map(f64::from)),
+            option::of((0u16..=500).prop_map(f64::from)),
+            option::of((0u16..=500).prop_map(f64::from)),
+            option::of(0.0..167_772.14f64),
+            option::of(0u32..=0xFF_FFFE),
+        )
+            .prop_map(
+                |(
+                    (temperature, humidity, pressure),
+                    (pm1_0, pm2_5, pm4_0, pm10_0),
+                    co2,
+                    voc_index,
+                    nox_index,
+                    luminosity,
+                    measurement_sequence,
+                )| Measurement {
+                    temperature,
+                    humidity,
+                    pressure,
+                    pm1_0,
+                    pm2_5,
+                    pm4_0,
+                    pm10_0,
+                    co2,
+                    voc_index,
+                    nox_index,
+                    luminosity,
+                    measurement_sequence,
+                    ..measurement(Format::E1)
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn test_v3_round_trip(original in v3()) {
+            let decoded = decode_ruuvi_data(TEST_MAC, &encode_ruuvi_data(&original)).unwrap();
+            prop_assert_eq!(decoded.format, Format::V3);
+            prop_assert!(close(decoded.temperature, original.temperature, 0.005 + 1e-9));
+            prop_assert!(close(decoded.humidity, original.humidity, 0.25 + 1e-9));
+            prop_assert!(close(decoded.pressure, original.pressure, 0.5 + 1e-6));
+            let (decoded_x, decoded_y, decoded_z) = decoded.acceleration.unwrap();
+            let (x, y, z) = original.acceleration.unwrap();
+            prop_assert!((decoded_x - x).abs() <= 0.0005 + 1e-9);
+            prop_assert!((decoded_y - y).abs() <= 0.0005 + 1e-9);
+            prop_assert!((decoded_z - z).abs() <= 0.0005 + 1e-9);
+            prop_assert!(close(decoded.battery, original.battery, 0.0005 + 1e-9));
+        }
+
+        #[test]
+        fn test_url_round_trip(original in url()) {
+            let decoded = decode_ruuvi_data(TEST_MAC, &encode_ruuvi_data(&original)).unwrap();
+            prop_assert_eq!(decoded.format, original.format);
+            prop_assert!(close(decoded.temperature, original.temperature, 0.005 + 1e-9));
+            prop_assert!(close(decoded.humidity, original.humidity, 0.25 + 1e-9));
+            prop_assert!(close(decoded.pressure, original.pressure, 0.5 + 1e-6));
+            prop_assert_eq!(decoded.tag_id, original.tag_id);
+        }
+
+        #[test]
+        fn test_v5_round_trip(original in v5()) {
+            let decoded = decode_ruuvi_data(TEST_MAC, &encode_ruuvi_data(&original)).unwrap();
+            assert_common(&decoded, &original)?;
+            match (decoded.acceleration, original.acceleration) {
+                (Some(decoded), Some(original)) => {
+                    prop_assert!((decoded.0 - original.0).abs() <= 0.0005 + 1e-9);
+                    prop_assert!((decoded.1 - original.1).abs() <= 0.0005 + 1e-9);
+                    prop_assert!((decoded.2 - original.2).abs() <= 0.0005 + 1e-9);
+                }
+                (decoded, original) => prop_assert_eq!(decoded, original),
+            }
+            prop_assert!(close(decoded.battery, original.battery, 0.0005 + 1e-9));
+            prop_assert_eq!(decoded.tx_power, original.tx_power);
+            prop_assert_eq!(decoded.movement_counter, original.movement_counter);
+            prop_assert_eq!(decoded.measurement_sequence, original.measurement_sequence);
+        }
+
+        #[test]
+        fn test_v6_round_trip(original in v6()) {
+            let decoded = decode_ruuvi_data(TEST_MAC, &encode_ruuvi_data(&original)).unwrap();
+            assert_common(&decoded, &original)?;
+            prop_assert!(close(decoded.pm2_5, original.pm2_5, 0.05 + 1e-9));
+            prop_assert_eq!(decoded.co2, original.co2);
+            prop_assert_eq!(decoded.voc_index, original.voc_index);
+            prop_assert_eq!(decoded.nox_index, original.nox_index);
+            // The luminosity code has a constant resolution on a log scale
+            let delta = 65_536f64.ln() / 254.0;
+            let log = |lux: Option<f64>| lux.map(|lux| (lux + 1.0).ln());
+            prop_assert!(close(log(decoded.luminosity), log(original.luminosity), delta / 2.0 + 1e-9));
+            prop_assert_eq!(decoded.measurement_sequence, original.measurement_sequence);
+        }
+
+        #[test]
+        fn test_e1_round_trip(original in e1()) {
+            let decoded = decode_ruuvi_data(TEST_MAC, &encode_ruuvi_data(&original)).unwrap();
+            assert_common(&decoded, &original)?;
+            prop_assert!(close(decoded.pm1_0, original.pm1_0, 0.05 + 1e-9));
+            prop_assert!(close(decoded.pm2_5, original.pm2_5, 0.05 + 1e-9));
+            prop_assert!(close(decoded.pm4_0, original.pm4_0, 0.05 + 1e-9));
+            prop_assert!(close(decoded.pm10_0, original.pm10_0, 0.05 + 1e-9));
+            prop_assert_eq!(decoded.co2, original.co2);
+            prop_assert_eq!(decoded.voc_index, original.voc_index);
+            prop_assert_eq!(decoded.nox_index, original.nox_index);
+            prop_assert!(close(decoded.luminosity, original.luminosity, 0.005 + 1e-9));
+            prop_assert_eq!(decoded.measurement_sequence, original.measurement_sequence);
+        }
+    }
+
+    #[test]
+    fn test_reference_payloads_round_trip() {
+        for hex in [
+            "03291A1ECE1EFC18F94202CA0B53",
+            "0300FF6300008001800180010000",
+            "0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F",
+            "06170C5668C79E007000C90501D9FFCD004C884F",
+        ] {
+            let data = from_hex(hex).unwrap();
+            let measurement = decode_ruuvi_data(TEST_MAC, &data).unwrap();
+            let mut encoded = encode_ruuvi_data(&measurement);
+            // Keep the reference address
+            let mac_length = match data[0] {
+                3 => 0,
+                6 => 3,
+                _ => 6,
+            };
+            let mac_start = encoded.len() - mac_length;
+            encoded[mac_start..].copy_from_slice(&data[mac_start..]);
+            assert_eq!(encoded, data, "{hex}");
+        }
+    }
+
+    #[test]
+    fn test_missing_values_stay_missing() {
+        for format in [Format::V5, Format::V6, Format::E1] {
+            let mut original = measurement(format);
+            original.temperature = Some(f64::NAN);
+            let decoded = decode_ruuvi_data(TEST_MAC, &encode_ruuvi_data(&original)).unwrap();
+            assert_eq!(decoded.temperature, None);
+            assert_eq!(decoded.humidity, None);
+            assert_eq!(decoded.pressure, None);
+            assert_eq!(decoded.co2, None);
+            assert_eq!(decoded.luminosity, None);
+            assert_eq!(decoded.battery, None);
+            assert_eq!(decoded.acceleration, None);
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_values_are_limited() {
+        let original = Measurement {
+            temperature: Some(500.0),
+            humidity: Some(-5.0),
+            pressure: Some(40_000.0),
+            co2: Some(100_000.0),
+            voc_index: Some(1000.0),
+            luminosity: Some(1e9),
+            ..measurement(Format::V6)
+        };
+        let decoded = decode_ruuvi_data(TEST_MAC, &encode_ruuvi_data(&original)).unwrap();
+        assert_eq!(decoded.temperature, Some(163.835));
+        assert_eq!(decoded.humidity, Some(0.0));
+        assert_eq!(decoded.pressure, Some(50_000.0));
+        assert_eq!(decoded.co2, Some(40_000.0));
+        assert_eq!(decoded.voc_index, Some(500.0));
+        assert_eq!(decoded.luminosity.map(f64::round), Some(65_535.0));
+    }
+}
@@ -0,0 +1,205 @@
+//! Events reported on stderr as logfmt lines.
+//!
+//! Backends and output files report what happens to them without interrupting
+//! scanning, e.g. a restarted scan or a failed write, one line per event:
+//!
+//! ```text
+//! event=scan_restart backend=hci adapter=hci1 attempt=1 delay_ms=1000
+//! event=record_failed error="No space left on device (os error 28)"
+//! ```
+
+use crate::scanner::{AdapterId, Backend};
+use std::fmt;
+use std::time::Duration;
+
+/// Something that happened to a backend or an output file, reported on
+/// stderr without interrupting scanning.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The scan ended and will be restarted after `delay`.
+    Restart {
+        backend: Backend,
+        adapter: Option<AdapterId>,
+        attempt: u32,
+        delay: Duration,
+    },
+    /// Restarting the scan failed; another attempt follows.
+    RestartFailed {
+        backend: Backend,
+        adapter: Option<AdapterId>,
+        attempt: u32,
+        error: String,
+    },
+    /// The scan was restarted and measurements flow again.
+    Resumed {
+        backend: Backend,
+        adapter: Option<AdapterId>,
+        attempt: u32,
+    },
+    /// No RuuviTag frames arrived for `idle`, so the backend restarted scanning
+    /// on the open adapter (see [`crate::scanner::watchdog`]).
+    WatchdogRestart {
+        backend: Backend,
+        adapter: Option<AdapterId>,
+        restart: u32,
+        idle: Duration,
+    },
+    /// Writing the `--record` capture failed, so recording stopped.
+    RecordFailed { error: String },
+    /// Writing the `--hci-dump` file failed, so dumping stopped.
+    HciDumpFailed { error: String },
+    /// The connection to the MQTT broker was lost; reconnecting follows.
+    MqttDisconnected { broker: String, error: String },
+    /// Polling the gateway failed; the next poll follows as usual.
+    GatewayPollFailed { url: String, error: String },
+}
+
+/// Write the adapter for an event, `default` when none was selected.
+fn fmt_adapter(f: &mut fmt::Formatter<'_>, adapter: &Option<AdapterId>) -> fmt::Result {
+    match adapter {
+        Some(adapter) => write!(f, "adapter={}", adapter),
+        None => write!(f, "adapter=default"),
+    }
+}
+
+impl fmt::Display for Event {
+    /// Format as a logfmt line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Restart {
+                backend,
+                adapter,
+                attempt,
+                delay,
+            } => {
+                write!(f, "event=scan_restart backend={} ", backend)?;
+                fmt_adapter(f, adapter)?;
+                write!(f, " attempt={} delay_ms={}", attempt, delay.as_millis())
+            }
+            Event::RestartFailed {
+                backend,
+                adapter,
+                attempt,
+                error,
+            } => {
+                write!(f, "event=scan_restart_failed backend={} ", backend)?;
+                fmt_adapter(f, adapter)?;
+                write!(f, " attempt={} error={:?}", attempt, error)
+            }
+            Event::Resumed {
+                backend,
+                adapter,
+                attempt,
+            } => {
+                write!(f, "event=scan_resumed backend={} ", backend)?;
+                fmt_adapter(f, adapter)?;
+                write!(f, " attempt={}", attempt)
+            }
+            Event::WatchdogRestart {
+                backend,
+                adapter,
+                restart,
+                idle,
+            } => {
+                write!(f, "event=scan_watchdog_restart backend={} ", backend)?;
+                fmt_adapter(f, adapter)?;
+                write!(f, " restart={} idle_ms={}", restart, idle.as_millis())
+            }
+            Event::RecordFailed { error } => write!(f, "event=record_failed error={:?}", error),
+            Event::HciDumpFailed { error } => {
+                write!(f, "event=hci_dump_failed error={:?}", error)
+            }
+            Event::MqttDisconnected { broker, error } => write!(
+                f,
+                "event=mqtt_disconnected broker={} error={:?}",
+                broker, error
+            ),
+            Event::GatewayPollFailed { url, error } => {
+                write!(f, "event=gateway_poll_failed url={} error={:?}", url, error)
+            }
+        }
+    }
+}
+
+/// Report an event on stderr.
+pub(crate) fn report(event: &Event) {
+    eprintln!("{}", event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Backends available in every build, as the events only name them
+    #[test]
+    fn test_event_logfmt() {
+        let event = Event::Restart {
+            backend: Backend::Replay,
+            adapter: Some(AdapterId::Index(1)),
+            attempt: 2,
+            delay: Duration::from_secs(2),
+        };
+        assert_eq!(
+            event.to_string(),
+            "event=scan_restart backend=replay adapter=hci1 attempt=2 delay_ms=2000"
+        );
+
+        let event = Event::RestartFailed {
+            backend: Backend::Simulate,
+            adapter: None,
+            attempt: 1,
+            error: "Bluetooth error: \"gone\"".to_string(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "event=scan_restart_failed backend=simulate adapter=default attempt=1 error=\"Bluetooth error: \\\"gone\\\"\""
+        );
+
+        let event = Event::Resumed {
+            backend: Backend::Replay,
+            adapter: None,
+            attempt: 3,
+        };
+        assert_eq!(
+            event.to_string(),
+            "event=scan_resumed backend=replay adapter=default attempt=3"
+        );
+
+        let event = Event::WatchdogRestart {
+            backend: Backend::Replay,
+            adapter: None,
+            restart: 1,
+            idle: Duration::from_secs(120),
+        };
+        assert_eq!(
+            event.to_string(),
+            "event=scan_watchdog_restart backend=replay adapter=default restart=1 idle_ms=120000"
+        );
+
+        let event = Event::RecordFailed {
+            error: "No space left on device (os error 28)".to_string(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "event=record_failed error=\"No space left on device (os error 28)\""
+        );
+
+        let event = Event::MqttDisconnected {
+            broker: "localhost:1883".to_string(),
+            error: "connection refused".to_string(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "event=mqtt_disconnected broker=localhost:1883 error=\"connection refused\""
+        );
+
+        let event = Event::GatewayPollFailed {
+            url: "http://10.0.0.5/history".to_string(),
+            error: "timed out".to_string(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "event=gateway_poll_failed url=http://10.0.0.5/history error=\"timed out\""
+        );
+    }
+}
@@ -0,0 +1,530 @@
+//! Reading and writing HCI traffic in btsnoop and pcap files.
+//!
+//! With `--hci-dump`, the HCI backend writes the LE Meta events it reads to a
+//! file that Wireshark opens directly, either in btsnoop format (HCI UART/H4
+//! datalink) or as pcap with `LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR`.
+//!
+//! [`DumpReader`] reads such files back, as well as btsnoop files written by
+//! `btmon -w` (Linux monitor datalink) and unencapsulated (H1) btsnoop files.
+//! Packets are returned in H4 framing, i.e. starting with the packet type byte,
+//! the same way the HCI backend reads them from its socket.
+
+use crate::event::{self, Event};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// btsnoop file identification pattern.
+const BTSNOOP_MAGIC: &[u8; 8] = b"btsnoop\0";
+
+/// btsnoop datalink: unencapsulated HCI (H1), without packet type byte.
+const BTSNOOP_HCI_H1: u32 = 1001;
+/// btsnoop datalink: HCI UART (H4), with packet type byte.
+const BTSNOOP_HCI_H4: u32 = 1002;
+/// btsnoop datalink: Linux monitor, as written by `btmon -w`.
+const BTSNOOP_MONITOR: u32 = 2001;
+
+/// btsnoop record flags of a received command or event.
+const BTSNOOP_FLAGS_RECEIVED_EVENT: u32 = 0b11;
+
+/// btsnoop timestamps count microseconds since midnight, January 1st, 0 AD.
+const BTSNOOP_EPOCH_DELTA_US: u64 = 0x00DC_DDB3_0F2F_8000;
+
+/// pcap magic for microsecond timestamps, as read in the writer's byte order.
+const PCAP_MAGIC: u32 = 0xA1B2_C3D4;
+/// pcap magic for nanosecond timestamps.
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+
+/// pcap linktype: H4 packets.
+const LINKTYPE_BLUETOOTH_HCI_H4: u32 = 187;
+/// pcap linktype: H4 packets preceded by a 4-byte direction header.
+const LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR: u32 = 201;
+
+/// Direction header value for packets received from the controller.
+const PHDR_RECEIVED: u32 = 1;
+
+/// H4 packet types.
+const H4_COMMAND: u8 = 0x01;
+const H4_ACL: u8 = 0x02;
+const H4_SCO: u8 = 0x03;
+const H4_EVENT: u8 = 0x04;
+const H4_ISO: u8 = 0x05;
+
+/// Longest packet accepted when reading, well above any HCI packet.
+const MAX_PACKET_LEN: usize = 1 << 16;
+
+/// File format written by [`DumpWriter`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DumpFormat {
+    /// btsnoop with the HCI UART (H4) datalink
+    #[default]
+    Btsnoop,
+    /// pcap with LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR
+    Pcap,
+}
+
+/// Writes HCI events to a btsnoop or pcap file.
+///
+/// Clones share the same file, so scans restarted by the supervisor keep
+/// writing into it. Each packet is written out right away, so the file can
+/// be opened while the listener is running.
+#[derive(Clone)]
+pub struct DumpWriter {
+    inner: Arc<Mutex<WriterState>>,
+}
+
+struct WriterState {
+    out: Box<dyn Write + Send>,
+    format: DumpFormat,
+    failed: bool,
+}
+
+impl fmt::Debug for DumpWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DumpWriter").finish_non_exhaustive()
+    }
+}
+
+impl DumpWriter {
+    /// Write to a new file at `path`, replacing any existing file.
+    pub fn create(path: &Path, format: DumpFormat) -> io::Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        Self::new(file, format)
+    }
+
+    /// Write to `out`, starting with the file header.
+    pub fn new(mut out: impl Write + Send + 'static, format: DumpFormat) -> io::Result<Self> {
+        out.write_all(&file_header(format))?;
+        out.flush()?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(WriterState {
+                out: Box::new(out),
+                format,
+                failed: false,
+            })),
+        })
+    }
+
+    /// Write an H4 event packet received at `timestamp`.
+    ///
+    /// Writing never interrupts scanning. If it fails, an `hci_dump_failed`
+    /// event is written to stderr and the dump stops.
+    pub fn write_event(&self, packet: &[u8], timestamp: SystemTime) {
+        let mut state = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if state.failed {
+            return;
+        }
+        let record = packet_record(state.format, packet, timestamp);
+        if let Err(e) = state
+            .out
+            .write_all(&record)
+            .and_then(|()| state.out.flush())
+        {
+            state.failed = true;
+            event::report(&Event::HciDumpFailed {
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+/// The header starting a file of `format`.
+fn file_header(format: DumpFormat) -> Vec<u8> {
+    let mut header = Vec::with_capacity(24);
+    match format {
+        DumpFormat::Btsnoop => {
+            header.extend_from_slice(BTSNOOP_MAGIC);
+            header.extend_from_slice(&1u32.to_be_bytes()); // version
+            header.extend_from_slice(&BTSNOOP_HCI_H4.to_be_bytes());
+        }
+        DumpFormat::Pcap => {
+            header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+            header.extend_from_slice(&2u16.to_le_bytes()); // version 2.4
+            header.extend_from_slice(&4u16.to_le_bytes());
+            header.extend_from_slice(&0i32.to_le_bytes()); // UTC
+            header.extend_from_slice(&0u32.to_le_bytes()); // timestamp accuracy
+            header.extend_from_slice(&(MAX_PACKET_LEN as u32).to_le_bytes()); // snaplen
+            header.extend_from_slice(&LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR.to_le_bytes());
+        }
+    }
+    header
+}
+
+/// One record of a file of `format` holding the received event `packet`.
+fn packet_record(format: DumpFormat, packet: &[u8], timestamp: SystemTime) -> Vec<u8> {
+    let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut record = Vec::with_capacity(packet.len() + 24);
+    match format {
+        DumpFormat::Btsnoop => {
+            let len = packet.len() as u32;
+            let micros = since_epoch.as_micros() as u64 + BTSNOOP_EPOCH_DELTA_US;
+            record.extend_from_slice(&len.to_be_bytes()); // original length
+            record.extend_from_slice(&len.to_be_bytes()); // included length
+            record.extend_from_slice(&BTSNOOP_FLAGS_RECEIVED_EVENT.to_be_bytes());
+            record.extend_from_slice(&0u32.to_be_bytes()); // cumulative drops
+            record.extend_from_slice(&micros.to_be_bytes());
+            record.extend_from_slice(packet);
+        }
+        DumpFormat::Pcap => {
+            let len = packet.len() as u32 + 4;
+            record.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+            record.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+            record.extend_from_slice(&len.to_le_bytes()); // included length
+            record.extend_from_slice(&len.to_le_bytes()); // original length
+            record.extend_from_slice(&PHDR_RECEIVED.to_be_bytes());
+            record.extend_from_slice(packet);
+        }
+    }
+    record
+}
+
+/// A packet read from a dump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// When the packet was captured
+    pub timestamp: SystemTime,
+    /// Index of the controller, i.e. `N` in `hciN`, if the file records it
+    pub adapter: Option<u16>,
+    /// The packet in H4 framing, starting with the packet type
+    pub data: Vec<u8>,
+}
+
+/// Layout of the file being read.
+#[derive(Debug, Clone, Copy)]
+enum Layout {
+    Btsnoop {
+        datalink: u32,
+    },
+    Pcap {
+        little_endian: bool,
+        nanos: bool,
+        phdr: bool,
+    },
+}
+
+/// Reads packets from a btsnoop or pcap file, detecting the format from the
+/// file header.
+#[derive(Debug)]
+pub struct DumpReader<R> {
+    inner: R,
+    layout: Layout,
+}
+
+impl<R: Read> DumpReader<R> {
+    /// Read the file header from `inner`.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        inner.read_exact(&mut magic).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("not a btsnoop or pcap file".to_string()),
+            _ => e,
+        })?;
+        if &magic == BTSNOOP_MAGIC {
+            let mut header = [0u8; 8];
+            inner.read_exact(&mut header)?;
+            let datalink = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+            if ![BTSNOOP_HCI_H1, BTSNOOP_HCI_H4, BTSNOOP_MONITOR].contains(&datalink) {
+                return Err(invalid(format!("unsupported btsnoop datalink {datalink}")));
+            }
+            return Ok(Self {
+                inner,
+                layout: Layout::Btsnoop { datalink },
+            });
+        }
+
+        let magic_bytes = [magic[0], magic[1], magic[2], magic[3]];
+        let (little_endian, nanos) = match (
+            u32::from_le_bytes(magic_bytes),
+            u32::from_be_bytes(magic_bytes),
+        ) {
+            (PCAP_MAGIC, _) => (true, false),
+            (PCAP_MAGIC_NANOS, _) => (true, true),
+            (_, PCAP_MAGIC) => (false, false),
+            (_, PCAP_MAGIC_NANOS) => (false, true),
+            _ => return Err(invalid("not a btsnoop or pcap file".to_string())),
+        };
+        let mut header = [0u8; 16];
+        inner.read_exact(&mut header)?;
+        let linktype = read_u32(&header[12..16], little_endian);
+        let phdr = match linktype {
+            LINKTYPE_BLUETOOTH_HCI_H4 => false,
+            LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR => true,
+            _ => return Err(invalid(format!("unsupported pcap linktype {linktype}"))),
+        };
+        Ok(Self {
+            inner,
+            layout: Layout::Pcap {
+                little_endian,
+                nanos,
+                phdr,
+            },
+        })
+    }
+
+    /// Read the next packet, or `None` at the end of the file.
+    ///
+    /// Records that hold no HCI packet, such as the index and logging records
+    /// of monitor captures, are skipped.
+    pub fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            let packet = match self.layout {
+                Layout::Btsnoop { datalink } => self.next_btsnoop(datalink)?,
+                Layout::Pcap {
+                    little_endian,
+                    nanos,
+                    phdr,
+                } => self.next_pcap(little_endian, nanos, phdr)?,
+            };
+            match packet {
+                Some(Some(packet)) => return Ok(Some(packet)),
+                Some(None) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn next_btsnoop(&mut self, datalink: u32) -> io::Result<Option<Option<Packet>>> {
+        let mut header = [0u8; 24];
+        if !self.read_header(&mut header)? {
+            return Ok(None);
+        }
+        let included = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let flags = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let mut micros = [0u8; 8];
+        micros.copy_from_slice(&header[16..24]);
+        let micros = u64::from_be_bytes(micros).saturating_sub(BTSNOOP_EPOCH_DELTA_US);
+        let timestamp = UNIX_EPOCH + Duration::from_micros(micros);
+        let body = self.read_body(included)?;
+
+        let (packet_type, adapter) = match datalink {
+            BTSNOOP_HCI_H4 => (None, None),
+            BTSNOOP_HCI_H1 => {
+                let packet_type = match flags & 0b11 {
+                    0b11 => H4_EVENT,
+                    0b10 => H4_COMMAND,
+                    _ => H4_ACL,
+                };
+                (Some(packet_type), None)
+            }
+            _ => {
+                // Monitor records carry the controller index and an opcode
+                let packet_type = match flags & 0xFFFF {
+                    2 => H4_COMMAND,
+                    3 => H4_EVENT,
+                    4 | 5 => H4_ACL,
+                    6 | 7 => H4_SCO,
+                    18 | 19 => H4_ISO,
+                    _ => return Ok(Some(None)),
+                };
+                (Some(packet_type), Some((flags >> 16) as u16))
+            }
+        };
+        let data = match packet_type {
+            Some(packet_type) => [&[packet_type][..], &body].concat(),
+            None => body,
+        };
+        Ok(Some(Some(Packet {
+            timestamp,
+            adapter,
+            data,
+        })))
+    }
+
+    fn next_pcap(
+        &mut self,
+        little_endian: bool,
+        nanos: bool,
+        phdr: bool,
+    ) -> io::Result<Option<Option<Packet>>> {
+        let mut header = [0u8; 16];
+        if !self.read_header(&mut header)? {
+            return Ok(None);
+        }
+        let seconds = read_u32(&header[0..4], little_endian);
+        let fraction = read_u32(&header[4..8], little_endian);
+        let included = read_u32(&header[8..12], little_endian);
+        let fraction = if nanos {
+            Duration::from_nanos(fraction.into())
+        } else {
+            Duration::from_micros(fraction.into())
+        };
+        let timestamp = UNIX_EPOCH + Duration::from_secs(seconds.into()) + fraction;
+        let mut data = self.read_body(included)?;
+        if phdr {
+            if data.len() < 4 {
+                return Ok(Some(None));
+            }
+            data.drain(..4);
+        }
+        Ok(Some(Some(Packet {
+            timestamp,
+            adapter: None,
+            data,
+        })))
+    }
+
+    /// Fill `header`, returning `false` at the end of the file.
+    fn read_header(&mut self, header: &mut [u8]) -> io::Result<bool> {
+        match self.inner.read_exact(header) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_body(&mut self, len: u32) -> io::Result<Vec<u8>> {
+        let len = len as usize;
+        if len > MAX_PACKET_LEN {
+            return Err(invalid(format!("packet of {len} bytes is too long")));
+        }
+        let mut body = vec![0u8; len];
+        self.inner.read_exact(&mut body)?;
+        Ok(body)
+    }
+}
+
+fn read_u32(bytes: &[u8], little_endian: bool) -> u32 {
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::SharedBuffer;
+
+    /// LE Meta event with a (truncated) advertising report, as read from an
+    /// HCI socket.
+    const EVENT: [u8; 6] = [0x04, 0x3E, 0x03, 0x02, 0x01, 0x00];
+
+    fn round_trip(format: DumpFormat) {
+        let buffer = SharedBuffer::default();
+        let writer = DumpWriter::new(buffer.clone(), format).unwrap();
+        let timestamp = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        writer.write_event(&EVENT, timestamp);
+        writer.write_event(&EVENT[..4], timestamp + Duration::from_secs(1));
+
+        let bytes = buffer.contents();
+        let mut reader = DumpReader::new(&bytes[..]).unwrap();
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.data, EVENT);
+        assert_eq!(packet.timestamp, timestamp);
+        assert_eq!(packet.adapter, None);
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.data, EVENT[..4]);
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_btsnoop_round_trip() {
+        round_trip(DumpFormat::Btsnoop);
+    }
+
+    #[test]
+    fn test_pcap_round_trip() {
+        round_trip(DumpFormat::Pcap);
+    }
+
+    #[test]
+    fn test_btsnoop_header() {
+        assert_eq!(
+            file_header(DumpFormat::Btsnoop),
+            b"btsnoop\0\x00\x00\x00\x01\x00\x00\x03\xea"
+        );
+    }
+
+    #[test]
+    fn test_pcap_header_and_record() {
+        let header = file_header(DumpFormat::Pcap);
+        assert_eq!(&header[..4], &[0xD4, 0xC3, 0xB2, 0xA1]);
+        assert_eq!(&header[20..], &[201, 0, 0, 0]);
+
+        let record = packet_record(DumpFormat::Pcap, &EVENT, UNIX_EPOCH);
+        // Lengths include the direction header, which is big-endian
+        assert_eq!(&record[8..16], &[10, 0, 0, 0, 10, 0, 0, 0]);
+        assert_eq!(&record[16..20], &[0, 0, 0, 1]);
+        assert_eq!(&record[20..], &EVENT);
+    }
+
+    /// A btsnoop file as written by `btmon -w`, with a new-index record and an
+    /// event from hci1.
+    fn monitor_capture() -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend_from_slice(BTSNOOP_MAGIC);
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(&BTSNOOP_MONITOR.to_be_bytes());
+        for (opcode, body) in [(0u32, &[0u8; 16][..]), (3, &EVENT[1..])] {
+            let len = body.len() as u32;
+            file.extend_from_slice(&len.to_be_bytes());
+            file.extend_from_slice(&len.to_be_bytes());
+            file.extend_from_slice(&((1 << 16) | opcode).to_be_bytes());
+            file.extend_from_slice(&0u32.to_be_bytes());
+            file.extend_from_slice(&(BTSNOOP_EPOCH_DELTA_US + 2_000_000).to_be_bytes());
+            file.extend_from_slice(body);
+        }
+        file
+    }
+
+    #[test]
+    fn test_read_btmon_capture() {
+        let file = monitor_capture();
+        let mut reader = DumpReader::new(&file[..]).unwrap();
+
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.adapter, Some(1));
+        assert_eq!(packet.data, EVENT);
+        assert_eq!(packet.timestamp, UNIX_EPOCH + Duration::from_secs(2));
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_big_endian_nanosecond_pcap() {
+        let mut file = Vec::new();
+        file.extend_from_slice(&PCAP_MAGIC_NANOS.to_be_bytes());
+        file.extend_from_slice(&[0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF]);
+        file.extend_from_slice(&LINKTYPE_BLUETOOTH_HCI_H4.to_be_bytes());
+        file.extend_from_slice(&3u32.to_be_bytes());
+        file.extend_from_slice(&500u32.to_be_bytes());
+        file.extend_from_slice(&(EVENT.len() as u32).to_be_bytes());
+        file.extend_from_slice(&(EVENT.len() as u32).to_be_bytes());
+        file.extend_from_slice(&EVENT);
+
+        let mut reader = DumpReader::new(&file[..]).unwrap();
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.data, EVENT);
+        assert_eq!(
+            packet.timestamp,
+            UNIX_EPOCH + Duration::from_secs(3) + Duration::from_nanos(500)
+        );
+    }
+
+    #[test]
+    fn test_reject_unknown_files() {
+        let err = DumpReader::new(&b"not a capture file"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut file = BTSNOOP_MAGIC.to_vec();
+        file.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0x03, 0xEB]); // BCSP
+        let err = DumpReader::new(&file[..]).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported btsnoop datalink 1003");
+    }
+
+    #[test]
+    fn test_truncated_record_is_an_error() {
+        let mut bytes = file_header(DumpFormat::Btsnoop);
+        bytes.extend_from_slice(&packet_record(DumpFormat::Btsnoop, &EVENT, UNIX_EPOCH)[..28]);
+        let mut reader = DumpReader::new(&bytes[..]).unwrap();
+        assert!(reader.next_packet().is_err());
+    }
+}
@@ -6,9 +6,14 @@
 
 pub mod alias;
 pub mod app;
+pub mod capture;
+pub mod encoder;
+pub mod event;
+pub mod hci_dump;
 pub mod mac_address;
 pub mod measurement;
 pub mod output;
+pub mod pressure;
 pub mod scanner;
 pub mod throttle;
 
@@ -17,9 +22,13 @@ pub(crate) mod test_utils;
 
 // Re-export commonly used types at the crate root
 pub use alias::{Alias, AliasMap, parse_alias, resolve_name, to_map};
+pub use encoder::encode_ruuvi_data;
 pub use mac_address::MacAddress;
 pub use measurement::Measurement;
 pub use output::OutputFormatter;
 pub use output::influxdb::InfluxDbFormatter;
-pub use scanner::{Backend, DecodeError, MeasurementResult, ScanError, decode_ruuvi_data};
+pub use pressure::{PressureProcessor, TagAltitude, parse_tag_altitude};
+pub use scanner::{
+    AdapterId, Backend, DecodeError, MeasurementResult, ScanConfig, ScanError, decode_ruuvi_data,
+};
 pub use throttle::{Throttle, parse_duration};
@@ -1,7 +1,9 @@
 use clap::Parser;
+use std::io;
 use std::panic::{self, PanicHookInfo};
+use tokio::signal::unix::{SignalKind, signal};
 
-use ruuvitag_listener::app::{Options, RealScanner, RunError, run_with_io};
+use ruuvitag_listener::app::{Options, RealScanner, RunError, run_until};
 
 /// Exit codes for the application
 const EXIT_SUCCESS: i32 = 0;
@@ -14,8 +16,9 @@ const EXIT_PANIC: i32 = 2;
 /// 1. Converts CLI aliases into a lookup map
 /// 2. Creates an InfluxDB formatter with the specified measurement name
 /// 3. Optionally creates a throttle to limit event frequency per tag
-/// 4. Starts the BLE scanner
-/// 5. Processes measurements and outputs them to stdout until interrupted
+/// 4. Starts the BLE scanner, or replays a capture with the replay backend
+/// 5. Processes measurements and outputs them to stdout until SIGINT or SIGTERM,
+///    then stops scanning and flushes the output
 ///
 /// # Arguments
 /// * `options` - Command-line options parsed from user input
@@ -23,10 +26,25 @@ const EXIT_PANIC: i32 = 2;
 /// # Errors
 /// Returns `ScanError` if Bluetooth initialization fails
 async fn run(run_options: Options) -> Result<(), RunError> {
-    let scanner = RealScanner;
     let mut out = std::io::stdout();
     let mut err = std::io::stderr();
-    run_with_io(run_options, &scanner, &mut out, &mut err).await
+    let shutdown = shutdown_signal()?;
+    run_until(run_options, &RealScanner, &mut out, &mut err, shutdown).await
+}
+
+/// Completes on the first SIGINT or SIGTERM.
+///
+/// The handlers are installed before returning, so signals arriving while the
+/// scan is being set up are not lost.
+fn shutdown_signal() -> io::Result<impl Future<Output = ()>> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = interrupt.recv() => {}
+            _ = terminate.recv() => {}
+        }
+    })
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -39,6 +57,9 @@ async fn main() {
     }));
 
     let options = Options::parse();
+    if let Err(e) = options.validate() {
+        e.exit();
+    }
 
     match run(options).await {
         Ok(_) => std::process::exit(EXIT_SUCCESS),
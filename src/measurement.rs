@@ -1,6 +1,7 @@
 //! RuuviTag measurement data structure.
 
 use crate::mac_address::MacAddress;
+use std::sync::Arc;
 
 /// The RuuviTag advertisement data format a measurement was decoded from.
 ///
@@ -10,6 +11,12 @@ use crate::mac_address::MacAddress;
 /// be dropped.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Format {
+    /// Data format 2, broadcast by older firmware as an Eddystone URL.
+    V2,
+    /// Data format 3 (RAWv1), broadcast by older firmware.
+    V3,
+    /// Data format 4, as format 2 followed by a tag identifier.
+    V4,
     /// Data format 5 (RAWv2).
     V5,
     /// Data format 6 (compact, Bluetooth 4 compatibility).
@@ -18,6 +25,62 @@ pub enum Format {
     E1,
 }
 
+impl std::fmt::Display for Format {
+    /// Format as the data format identifier used in Ruuvi documentation.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::V2 => write!(f, "2"),
+            Format::V3 => write!(f, "3"),
+            Format::V4 => write!(f, "4"),
+            Format::V5 => write!(f, "5"),
+            Format::V6 => write!(f, "6"),
+            Format::E1 => write!(f, "E1"),
+        }
+    }
+}
+
+/// The LE PHY an advertisement was received on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phy {
+    /// LE 1M, used by all legacy advertising.
+    Le1M,
+    /// LE 2M, only used for secondary advertising channels.
+    Le2M,
+    /// LE Coded (long range).
+    LeCoded,
+}
+
+impl std::fmt::Display for Phy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phy::Le1M => write!(f, "1M"),
+            Phy::Le2M => write!(f, "2M"),
+            Phy::LeCoded => write!(f, "coded"),
+        }
+    }
+}
+
+/// Direction of the pressure change over the tendency window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureTendency {
+    /// Pressure rose by at least 0.1 hPa.
+    Rising,
+    /// Pressure fell by at least 0.1 hPa.
+    Falling,
+    /// Pressure changed by less than 0.1 hPa.
+    Steady,
+}
+
+impl std::fmt::Display for PressureTendency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PressureTendency::Rising => write!(f, "rising"),
+            PressureTendency::Falling => write!(f, "falling"),
+            PressureTendency::Steady => write!(f, "steady"),
+        }
+    }
+}
+
 /// A measurement from a RuuviTag sensor.
 ///
 /// All values are in standard SI units:
@@ -37,6 +100,11 @@ pub struct Measurement {
     pub mac: MacAddress,
     /// The advertisement data format this measurement was decoded from
     pub format: Format,
+    /// Name of the adapter that received the advertisement (e.g. `hci1`),
+    /// shared between all measurements from the same adapter
+    pub adapter: Option<Arc<str>>,
+    /// Primary PHY the advertisement was received on, if the backend knows it
+    pub phy: Option<Phy>,
     /// Timestamp when the measurement was taken
     pub timestamp: std::time::SystemTime,
     /// Temperature in Celsius
@@ -45,6 +113,12 @@ pub struct Measurement {
     pub humidity: Option<f64>,
     /// Atmospheric pressure in Pascals
     pub pressure: Option<f64>,
+    /// Pressure reduced to sea level in Pascals (derived)
+    pub pressure_sea_level: Option<f64>,
+    /// Pressure change over the last three hours in Pascals (derived)
+    pub pressure_trend_3h: Option<f64>,
+    /// Direction of the three-hour pressure change (derived)
+    pub pressure_tendency: Option<PressureTendency>,
     /// Battery voltage in Volts
     pub battery: Option<f64>,
     /// TX power in dBm
@@ -71,4 +145,7 @@ pub struct Measurement {
     pub nox_index: Option<f64>,
     /// Ambient luminosity in lux
     pub luminosity: Option<f64>,
+    /// Random identifier of the tag, broadcast in data format 4. Only its upper
+    /// six bits fit in the URL, so the lower two are always zero
+    pub tag_id: Option<u8>,
 }
@@ -0,0 +1,163 @@
+//! Field selection and renaming for output formatters.
+//!
+//! A [`FieldMapping`] decides which fields are written and under what name.
+//! Mapping applies to field names as the formatter would emit them, i.e. after
+//! any unit suffix has been appended (`temperature_f`, not `temperature`).
+
+use std::collections::{HashMap, HashSet};
+
+/// Measurement metadata that can be written either as tags or as fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, clap::ValueEnum)]
+pub enum MetaField {
+    /// MAC address of the tag
+    Mac,
+    /// Resolved device name (alias or MAC address)
+    Name,
+    /// RuuviTag data format the measurement was decoded from
+    Format,
+    /// Bluetooth adapter that received the advertisement
+    Adapter,
+    /// LE PHY the advertisement was received on (`1M` or `coded`)
+    Phy,
+}
+
+impl MetaField {
+    /// Key used for this metadata in the output.
+    pub fn key(self) -> &'static str {
+        match self {
+            MetaField::Mac => "mac",
+            MetaField::Name => "name",
+            MetaField::Format => "format",
+            MetaField::Adapter => "adapter",
+            MetaField::Phy => "phy",
+        }
+    }
+}
+
+/// A field rename from the emitted field name to a new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldRename {
+    pub from: String,
+    pub to: String,
+}
+
+/// Parse a field rename from a string in the format "FIELD=NEW_NAME".
+///
+/// # Example
+/// ```
+/// use ruuvitag_listener::output::fields::parse_field_rename;
+///
+/// let rename = parse_field_rename("battery_potential=battery").unwrap();
+/// assert_eq!(rename.from, "battery_potential");
+/// assert_eq!(rename.to, "battery");
+/// ```
+pub fn parse_field_rename(src: &str) -> Result<FieldRename, String> {
+    let (from, to) = src
+        .split_once('=')
+        .ok_or_else(|| "invalid field rename: expected format FIELD=NEW_NAME".to_string())?;
+    let (from, to) = (from.trim(), to.trim());
+    if from.is_empty() || to.is_empty() {
+        return Err("invalid field rename: field names must not be empty".to_string());
+    }
+    Ok(FieldRename {
+        from: from.to_string(),
+        to: to.to_string(),
+    })
+}
+
+/// Rules for selecting and renaming output fields.
+///
+/// A field is written if it is in the include list (when one is given) and not
+/// in the drop list. Both lists refer to the emitted names, before renaming.
+#[derive(Debug, Clone, Default)]
+pub struct FieldMapping {
+    renames: HashMap<String, String>,
+    dropped: HashSet<String>,
+    included: Option<HashSet<String>>,
+}
+
+impl FieldMapping {
+    /// Create a field mapping.
+    ///
+    /// An empty `included` list means all fields are included.
+    pub fn new(renames: &[FieldRename], dropped: &[String], included: &[String]) -> Self {
+        Self {
+            renames: renames
+                .iter()
+                .map(|r| (r.from.clone(), r.to.clone()))
+                .collect(),
+            dropped: dropped.iter().cloned().collect(),
+            included: (!included.is_empty()).then(|| included.iter().cloned().collect()),
+        }
+    }
+
+    /// Whether the mapping writes every field under its own name.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.renames.is_empty() && self.dropped.is_empty() && self.included.is_none()
+    }
+
+    /// Map an emitted field name to its output name.
+    ///
+    /// Returns `None` if the field should not be written.
+    pub fn map<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        if self.dropped.contains(name) {
+            return None;
+        }
+        if let Some(included) = &self.included
+            && !included.contains(name)
+        {
+            return None;
+        }
+        Some(self.renames.get(name).map_or(name, String::as_str))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_empty_mapping_keeps_all_fields() {
+        let mapping = FieldMapping::default();
+        assert!(mapping.is_empty());
+        assert_eq!(mapping.map("temperature"), Some("temperature"));
+    }
+
+    #[test]
+    fn test_rename_and_drop() {
+        let renames = [parse_field_rename("battery_potential=battery").unwrap()];
+        let mapping = FieldMapping::new(&renames, &names(&["tx_power"]), &[]);
+
+        assert!(!mapping.is_empty());
+        assert_eq!(mapping.map("battery_potential"), Some("battery"));
+        assert_eq!(mapping.map("tx_power"), None);
+        assert_eq!(mapping.map("humidity"), Some("humidity"));
+    }
+
+    #[test]
+    fn test_include_only() {
+        let renames = [parse_field_rename("temperature_c=temp").unwrap()];
+        let mapping = FieldMapping::new(
+            &renames,
+            &names(&["humidity"]),
+            &names(&["temperature_c", "humidity"]),
+        );
+
+        assert_eq!(mapping.map("temperature_c"), Some("temp"));
+        // Drop wins over include
+        assert_eq!(mapping.map("humidity"), None);
+        assert_eq!(mapping.map("pressure"), None);
+    }
+
+    #[test]
+    fn test_parse_field_rename_invalid() {
+        assert!(parse_field_rename("battery").is_err());
+        assert!(parse_field_rename("=battery").is_err());
+        assert!(parse_field_rename("battery_potential= ").is_err());
+    }
+}
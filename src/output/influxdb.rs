@@ -1,13 +1,50 @@
 //! InfluxDB line protocol output formatter.
 
 use crate::measurement::Measurement;
-use crate::output::OutputFormatter;
+use crate::output::fields::{FieldMapping, MetaField};
+use crate::output::units::Units;
+use crate::output::{OutputFormatter, TimestampPrecision};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::Write;
 use std::time::SystemTime;
 
 #[cfg(test)]
 use std::time::Duration;
 
+/// How integer-valued fields are written in line protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum IntegerMode {
+    /// Write integers as floats, compatible with existing buckets
+    #[default]
+    Float,
+    /// Write integers as signed integers (`i` suffix)
+    Integer,
+    /// Write counters as unsigned integers (`u` suffix) and signed values as
+    /// signed integers. Requires InfluxDB 1.8 or later.
+    Unsigned,
+}
+
+/// A typed line protocol field value.
+///
+/// Displays in line protocol syntax, e.g. `42`, `42i` or `42u`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    Unsigned(u64),
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Float(v) => write!(f, "{}", v),
+            FieldValue::Integer(v) => write!(f, "{}i", v),
+            FieldValue::Unsigned(v) => write!(f, "{}u", v),
+        }
+    }
+}
+
 /// InfluxDB line protocol formatter.
 ///
 /// Formats measurements according to the InfluxDB line protocol specification.
@@ -17,17 +54,29 @@ pub struct InfluxDbFormatter {
     measurement_name: String,
     /// Whether the measurement name needs escaping (precomputed at initialization)
     needs_measurement_escape: bool,
+    /// Units for the temperature, pressure and humidity fields
+    units: Units,
+    /// Whether to append the unit to field names (e.g. `temperature_c`)
+    unit_suffix: bool,
+    /// Metadata written as tags, in output order
+    tags: Vec<MetaField>,
+    /// Metadata written as string fields, in output order
+    meta_fields: Vec<MetaField>,
+    /// Field selection and renaming
+    fields: FieldMapping,
+    /// How integer-valued fields are typed
+    integers: IntegerMode,
+    /// Precision of the timestamp, or whether it is omitted
+    timestamp_precision: TimestampPrecision,
 }
 
 impl InfluxDbFormatter {
-    /// Convert pressure from Pascals to kilopascals.
-    #[inline]
-    fn pressure_kpa(pascals: f64) -> f64 {
-        pascals / 1000.0
-    }
-
     /// Create a new InfluxDB formatter.
     ///
+    /// Uses the default units (Celsius, kilopascals, percent) without unit
+    /// suffixes in field names, writes `mac` and `name` as tags, and writes
+    /// every field under its own name.
+    ///
     /// # Arguments
     /// * `measurement_name` - The measurement name to use in the line protocol
     pub fn new(measurement_name: String) -> Self {
@@ -35,9 +84,155 @@ impl InfluxDbFormatter {
         Self {
             measurement_name,
             needs_measurement_escape: needs_escape,
+            units: Units::default(),
+            unit_suffix: false,
+            tags: vec![MetaField::Mac, MetaField::Name],
+            meta_fields: Vec::new(),
+            fields: FieldMapping::default(),
+            integers: IntegerMode::default(),
+            timestamp_precision: TimestampPrecision::default(),
+        }
+    }
+
+    /// Set the units used for temperature, pressure and humidity fields.
+    pub fn with_units(mut self, units: Units) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Append the unit to the names of unit-bearing fields.
+    ///
+    /// With suffixes enabled, `temperature` is written as e.g. `temperature_c`
+    /// or `temperature_f`, so databases holding data in mixed units stay
+    /// unambiguous.
+    pub fn with_unit_suffix(mut self, enabled: bool) -> Self {
+        self.unit_suffix = enabled;
+        self
+    }
+
+    /// Choose which metadata is written as tags and which as string fields.
+    ///
+    /// Metadata listed as a tag is not written as a field as well.
+    pub fn with_meta(mut self, tags: &[MetaField], fields: &[MetaField]) -> Self {
+        let mut tags = tags.to_vec();
+        tags.sort_unstable();
+        tags.dedup();
+        let mut meta_fields: Vec<MetaField> = fields
+            .iter()
+            .copied()
+            .filter(|f| !tags.contains(f))
+            .collect();
+        meta_fields.sort_unstable();
+        meta_fields.dedup();
+        self.tags = tags;
+        self.meta_fields = meta_fields;
+        self
+    }
+
+    /// Set how integer-valued fields (`tx_power`, `movement_counter`,
+    /// `measurement_sequence_number` and `tag_id`) are typed.
+    ///
+    /// Defaults to [`IntegerMode::Float`]; switching an existing bucket to
+    /// integers causes field type conflicts in InfluxDB.
+    pub fn with_integers(mut self, integers: IntegerMode) -> Self {
+        self.integers = integers;
+        self
+    }
+
+    /// Set the timestamp precision, or omit timestamps with
+    /// [`TimestampPrecision::None`]. Defaults to nanoseconds.
+    pub fn with_timestamp_precision(mut self, precision: TimestampPrecision) -> Self {
+        self.timestamp_precision = precision;
+        self
+    }
+
+    /// Typed value for a signed integer field.
+    #[inline]
+    fn signed(&self, v: i64) -> FieldValue {
+        match self.integers {
+            IntegerMode::Float => FieldValue::Float(v as f64),
+            IntegerMode::Integer | IntegerMode::Unsigned => FieldValue::Integer(v),
+        }
+    }
+
+    /// Typed value for an unsigned integer field.
+    #[inline]
+    fn unsigned(&self, v: u32) -> FieldValue {
+        match self.integers {
+            IntegerMode::Float => FieldValue::Float(f64::from(v)),
+            IntegerMode::Integer => FieldValue::Integer(i64::from(v)),
+            IntegerMode::Unsigned => FieldValue::Unsigned(u64::from(v)),
         }
     }
 
+    /// Set the rules for selecting and renaming fields.
+    pub fn with_field_mapping(mut self, fields: FieldMapping) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Names of the fields this formatter may write, as emitted before the
+    /// field mapping is applied.
+    pub fn field_names(&self) -> Vec<String> {
+        let units = self.units;
+        let with_suffix = |name: &str, suffix: &str| match self.unit_suffix {
+            true => format!("{name}_{suffix}"),
+            false => name.to_string(),
+        };
+        let mut names: Vec<String> = self
+            .meta_fields
+            .iter()
+            .map(|meta| meta.key().to_string())
+            .collect();
+        names.extend([
+            with_suffix("temperature", units.temperature.suffix()),
+            with_suffix(units.humidity.field(), units.humidity.suffix()),
+            with_suffix("pressure", units.pressure.suffix()),
+            with_suffix("pressure_sea_level", units.pressure_sea_level.suffix()),
+            with_suffix("pressure_trend_3h", units.pressure_trend_3h.suffix()),
+        ]);
+        names.extend(
+            [
+                "pressure_tendency",
+                "battery_potential",
+                "tx_power",
+                "movement_counter",
+                "measurement_sequence_number",
+                "tag_id",
+                "pm1_0",
+                "pm2_5",
+                "pm4_0",
+                "pm10_0",
+                "co2",
+                "voc_index",
+                "nox_index",
+                "luminosity",
+                "acceleration_x",
+                "acceleration_y",
+                "acceleration_z",
+            ]
+            .map(String::from),
+        );
+        names
+    }
+
+    /// Check that the field mapping writes no two fields under the same name,
+    /// e.g. because a field was renamed to the name of another one.
+    pub fn check_field_mapping(&self) -> Result<(), String> {
+        let names = self.field_names();
+        let mut written = HashMap::new();
+        for name in &names {
+            if let Some(key) = self.fields.map(name)
+                && let Some(other) = written.insert(key, name)
+            {
+                return Err(format!(
+                    "invalid field rename: {other} and {name} would both be written as {key}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
     /// Check if a measurement name needs escaping (fast path).
     ///
     /// Returns true if the string contains commas or spaces.
@@ -84,6 +279,7 @@ impl InfluxDbFormatter {
     ///
     /// Escapes commas, equals signs, and spaces with backslashes.
     /// Tag values must escape: `,` → `\,`, `=` → `\=`, ` ` → `\ `
+    /// Field keys follow the same rules, so this is used for renamed fields too.
     #[inline]
     fn write_tag_value(buf: &mut String, s: &str) {
         if Self::needs_tag_escape(s) {
@@ -102,9 +298,25 @@ impl InfluxDbFormatter {
         }
     }
 
+    /// Write string field value to buffer, quoted and escaped.
+    ///
+    /// String field values must escape: `"` → `\"`, `\` → `\\`
+    #[inline]
+    fn write_string_value(buf: &mut String, s: &str) {
+        buf.push('"');
+        for ch in s.chars() {
+            match ch {
+                '"' => buf.push_str("\\\""),
+                '\\' => buf.push_str("\\\\"),
+                _ => buf.push(ch),
+            }
+        }
+        buf.push('"');
+    }
+
     /// Write tags directly to the buffer (no intermediate BTreeMap).
     ///
-    /// Tags are written in a fixed order: mac, name.
+    /// Tags are written in a fixed order: mac, name, format, adapter.
     /// InfluxDB accepts tags in any order, so we don't need to sort.
     ///
     /// Tag values are escaped according to InfluxDB line protocol rules.
@@ -112,46 +324,181 @@ impl InfluxDbFormatter {
     /// Note: `write!` to a `String` is infallible (only fails on OOM which panics anyway),
     /// so we use `let _ = ...` to explicitly ignore the Result.
     #[inline]
-    fn write_tags(buf: &mut String, m: &Measurement, name: &str) {
-        // Write mac tag (MAC addresses are safe - format is AA:BB:CC:DD:EE:FF)
-        let _ = write!(buf, ",mac={}", m.mac);
+    fn write_tags(&self, buf: &mut String, m: &Measurement, name: &str) {
+        for tag in &self.tags {
+            match tag {
+                // MAC addresses are safe - format is AA:BB:CC:DD:EE:FF
+                MetaField::Mac => {
+                    let _ = write!(buf, ",mac={}", m.mac);
+                }
+                // Name is resolved by caller - escape special characters if needed
+                MetaField::Name => {
+                    buf.push_str(",name=");
+                    Self::write_tag_value(buf, name);
+                }
+                MetaField::Format => {
+                    let _ = write!(buf, ",format={}", m.format);
+                }
+                // Tags cannot be empty, so the tag is left out for measurements
+                // without an adapter
+                MetaField::Adapter => {
+                    if let Some(adapter) = &m.adapter {
+                        buf.push_str(",adapter=");
+                        Self::write_tag_value(buf, adapter);
+                    }
+                }
+                MetaField::Phy => {
+                    if let Some(phy) = m.phy {
+                        let _ = write!(buf, ",phy={}", phy);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write the separator and key of a field, applying the field mapping.
+    ///
+    /// Returns false if the field is not selected, in which case the caller
+    /// must not write its value.
+    #[inline]
+    fn write_key(
+        &self,
+        buf: &mut String,
+        first: &mut bool,
+        name: &str,
+        suffix: Option<&str>,
+    ) -> bool {
+        let push_separator = |buf: &mut String, first: &mut bool| {
+            if *first {
+                *first = false;
+            } else {
+                buf.push(',');
+            }
+        };
 
-        // Write name tag (resolved by caller) - escape special characters if needed
-        buf.push_str(",name=");
-        Self::write_tag_value(buf, name);
+        // Fast path: field names are written as is
+        if self.fields.is_empty() {
+            push_separator(buf, first);
+            buf.push_str(name);
+            if let Some(suffix) = suffix {
+                buf.push('_');
+                buf.push_str(suffix);
+            }
+            return true;
+        }
+
+        let emitted = match suffix {
+            Some(suffix) => Cow::Owned(format!("{name}_{suffix}")),
+            None => Cow::Borrowed(name),
+        };
+        match self.fields.map(&emitted) {
+            Some(key) => {
+                push_separator(buf, first);
+                Self::write_tag_value(buf, key);
+                true
+            }
+            None => false,
+        }
     }
 
     /// Write fields directly to the buffer (no intermediate BTreeMap).
     ///
-    /// Only writes fields that have values. Uses a macro to avoid code duplication.
+    /// Only writes fields that have values and are selected by the field mapping.
+    /// Uses a macro to avoid code duplication. Unit-bearing fields are converted
+    /// to the configured units and, if enabled, get the unit appended to their name.
+    ///
+    /// Returns false if no field was written.
     #[inline]
-    fn write_fields(buf: &mut String, m: &Measurement) {
+    fn write_fields(&self, buf: &mut String, m: &Measurement, name: &str) -> bool {
         let mut first = true;
 
-        // Macro to write a field if present, handling the comma separator.
+        // Macro to write a field if present, handling the comma separator, the
+        // optional unit suffix and the field mapping.
         macro_rules! write_field {
             ($name:literal, $val:expr) => {
-                if let Some(v) = $val {
-                    if first {
-                        first = false;
-                    } else {
-                        buf.push(',');
-                    }
-                    let _ = write!(buf, "{}={}", $name, v);
+                write_field!($name, None, $val)
+            };
+            ($name:expr, $suffix:expr, $val:expr) => {
+                if let Some(v) = $val
+                    && self.write_key(buf, &mut first, $name, $suffix)
+                {
+                    let _ = write!(buf, "={}", v);
                 }
             };
         }
 
-        write_field!("temperature", m.temperature);
-        write_field!("humidity", m.humidity);
-        write_field!("pressure", m.pressure.map(Self::pressure_kpa));
+        // String fields, which line protocol requires to be quoted
+        macro_rules! write_string_field {
+            ($name:expr, $val:expr) => {
+                if self.write_key(buf, &mut first, $name, None) {
+                    buf.push('=');
+                    Self::write_string_value(buf, $val);
+                }
+            };
+        }
+
+        for &meta in &self.meta_fields {
+            let value = match meta {
+                MetaField::Mac => Cow::Owned(m.mac.to_string()),
+                MetaField::Name => Cow::Borrowed(name),
+                MetaField::Format => Cow::Owned(m.format.to_string()),
+                MetaField::Adapter => match &m.adapter {
+                    Some(adapter) => Cow::Borrowed(&**adapter),
+                    None => continue,
+                },
+                MetaField::Phy => match m.phy {
+                    Some(phy) => Cow::Owned(phy.to_string()),
+                    None => continue,
+                },
+            };
+            write_string_field!(meta.key(), &value);
+        }
+
+        let units = self.units;
+        let suffix = |unit_suffix: &'static str| self.unit_suffix.then_some(unit_suffix);
+
+        write_field!(
+            "temperature",
+            suffix(units.temperature.suffix()),
+            m.temperature.map(|t| units.temperature.convert(t))
+        );
+        write_field!(
+            units.humidity.field(),
+            suffix(units.humidity.suffix()),
+            m.humidity
+                .and_then(|h| units.humidity.convert(h, m.temperature, m.pressure))
+        );
+        write_field!(
+            "pressure",
+            suffix(units.pressure.suffix()),
+            m.pressure.map(|p| units.pressure.convert(p))
+        );
+        write_field!(
+            "pressure_sea_level",
+            suffix(units.pressure_sea_level.suffix()),
+            m.pressure_sea_level
+                .map(|p| units.pressure_sea_level.convert(p))
+        );
+        write_field!(
+            "pressure_trend_3h",
+            suffix(units.pressure_trend_3h.suffix()),
+            m.pressure_trend_3h
+                .map(|p| units.pressure_trend_3h.convert(p))
+        );
+        if let Some(tendency) = m.pressure_tendency {
+            write_string_field!("pressure_tendency", &tendency.to_string());
+        }
         write_field!("battery_potential", m.battery);
-        write_field!("tx_power", m.tx_power.map(f64::from));
-        write_field!("movement_counter", m.movement_counter.map(f64::from));
+        write_field!("tx_power", m.tx_power.map(|v| self.signed(v.into())));
+        write_field!(
+            "movement_counter",
+            m.movement_counter.map(|v| self.unsigned(v))
+        );
         write_field!(
             "measurement_sequence_number",
-            m.measurement_sequence.map(f64::from)
+            m.measurement_sequence.map(|v| self.unsigned(v))
         );
+        write_field!("tag_id", m.tag_id.map(|v| self.unsigned(v.into())));
         write_field!("pm1_0", m.pm1_0);
         write_field!("pm2_5", m.pm2_5);
         write_field!("pm4_0", m.pm4_0);
@@ -160,34 +507,22 @@ impl InfluxDbFormatter {
         write_field!("voc_index", m.voc_index);
         write_field!("nox_index", m.nox_index);
         write_field!("luminosity", m.luminosity);
+        write_field!("acceleration_x", m.acceleration.map(|(x, _, _)| x));
+        write_field!("acceleration_y", m.acceleration.map(|(_, y, _)| y));
+        write_field!("acceleration_z", m.acceleration.map(|(_, _, z)| z));
 
-        // Handle acceleration tuple specially
-        if let Some((x, y, z)) = m.acceleration {
-            if first {
-                first = false;
-            } else {
-                buf.push(',');
-            }
-            let _ = write!(
-                buf,
-                "acceleration_x={},acceleration_y={},acceleration_z={}",
-                x, y, z
-            );
-        }
-        let _ = first; // suppress unused warning
+        !first
     }
 
-    /// Write timestamp as nanoseconds since Unix epoch.
+    /// Write timestamp in the configured precision, if not omitted.
     ///
     /// If the timestamp is before Unix epoch (which shouldn't happen for sensor data),
     /// writes 0 as a safe fallback rather than panicking.
     #[inline]
-    fn write_timestamp(buf: &mut String, timestamp: SystemTime) {
-        let nanos = timestamp
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map(|d| d.as_nanos())
-            .unwrap_or(0);
-        let _ = write!(buf, " {}", nanos);
+    fn write_timestamp(&self, buf: &mut String, timestamp: SystemTime) {
+        if let Some(value) = self.timestamp_precision.since_epoch(timestamp) {
+            let _ = write!(buf, " {}", value);
+        }
     }
 }
 
@@ -210,16 +545,19 @@ impl OutputFormatter for InfluxDbFormatter {
         );
 
         // Write tags directly
-        Self::write_tags(&mut buf, m, name);
+        self.write_tags(&mut buf, m, name);
 
         // Space separator between tags and fields
         buf.push(' ');
 
-        // Write fields directly
-        Self::write_fields(&mut buf, m);
+        // Write fields directly. A line without fields is invalid line protocol,
+        // so there is nothing to output if the field mapping dropped them all.
+        if !self.write_fields(&mut buf, m, name) && !self.fields.is_empty() {
+            return String::new();
+        }
 
         // Write timestamp
-        Self::write_timestamp(&mut buf, m.timestamp);
+        self.write_timestamp(&mut buf, m.timestamp);
 
         buf
     }
@@ -228,6 +566,9 @@ impl OutputFormatter for InfluxDbFormatter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::measurement::{Phy, PressureTendency};
+    use crate::output::fields::parse_field_rename;
+    use crate::output::units::{HumidityUnit, UnitOverride, UnitSystem};
     use crate::test_utils::{TEST_MAC, base_measurement};
 
     fn assert_contains_all(haystack: &str, needles: &[&str]) {
@@ -287,6 +628,320 @@ mod tests {
         assert!(result.ends_with("1000000000000000000"));
     }
 
+    #[test]
+    fn test_influxdb_formatter_pressure_derived_fields() {
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string());
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.pressure = Some(95000.0);
+        measurement.pressure_sea_level = Some(101325.0);
+        measurement.pressure_trend_3h = Some(-150.0);
+        measurement.pressure_tendency = Some(PressureTendency::Falling);
+
+        let result = formatter.format(&measurement, "Device");
+
+        assert_contains_all(
+            &result,
+            &[
+                "pressure=95,",
+                "pressure_sea_level=101.325",
+                "pressure_trend_3h=-1.5",
+                "pressure_tendency=\"falling\"",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_influxdb_formatter_units() {
+        let units = Units::new(
+            UnitSystem::Imperial,
+            &[UnitOverride::Humidity(HumidityUnit::Ratio)],
+        );
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string()).with_units(units);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(100.0);
+        measurement.humidity = Some(0.0);
+        measurement.pressure = Some(3386.389);
+
+        let result = formatter.format(&measurement, "Device");
+
+        assert_contains_all(
+            &result,
+            &["temperature=212", "humidity_ratio=0", "pressure=1"],
+        );
+        // The ratio never shares the field of relative humidity
+        assert!(!result.contains("humidity="));
+    }
+
+    #[test]
+    fn test_influxdb_formatter_unit_suffix() {
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_units(UnitSystem::MetricHpa.into())
+            .with_unit_suffix(true);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(21.5);
+        measurement.humidity = Some(40.0);
+        measurement.pressure = Some(101325.0);
+        measurement.pressure_sea_level = Some(101325.0);
+        measurement.pressure_trend_3h = Some(-150.0);
+        measurement.battery = Some(3.0);
+
+        let result = formatter.format(&measurement, "Device");
+
+        assert_contains_all(
+            &result,
+            &[
+                "temperature_c=21.5",
+                "humidity_pct=40",
+                "pressure_hpa=1013.25",
+                "pressure_sea_level_hpa=1013.25",
+                "pressure_trend_3h_hpa=-1.5",
+                // Fields without a selectable unit keep their name
+                "battery_potential=3",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_influxdb_formatter_meta_as_fields() {
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_meta(&[MetaField::Format], &[MetaField::Mac, MetaField::Name]);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(21.5);
+
+        let result = formatter.format(&measurement, "Living \"room\"");
+
+        assert_eq!(
+            result,
+            "ruuvi,format=5 mac=\"AA:BB:CC:DD:EE:FF\",name=\"Living \\\"room\\\"\",temperature=21.5 1000000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_formatter_adapter_tag() {
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_meta(&[MetaField::Mac, MetaField::Adapter], &[]);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(21.5);
+
+        // No adapter known: the tag is left out
+        assert!(
+            formatter
+                .format(&measurement, "Device")
+                .starts_with("ruuvi,mac=AA:BB:CC:DD:EE:FF temperature=21.5")
+        );
+
+        measurement.adapter = Some("hci1".into());
+        assert!(
+            formatter
+                .format(&measurement, "Device")
+                .starts_with("ruuvi,mac=AA:BB:CC:DD:EE:FF,adapter=hci1 temperature=21.5")
+        );
+    }
+
+    #[test]
+    fn test_influxdb_formatter_phy() {
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_meta(&[MetaField::Mac, MetaField::Phy], &[]);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(21.5);
+        measurement.phy = Some(Phy::LeCoded);
+
+        assert!(
+            formatter
+                .format(&measurement, "Device")
+                .starts_with("ruuvi,mac=AA:BB:CC:DD:EE:FF,phy=coded temperature=21.5")
+        );
+
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_meta(&[MetaField::Mac], &[MetaField::Phy]);
+        assert!(
+            formatter
+                .format(&measurement, "Device")
+                .starts_with("ruuvi,mac=AA:BB:CC:DD:EE:FF phy=\"coded\",temperature=21.5")
+        );
+    }
+
+    #[test]
+    fn test_influxdb_formatter_field_mapping() {
+        let renames = [
+            parse_field_rename("battery_potential=battery").unwrap(),
+            parse_field_rename("temperature_c=temp").unwrap(),
+        ];
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_unit_suffix(true)
+            .with_field_mapping(FieldMapping::new(
+                &renames,
+                &["acceleration_z".to_string()],
+                &[],
+            ));
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(21.5);
+        measurement.battery = Some(3.0);
+        measurement.acceleration = Some((0.0, 0.5, 1.0));
+
+        let result = formatter.format(&measurement, "Device");
+
+        assert_contains_all(
+            &result,
+            &[
+                "temp=21.5",
+                "battery=3",
+                "acceleration_x=0",
+                "acceleration_y=0.5",
+            ],
+        );
+        assert!(!result.contains("acceleration_z"));
+        assert!(!result.contains("temperature_c"));
+    }
+
+    #[test]
+    fn test_influxdb_formatter_no_selected_fields() {
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_field_mapping(FieldMapping::new(&[], &[], &["co2".to_string()]));
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(21.5);
+
+        assert_eq!(formatter.format(&measurement, "Device"), "");
+    }
+
+    #[test]
+    fn test_field_names_match_written_fields() {
+        let units = Units::new(
+            UnitSystem::Si,
+            &[UnitOverride::Humidity(HumidityUnit::Ratio)],
+        );
+        let formatter = InfluxDbFormatter::new("ruuvi".to_string())
+            .with_units(units)
+            .with_unit_suffix(true)
+            .with_meta(&[], &[MetaField::Format, MetaField::Phy]);
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut m = base_measurement(TEST_MAC, timestamp);
+        m.phy = Some(Phy::Le1M);
+        m.temperature = Some(21.5);
+        m.humidity = Some(45.0);
+        m.pressure = Some(100_000.0);
+        m.pressure_sea_level = Some(101_000.0);
+        m.pressure_trend_3h = Some(-150.0);
+        m.pressure_tendency = Some(PressureTendency::Falling);
+        m.battery = Some(3.0);
+        m.tx_power = Some(4);
+        m.movement_counter = Some(1);
+        m.measurement_sequence = Some(2);
+        m.tag_id = Some(3);
+        m.acceleration = Some((0.0, 0.5, 1.0));
+        (m.pm1_0, m.pm2_5, m.pm4_0, m.pm10_0) = (Some(1.0), Some(2.5), Some(4.0), Some(10.0));
+        (m.co2, m.voc_index, m.nox_index) = (Some(400.0), Some(100.0), Some(1.0));
+        m.luminosity = Some(50.0);
+
+        let line = formatter.format(&m, "Device");
+        let fields = line.split(' ').nth(1).unwrap();
+        let written: Vec<&str> = fields
+            .split(',')
+            .map(|field| field.split_once('=').unwrap().0)
+            .collect();
+
+        assert_eq!(written, formatter.field_names());
+    }
+
+    #[test]
+    fn test_check_field_mapping() {
+        let mapping = |renames: &[&str], dropped: &[&str]| {
+            let renames: Vec<_> = renames
+                .iter()
+                .map(|r| parse_field_rename(r).unwrap())
+                .collect();
+            let dropped: Vec<_> = dropped.iter().map(|d| d.to_string()).collect();
+            InfluxDbFormatter::new("ruuvi".to_string())
+                .with_field_mapping(FieldMapping::new(&renames, &dropped, &[]))
+                .check_field_mapping()
+        };
+
+        assert!(mapping(&["temperature=temp"], &[]).is_ok());
+        assert!(mapping(&["temperature=humidity", "humidity=rh"], &[]).is_ok());
+        assert!(mapping(&["temperature=humidity"], &["humidity"]).is_ok());
+        assert_eq!(
+            mapping(&["temperature=humidity"], &[]).unwrap_err(),
+            "invalid field rename: temperature and humidity would both be written as humidity"
+        );
+        assert!(mapping(&["pm1_0=pm", "pm2_5=pm"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_influxdb_formatter_integer_modes() {
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1000000000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.tx_power = Some(-4);
+        measurement.movement_counter = Some(10);
+        measurement.measurement_sequence = Some(100);
+        measurement.tag_id = Some(64);
+
+        let format = |integers| {
+            InfluxDbFormatter::new("ruuvi".to_string())
+                .with_integers(integers)
+                .format(&measurement, "Device")
+        };
+
+        assert_contains_all(
+            &format(IntegerMode::Float),
+            &[
+                "tx_power=-4,",
+                "movement_counter=10,",
+                "measurement_sequence_number=100,",
+                "tag_id=64 ",
+            ],
+        );
+        assert_contains_all(
+            &format(IntegerMode::Integer),
+            &[
+                "tx_power=-4i",
+                "movement_counter=10i",
+                "measurement_sequence_number=100i",
+                "tag_id=64i",
+            ],
+        );
+        assert_contains_all(
+            &format(IntegerMode::Unsigned),
+            &[
+                "tx_power=-4i",
+                "movement_counter=10u",
+                "measurement_sequence_number=100u",
+                "tag_id=64u",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_influxdb_formatter_timestamp_precision() {
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::new(1000000000, 600_000_000);
+        let mut measurement = base_measurement(TEST_MAC, timestamp);
+        measurement.temperature = Some(21.5);
+
+        let format = |precision| {
+            InfluxDbFormatter::new("ruuvi".to_string())
+                .with_timestamp_precision(precision)
+                .format(&measurement, "Device")
+        };
+
+        assert!(format(TimestampPrecision::S).ends_with("temperature=21.5 1000000001"));
+        assert!(format(TimestampPrecision::Ms).ends_with("temperature=21.5 1000000000600"));
+        assert!(format(TimestampPrecision::None).ends_with("temperature=21.5"));
+    }
+
+    #[test]
+    fn test_field_value_display() {
+        assert_eq!(FieldValue::Float(1.5).to_string(), "1.5");
+        assert_eq!(FieldValue::Integer(-3).to_string(), "-3i");
+        assert_eq!(FieldValue::Unsigned(3).to_string(), "3u");
+    }
+
     #[test]
     fn test_influxdb_formatter_with_alias() {
         let formatter = InfluxDbFormatter::new("ruuvi".to_string());
@@ -4,9 +4,60 @@
 //! for various output formats. Currently supports InfluxDB line protocol, with
 //! extensibility for future formats like JSON and CSV.
 
+pub mod fields;
 pub mod influxdb;
+pub mod units;
 
 use crate::measurement::Measurement;
+use std::time::SystemTime;
+
+/// Precision of timestamps in formatted output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TimestampPrecision {
+    /// Seconds
+    S,
+    /// Milliseconds
+    Ms,
+    /// Microseconds
+    Us,
+    /// Nanoseconds
+    #[default]
+    Ns,
+    /// Omit the timestamp, letting the receiver assign one
+    None,
+}
+
+impl TimestampPrecision {
+    /// Convert a timestamp to an integer count of this precision's units since
+    /// the Unix epoch, rounded to the nearest unit.
+    ///
+    /// Returns `None` if timestamps are omitted. Timestamps before the Unix epoch
+    /// (which shouldn't happen for sensor data) are mapped to 0.
+    ///
+    /// # Example
+    /// ```
+    /// use ruuvitag_listener::output::TimestampPrecision;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(1_500);
+    /// assert_eq!(TimestampPrecision::S.since_epoch(timestamp), Some(2));
+    /// assert_eq!(TimestampPrecision::None.since_epoch(timestamp), None);
+    /// ```
+    pub fn since_epoch(self, timestamp: SystemTime) -> Option<u128> {
+        let unit: u128 = match self {
+            TimestampPrecision::S => 1_000_000_000,
+            TimestampPrecision::Ms => 1_000_000,
+            TimestampPrecision::Us => 1_000,
+            TimestampPrecision::Ns => 1,
+            TimestampPrecision::None => return None,
+        };
+        let nanos = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        Some((nanos + unit / 2) / unit)
+    }
+}
 
 /// Trait for formatting measurements into output strings.
 ///
@@ -15,6 +66,10 @@ use crate::measurement::Measurement;
 ///
 /// The `name` parameter is the resolved device name (either an alias or the MAC address),
 /// determined by the caller. This keeps formatters simple and free of alias handling logic.
+///
+/// Measurements are in SI base units; implementations convert unit-bearing values
+/// to the user's choice using [`units::Units`], and write timestamps with the
+/// user's [`TimestampPrecision`].
 pub trait OutputFormatter: Send + Sync {
     /// Format a measurement.
     ///
@@ -23,6 +78,48 @@ pub trait OutputFormatter: Send + Sync {
     /// * `name` - The resolved device name (alias or MAC address)
     ///
     /// # Returns
-    /// A formatted string representation of the measurement
+    /// A formatted string representation of the measurement, or an empty string
+    /// if the measurement has nothing to output
     fn format(&self, measurement: &Measurement, name: &str) -> String;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp_precision_rounds() {
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::new(1_000_000_000, 499_999_999);
+        assert_eq!(
+            TimestampPrecision::S.since_epoch(timestamp),
+            Some(1_000_000_000)
+        );
+        assert_eq!(
+            TimestampPrecision::Ms.since_epoch(timestamp),
+            Some(1_000_000_000_500)
+        );
+        assert_eq!(
+            TimestampPrecision::Us.since_epoch(timestamp),
+            Some(1_000_000_000_500_000)
+        );
+        assert_eq!(
+            TimestampPrecision::Ns.since_epoch(timestamp),
+            Some(1_000_000_000_499_999_999)
+        );
+
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::new(1, 500_000_000);
+        assert_eq!(TimestampPrecision::S.since_epoch(timestamp), Some(2));
+    }
+
+    #[test]
+    fn test_timestamp_precision_none_and_before_epoch() {
+        assert_eq!(
+            TimestampPrecision::None.since_epoch(SystemTime::now()),
+            None
+        );
+
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(TimestampPrecision::Ms.since_epoch(before_epoch), Some(0));
+    }
+}
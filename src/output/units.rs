@@ -0,0 +1,444 @@
+//! Unit selection for output formatters.
+//!
+//! Measurements are always stored in SI base units (see [`Measurement`]).
+//! Formatters convert them to the units chosen here on output, so the same
+//! unit settings apply to every output format.
+//!
+//! [`Measurement`]: crate::measurement::Measurement
+
+/// Standard atmospheric pressure in Pascals.
+const STANDARD_PRESSURE: f64 = 101_325.0;
+
+/// Pascals per inch of mercury.
+const PASCALS_PER_INHG: f64 = 3386.389;
+
+/// Pascals per millimeter of mercury.
+const PASCALS_PER_MMHG: f64 = 133.322_387;
+
+/// Ratio of the molar masses of water vapour and dry air.
+const WATER_AIR_MASS_RATIO: f64 = 0.621_945;
+
+/// A predefined set of output units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum UnitSystem {
+    /// Celsius, kilopascals and relative humidity in percent
+    #[default]
+    Si,
+    /// Celsius, hectopascals and relative humidity in percent
+    MetricHpa,
+    /// Fahrenheit, inches of mercury and relative humidity in percent
+    Imperial,
+}
+
+/// Output unit for temperatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    /// Degrees Celsius
+    Celsius,
+    /// Degrees Fahrenheit
+    Fahrenheit,
+    /// Kelvin
+    Kelvin,
+}
+
+/// Output unit for pressures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureUnit {
+    /// Pascals
+    Pascal,
+    /// Hectopascals (equal to millibars)
+    Hectopascal,
+    /// Kilopascals
+    Kilopascal,
+    /// Inches of mercury
+    InchOfMercury,
+    /// Millimeters of mercury
+    MillimeterOfMercury,
+}
+
+/// Output unit for humidity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HumidityUnit {
+    /// Relative humidity in percent
+    Percent,
+    /// Humidity ratio in grams of water vapour per kilogram of dry air
+    Ratio,
+}
+
+impl TemperatureUnit {
+    /// Convert a temperature in Celsius to this unit.
+    #[inline]
+    pub fn convert(self, celsius: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+            TemperatureUnit::Kelvin => celsius + 273.15,
+        }
+    }
+
+    /// Suffix appended to field names when unit suffixes are enabled.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "c",
+            TemperatureUnit::Fahrenheit => "f",
+            TemperatureUnit::Kelvin => "k",
+        }
+    }
+}
+
+impl PressureUnit {
+    /// Convert a pressure (or pressure difference) in Pascals to this unit.
+    #[inline]
+    pub fn convert(self, pascals: f64) -> f64 {
+        match self {
+            PressureUnit::Pascal => pascals,
+            PressureUnit::Hectopascal => pascals / 100.0,
+            PressureUnit::Kilopascal => pascals / 1000.0,
+            PressureUnit::InchOfMercury => pascals / PASCALS_PER_INHG,
+            PressureUnit::MillimeterOfMercury => pascals / PASCALS_PER_MMHG,
+        }
+    }
+
+    /// Suffix appended to field names when unit suffixes are enabled.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            PressureUnit::Pascal => "pa",
+            PressureUnit::Hectopascal => "hpa",
+            PressureUnit::Kilopascal => "kpa",
+            PressureUnit::InchOfMercury => "inhg",
+            PressureUnit::MillimeterOfMercury => "mmhg",
+        }
+    }
+}
+
+impl HumidityUnit {
+    /// Convert relative humidity to this unit.
+    ///
+    /// The humidity ratio depends on temperature and pressure; it is `None`
+    /// when the temperature is unknown. Standard atmospheric pressure is
+    /// assumed when the pressure is unknown.
+    ///
+    /// # Arguments
+    /// * `relative` - Relative humidity in percent
+    /// * `temperature` - Temperature in Celsius
+    /// * `pressure` - Pressure in Pascals
+    pub fn convert(
+        self,
+        relative: f64,
+        temperature: Option<f64>,
+        pressure: Option<f64>,
+    ) -> Option<f64> {
+        match self {
+            HumidityUnit::Percent => Some(relative),
+            HumidityUnit::Ratio => {
+                let vapour = relative / 100.0 * saturation_vapour_pressure(temperature?);
+                let pressure = pressure.unwrap_or(STANDARD_PRESSURE);
+                Some(1000.0 * WATER_AIR_MASS_RATIO * vapour / (pressure - vapour))
+            }
+        }
+    }
+
+    /// Name of the humidity field in this unit.
+    ///
+    /// The humidity ratio is a different quantity from relative humidity, so
+    /// it gets a field of its own rather than sharing `humidity`.
+    pub fn field(self) -> &'static str {
+        match self {
+            HumidityUnit::Percent => "humidity",
+            HumidityUnit::Ratio => "humidity_ratio",
+        }
+    }
+
+    /// Suffix appended to field names when unit suffixes are enabled.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            HumidityUnit::Percent => "pct",
+            HumidityUnit::Ratio => "gkg",
+        }
+    }
+}
+
+/// Saturation vapour pressure over water in Pascals (Magnus formula).
+fn saturation_vapour_pressure(celsius: f64) -> f64 {
+    611.2 * (17.62 * celsius / (243.12 + celsius)).exp()
+}
+
+/// The output unit for each field with a selectable unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Units {
+    /// Unit for the temperature field
+    pub temperature: TemperatureUnit,
+    /// Unit for the station pressure field
+    pub pressure: PressureUnit,
+    /// Unit for the sea-level pressure field
+    pub pressure_sea_level: PressureUnit,
+    /// Unit for the three-hour pressure change, hPa in every unit system
+    pub pressure_trend_3h: PressureUnit,
+    /// Unit for the humidity field
+    pub humidity: HumidityUnit,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        UnitSystem::default().into()
+    }
+}
+
+impl From<UnitSystem> for Units {
+    fn from(system: UnitSystem) -> Self {
+        let (temperature, pressure) = match system {
+            UnitSystem::Si => (TemperatureUnit::Celsius, PressureUnit::Kilopascal),
+            UnitSystem::MetricHpa => (TemperatureUnit::Celsius, PressureUnit::Hectopascal),
+            UnitSystem::Imperial => (TemperatureUnit::Fahrenheit, PressureUnit::InchOfMercury),
+        };
+        Units {
+            temperature,
+            pressure,
+            pressure_sea_level: pressure,
+            // Synoptic reports give the pressure tendency in hPa
+            pressure_trend_3h: PressureUnit::Hectopascal,
+            humidity: HumidityUnit::Percent,
+        }
+    }
+}
+
+impl Units {
+    /// Build units from a unit system with per-field overrides applied on top.
+    pub fn new(system: UnitSystem, overrides: &[UnitOverride]) -> Self {
+        let mut units = Units::from(system);
+        for unit_override in overrides {
+            match *unit_override {
+                UnitOverride::Temperature(unit) => units.temperature = unit,
+                UnitOverride::Pressure(PressureField::Pressure, unit) => units.pressure = unit,
+                UnitOverride::Pressure(PressureField::SeaLevel, unit) => {
+                    units.pressure_sea_level = unit
+                }
+                UnitOverride::Pressure(PressureField::Trend3h, unit) => {
+                    units.pressure_trend_3h = unit
+                }
+                UnitOverride::Humidity(unit) => units.humidity = unit,
+            }
+        }
+        units
+    }
+}
+
+/// A field written in a pressure unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureField {
+    /// `pressure`, the station pressure
+    Pressure,
+    /// `pressure_sea_level`
+    SeaLevel,
+    /// `pressure_trend_3h`
+    Trend3h,
+}
+
+/// A unit chosen for a single field, overriding the unit system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitOverride {
+    Temperature(TemperatureUnit),
+    Pressure(PressureField, PressureUnit),
+    Humidity(HumidityUnit),
+}
+
+/// Parse a unit override from a string in the format "FIELD=UNIT".
+///
+/// Fields are `temperature`, `humidity`, `pressure`, `pressure_sea_level` and
+/// `pressure_trend_3h`. Units are `c`, `f`, `k` for temperature; `pa`, `hpa`,
+/// `kpa`, `inhg`, `mmhg` for the pressure fields; and `percent`, `ratio` for
+/// humidity.
+///
+/// # Example
+/// ```
+/// use ruuvitag_listener::output::units::{
+///     PressureField, PressureUnit, UnitOverride, parse_unit_override,
+/// };
+///
+/// let unit = parse_unit_override("pressure_sea_level=inhg").unwrap();
+/// assert_eq!(
+///     unit,
+///     UnitOverride::Pressure(PressureField::SeaLevel, PressureUnit::InchOfMercury)
+/// );
+/// ```
+pub fn parse_unit_override(src: &str) -> Result<UnitOverride, String> {
+    let (field, unit) = src
+        .split_once('=')
+        .ok_or_else(|| "invalid unit: expected format FIELD=UNIT".to_string())?;
+    let unit = unit.trim().to_lowercase();
+
+    let pressure_field = match field.trim().to_lowercase().as_str() {
+        "pressure" => Some(PressureField::Pressure),
+        "pressure_sea_level" => Some(PressureField::SeaLevel),
+        "pressure_trend_3h" => Some(PressureField::Trend3h),
+        _ => None,
+    };
+    if let Some(pressure_field) = pressure_field {
+        return match unit.as_str() {
+            "pa" => Ok(PressureUnit::Pascal),
+            "hpa" | "mbar" => Ok(PressureUnit::Hectopascal),
+            "kpa" => Ok(PressureUnit::Kilopascal),
+            "inhg" => Ok(PressureUnit::InchOfMercury),
+            "mmhg" => Ok(PressureUnit::MillimeterOfMercury),
+            _ => Err(format!("invalid pressure unit: {}", unit)),
+        }
+        .map(|unit| UnitOverride::Pressure(pressure_field, unit));
+    }
+
+    match field.trim().to_lowercase().as_str() {
+        "temperature" => match unit.as_str() {
+            "c" | "celsius" => Ok(TemperatureUnit::Celsius),
+            "f" | "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+            "k" | "kelvin" => Ok(TemperatureUnit::Kelvin),
+            _ => Err(format!("invalid temperature unit: {}", unit)),
+        }
+        .map(UnitOverride::Temperature),
+        "humidity" => match unit.as_str() {
+            "percent" | "pct" | "%" => Ok(HumidityUnit::Percent),
+            "ratio" => Ok(HumidityUnit::Ratio),
+            _ => Err(format!("invalid humidity unit: {}", unit)),
+        }
+        .map(UnitOverride::Humidity),
+        _ => Err(format!("invalid unit field: {}", field)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn test_default_units_match_si() {
+        let units = Units::default();
+        assert_eq!(units.temperature, TemperatureUnit::Celsius);
+        assert_eq!(units.pressure, PressureUnit::Kilopascal);
+        assert_eq!(units.pressure_sea_level, PressureUnit::Kilopascal);
+        assert_eq!(units.pressure_trend_3h, PressureUnit::Hectopascal);
+        assert_eq!(units.humidity, HumidityUnit::Percent);
+    }
+
+    #[test]
+    fn test_temperature_conversion() {
+        assert_eq!(TemperatureUnit::Celsius.convert(21.5), 21.5);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert(100.0), 212.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert(-40.0), -40.0);
+        assert_eq!(TemperatureUnit::Kelvin.convert(0.0), 273.15);
+    }
+
+    #[test]
+    fn test_pressure_conversion() {
+        assert_eq!(PressureUnit::Pascal.convert(101_325.0), 101_325.0);
+        assert_eq!(PressureUnit::Hectopascal.convert(101_325.0), 1013.25);
+        assert_eq!(PressureUnit::Kilopascal.convert(101_325.0), 101.325);
+        assert_close(
+            PressureUnit::InchOfMercury.convert(101_325.0),
+            29.921,
+            0.001,
+        );
+        assert_close(
+            PressureUnit::MillimeterOfMercury.convert(101_325.0),
+            760.0,
+            0.01,
+        );
+    }
+
+    #[test]
+    fn test_humidity_ratio() {
+        // 50 % RH at 20 °C and sea level is about 7.3 g/kg.
+        let ratio = HumidityUnit::Ratio
+            .convert(50.0, Some(20.0), Some(101_325.0))
+            .unwrap();
+        assert_close(ratio, 7.25, 0.05);
+
+        // Standard pressure is assumed when unknown.
+        assert_eq!(
+            HumidityUnit::Ratio.convert(50.0, Some(20.0), None),
+            Some(ratio)
+        );
+
+        // Temperature is required.
+        assert_eq!(HumidityUnit::Ratio.convert(50.0, None, None), None);
+        assert_eq!(HumidityUnit::Percent.convert(50.0, None, None), Some(50.0));
+    }
+
+    #[test]
+    fn test_unit_systems() {
+        let imperial = Units::from(UnitSystem::Imperial);
+        assert_eq!(imperial.temperature, TemperatureUnit::Fahrenheit);
+        assert_eq!(imperial.pressure, PressureUnit::InchOfMercury);
+        assert_eq!(imperial.pressure_sea_level, PressureUnit::InchOfMercury);
+        assert_eq!(imperial.pressure_trend_3h, PressureUnit::Hectopascal);
+
+        let metric = Units::from(UnitSystem::MetricHpa);
+        assert_eq!(metric.temperature, TemperatureUnit::Celsius);
+        assert_eq!(metric.pressure, PressureUnit::Hectopascal);
+    }
+
+    #[test]
+    fn test_overrides_apply_on_top_of_system() {
+        let units = Units::new(
+            UnitSystem::Imperial,
+            &[
+                UnitOverride::Humidity(HumidityUnit::Ratio),
+                UnitOverride::Pressure(PressureField::Pressure, PressureUnit::Hectopascal),
+                UnitOverride::Pressure(PressureField::Trend3h, PressureUnit::Pascal),
+            ],
+        );
+        assert_eq!(units.temperature, TemperatureUnit::Fahrenheit);
+        assert_eq!(units.pressure, PressureUnit::Hectopascal);
+        // Each pressure field has its own unit
+        assert_eq!(units.pressure_sea_level, PressureUnit::InchOfMercury);
+        assert_eq!(units.pressure_trend_3h, PressureUnit::Pascal);
+        assert_eq!(units.humidity, HumidityUnit::Ratio);
+    }
+
+    #[test]
+    fn test_parse_unit_override() {
+        let cases = [
+            (
+                "temperature=f",
+                UnitOverride::Temperature(TemperatureUnit::Fahrenheit),
+            ),
+            (
+                "Temperature=Kelvin",
+                UnitOverride::Temperature(TemperatureUnit::Kelvin),
+            ),
+            (
+                "pressure=hpa",
+                UnitOverride::Pressure(PressureField::Pressure, PressureUnit::Hectopascal),
+            ),
+            (
+                "pressure_sea_level=mmhg",
+                UnitOverride::Pressure(PressureField::SeaLevel, PressureUnit::MillimeterOfMercury),
+            ),
+            (
+                "pressure_trend_3h=pa",
+                UnitOverride::Pressure(PressureField::Trend3h, PressureUnit::Pascal),
+            ),
+            (
+                "humidity=ratio",
+                UnitOverride::Humidity(HumidityUnit::Ratio),
+            ),
+        ];
+
+        for (src, expected) in cases {
+            assert_eq!(parse_unit_override(src).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_unit_override_invalid() {
+        assert!(parse_unit_override("temperature").is_err());
+        assert!(parse_unit_override("temperature=rankine").is_err());
+        assert!(parse_unit_override("speed=knots").is_err());
+        assert!(parse_unit_override("humidity=kpa").is_err());
+        assert!(parse_unit_override("pressure_trend_3h=c").is_err());
+    }
+}
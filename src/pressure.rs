@@ -0,0 +1,387 @@
+//! Barometric pressure post-processing for RuuviTag measurements.
+//!
+//! RuuviTags report station pressure, i.e. the absolute pressure at the height
+//! the tag is mounted. Weather dashboards usually want pressure reduced to sea
+//! level and the three-hour pressure tendency used in synoptic reports. This
+//! module derives both from the `Measurement.pressure` stream.
+
+use crate::mac_address::MacAddress;
+use crate::measurement::{Measurement, PressureTendency};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// Length of the window over which the pressure tendency is computed.
+const TREND_WINDOW: Duration = Duration::from_secs(3 * 3600);
+
+/// Minimum spacing between samples kept in the per-device history.
+///
+/// Tags broadcast roughly once per second, but a tendency over three hours
+/// needs nowhere near that resolution. Sampling keeps the history at a few
+/// dozen entries per device.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Oldest reference sample the tendency is computed from.
+///
+/// After a gap in the data, the newest sample before the window can be far
+/// older than three hours; the change since then is not a three-hour tendency.
+const MAX_REFERENCE_AGE: Duration = TREND_WINDOW.saturating_add(SAMPLE_INTERVAL);
+
+/// Pressure changes smaller than this (in Pa) over the window count as steady.
+///
+/// 0.1 hPa is the smallest change the WMO tendency code distinguishes.
+const STEADY_THRESHOLD_PA: f64 = 10.0;
+
+/// Temperature gradient of the standard atmosphere in K/m.
+const LAPSE_RATE: f64 = 0.0065;
+
+/// Exponent of the barometric formula (g·M / (R·L)).
+const BAROMETRIC_EXPONENT: f64 = 5.257;
+
+/// Temperature assumed when a measurement carries none (standard atmosphere).
+const STANDARD_TEMPERATURE: f64 = 15.0;
+
+/// A parsed per-tag altitude mapping a MAC address to a height in meters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagAltitude {
+    /// The MAC address of the tag
+    pub address: MacAddress,
+    /// Altitude of the tag above sea level in meters
+    pub altitude: f64,
+}
+
+/// Parse a per-tag altitude from a string in the format "MAC=METERS".
+///
+/// # Example
+/// ```
+/// use ruuvitag_listener::pressure::parse_tag_altitude;
+///
+/// let tag = parse_tag_altitude("AA:BB:CC:DD:EE:FF=120.5").unwrap();
+/// assert_eq!(tag.address.to_string(), "AA:BB:CC:DD:EE:FF");
+/// assert_eq!(tag.altitude, 120.5);
+/// ```
+pub fn parse_tag_altitude(src: &str) -> Result<TagAltitude, String> {
+    let (address_str, altitude_str) = src
+        .split_once('=')
+        .ok_or_else(|| "invalid tag altitude: expected format MAC=METERS".to_string())?;
+
+    let address: MacAddress = address_str
+        .parse()
+        .map_err(|e| format!("invalid MAC address: {}", e))?;
+
+    let altitude: f64 = altitude_str
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid altitude: {}", altitude_str))?;
+
+    if !altitude.is_finite() {
+        return Err(format!("invalid altitude: {}", altitude_str));
+    }
+
+    Ok(TagAltitude { address, altitude })
+}
+
+/// Reduce station pressure to sea level.
+///
+/// Uses the barometric formula with the standard lapse rate, taking the
+/// measured temperature at the station into account. When no temperature is
+/// available the standard atmosphere temperature of 15 °C is assumed.
+///
+/// # Arguments
+/// * `pressure` - Station pressure in Pascals
+/// * `altitude` - Station altitude in meters
+/// * `temperature` - Temperature at the station in Celsius
+///
+/// # Returns
+/// Pressure reduced to sea level in Pascals.
+pub fn sea_level_pressure(pressure: f64, altitude: f64, temperature: Option<f64>) -> f64 {
+    let temperature = temperature.unwrap_or(STANDARD_TEMPERATURE);
+    let gradient = LAPSE_RATE * altitude;
+    pressure * (1.0 - gradient / (temperature + gradient + 273.15)).powf(-BAROMETRIC_EXPONENT)
+}
+
+/// Classify a pressure change over the tendency window.
+fn tendency(change: f64) -> PressureTendency {
+    if change >= STEADY_THRESHOLD_PA {
+        PressureTendency::Rising
+    } else if change <= -STEADY_THRESHOLD_PA {
+        PressureTendency::Falling
+    } else {
+        PressureTendency::Steady
+    }
+}
+
+/// Stateful processor deriving sea-level pressure and pressure tendency.
+///
+/// Sea-level pressure is computed for tags that have an altitude, either
+/// configured per tag or through the global default. The tendency is tracked
+/// per device from a sampled pressure history and reported once the history
+/// covers the full three-hour window.
+///
+/// Measurement timestamps, not wall-clock time, drive the history so that
+/// recorded data produces the same tendency as it did live.
+#[derive(Debug)]
+pub struct PressureProcessor {
+    /// Altitude applied to tags without a per-tag override
+    default_altitude: Option<f64>,
+    /// Per-tag altitudes in meters
+    altitudes: HashMap<MacAddress, f64>,
+    /// Whether to compute the three-hour tendency
+    track_trend: bool,
+    /// Sampled (timestamp, station pressure) history per device, oldest first
+    history: HashMap<MacAddress, VecDeque<(SystemTime, f64)>>,
+}
+
+impl PressureProcessor {
+    /// Create a new pressure processor.
+    ///
+    /// # Arguments
+    /// * `default_altitude` - Altitude in meters for tags without an override
+    /// * `altitudes` - Per-tag altitude overrides
+    /// * `track_trend` - Whether to compute the three-hour pressure tendency
+    pub fn new(
+        default_altitude: Option<f64>,
+        altitudes: &[TagAltitude],
+        track_trend: bool,
+    ) -> Self {
+        PressureProcessor {
+            default_altitude,
+            altitudes: altitudes.iter().map(|t| (t.address, t.altitude)).collect(),
+            track_trend,
+            history: HashMap::new(),
+        }
+    }
+
+    /// Derive pressure fields for a measurement in place.
+    ///
+    /// Measurements without a pressure reading are left untouched.
+    pub fn process(&mut self, m: &mut Measurement) {
+        let Some(pressure) = m.pressure else {
+            return;
+        };
+
+        let altitude = self
+            .altitudes
+            .get(&m.mac)
+            .copied()
+            .or(self.default_altitude);
+        if let Some(altitude) = altitude {
+            m.pressure_sea_level = Some(sea_level_pressure(pressure, altitude, m.temperature));
+        }
+
+        if self.track_trend
+            && let Some(change) = self.record(m.mac, m.timestamp, pressure)
+        {
+            m.pressure_trend_3h = Some(change);
+            m.pressure_tendency = Some(tendency(change));
+        }
+    }
+
+    /// Add a sample to a device's history and return the pressure change over
+    /// the tendency window, if the history covers it without a gap.
+    fn record(&mut self, mac: MacAddress, timestamp: SystemTime, pressure: f64) -> Option<f64> {
+        let samples = self.history.entry(mac).or_default();
+
+        let age = |then: SystemTime| timestamp.duration_since(then).unwrap_or(Duration::ZERO);
+
+        // Drop samples that no longer contribute to the window. The newest
+        // sample older than the window is kept as the reference point.
+        while samples.len() >= 2 && age(samples[1].0) >= TREND_WINDOW {
+            samples.pop_front();
+        }
+
+        let change = samples
+            .front()
+            .filter(|(then, _)| (TREND_WINDOW..=MAX_REFERENCE_AGE).contains(&age(*then)))
+            .map(|(_, reference)| pressure - reference);
+
+        let due = samples
+            .back()
+            .is_none_or(|(then, _)| age(*then) >= SAMPLE_INTERVAL);
+        if due {
+            samples.push_back((timestamp, pressure));
+        }
+
+        change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TEST_MAC, base_measurement};
+
+    const MAC2: MacAddress = MacAddress([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    fn pressure_measurement(mac: MacAddress, secs: u64, pressure: f64) -> Measurement {
+        let mut m = base_measurement(mac, SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        m.pressure = Some(pressure);
+        m
+    }
+
+    #[test]
+    fn parse_tag_altitude_valid_cases() {
+        let cases = [
+            ("AA:BB:CC:DD:EE:FF=120", 120.0),
+            ("AA:BB:CC:DD:EE:FF=-3.5", -3.5),
+            ("aa:bb:cc:dd:ee:ff= 42 ", 42.0),
+        ];
+
+        for (src, expected) in cases {
+            let tag = parse_tag_altitude(src).unwrap();
+            assert_eq!(tag.address, TEST_MAC);
+            assert_eq!(tag.altitude, expected);
+        }
+    }
+
+    #[test]
+    fn parse_tag_altitude_invalid() {
+        assert!(parse_tag_altitude("no-equals-sign").is_err());
+        assert!(parse_tag_altitude("invalid-mac=10").is_err());
+        assert!(parse_tag_altitude("AA:BB:CC:DD:EE:FF=high").is_err());
+        assert!(parse_tag_altitude("AA:BB:CC:DD:EE:FF=NaN").is_err());
+    }
+
+    #[test]
+    fn test_sea_level_pressure_at_sea_level_is_unchanged() {
+        assert_eq!(sea_level_pressure(101_325.0, 0.0, Some(20.0)), 101_325.0);
+    }
+
+    #[test]
+    fn test_sea_level_pressure_standard_atmosphere() {
+        // Standard atmosphere at 500 m is 95 461 Pa at 11.75 °C.
+        let reduced = sea_level_pressure(95_461.0, 500.0, Some(11.75));
+        assert!((reduced - 101_325.0).abs() < 10.0, "got {reduced}");
+    }
+
+    #[test]
+    fn test_sea_level_pressure_defaults_to_standard_temperature() {
+        assert_eq!(
+            sea_level_pressure(95_000.0, 500.0, None),
+            sea_level_pressure(95_000.0, 500.0, Some(STANDARD_TEMPERATURE))
+        );
+    }
+
+    #[test]
+    fn test_processor_uses_per_tag_altitude_over_default() {
+        let altitudes = [TagAltitude {
+            address: TEST_MAC,
+            altitude: 500.0,
+        }];
+        let mut processor = PressureProcessor::new(Some(0.0), &altitudes, false);
+
+        let mut tagged = pressure_measurement(TEST_MAC, 0, 95_000.0);
+        processor.process(&mut tagged);
+        assert!(tagged.pressure_sea_level.unwrap() > 100_000.0);
+
+        let mut other = pressure_measurement(MAC2, 0, 95_000.0);
+        processor.process(&mut other);
+        assert_eq!(other.pressure_sea_level, Some(95_000.0));
+    }
+
+    #[test]
+    fn test_processor_without_altitude_skips_sea_level() {
+        let mut processor = PressureProcessor::new(None, &[], false);
+        let mut m = pressure_measurement(TEST_MAC, 0, 95_000.0);
+        processor.process(&mut m);
+        assert_eq!(m.pressure_sea_level, None);
+        assert_eq!(m.pressure_trend_3h, None);
+    }
+
+    #[test]
+    fn test_processor_ignores_missing_pressure() {
+        let mut processor = PressureProcessor::new(Some(100.0), &[], true);
+        let mut m = base_measurement(TEST_MAC, SystemTime::UNIX_EPOCH);
+        processor.process(&mut m);
+        assert_eq!(m.pressure_sea_level, None);
+        assert!(processor.history.is_empty());
+    }
+
+    #[test]
+    fn test_trend_requires_full_window() {
+        let mut processor = PressureProcessor::new(None, &[], true);
+
+        let mut first = pressure_measurement(TEST_MAC, 0, 100_000.0);
+        processor.process(&mut first);
+        assert_eq!(first.pressure_trend_3h, None);
+
+        let mut early = pressure_measurement(TEST_MAC, 2 * 3600, 100_050.0);
+        processor.process(&mut early);
+        assert_eq!(early.pressure_trend_3h, None);
+        assert_eq!(early.pressure_tendency, None);
+    }
+
+    #[test]
+    fn test_trend_rising_falling_steady() {
+        let cases = [
+            (100_150.0, 150.0, PressureTendency::Rising),
+            (99_800.0, -200.0, PressureTendency::Falling),
+            (100_005.0, 5.0, PressureTendency::Steady),
+        ];
+
+        for (current, expected_change, expected_tendency) in cases {
+            let mut processor = PressureProcessor::new(None, &[], true);
+            processor.process(&mut pressure_measurement(TEST_MAC, 0, 100_000.0));
+
+            let mut m = pressure_measurement(TEST_MAC, 3 * 3600, current);
+            processor.process(&mut m);
+            assert_eq!(m.pressure_trend_3h, Some(expected_change));
+            assert_eq!(m.pressure_tendency, Some(expected_tendency));
+        }
+    }
+
+    #[test]
+    fn test_trend_uses_sample_closest_to_window() {
+        let mut processor = PressureProcessor::new(None, &[], true);
+
+        // One sample every 10 minutes for four hours, rising 10 Pa each time.
+        for i in 0..=24u64 {
+            let mut m = pressure_measurement(TEST_MAC, i * 600, 100_000.0 + i as f64 * 10.0);
+            processor.process(&mut m);
+            if i >= 18 {
+                // Exactly three hours (18 samples) back.
+                assert_eq!(m.pressure_trend_3h, Some(180.0), "sample {i}");
+            }
+        }
+
+        // History stays bounded to the window.
+        assert!(processor.history[&TEST_MAC].len() <= 20);
+    }
+
+    #[test]
+    fn test_trend_skips_data_gaps() {
+        let mut processor = PressureProcessor::new(None, &[], true);
+        processor.process(&mut pressure_measurement(TEST_MAC, 0, 100_000.0));
+
+        // Silent for ten hours: the change since then is not a 3 h tendency
+        let mut after_gap = pressure_measurement(TEST_MAC, 10 * 3600, 100_500.0);
+        processor.process(&mut after_gap);
+        assert_eq!(after_gap.pressure_trend_3h, None);
+        assert_eq!(after_gap.pressure_tendency, None);
+
+        // Three hours after the gap, the tendency is back
+        let mut later = pressure_measurement(TEST_MAC, 13 * 3600, 100_400.0);
+        processor.process(&mut later);
+        assert_eq!(later.pressure_trend_3h, Some(-100.0));
+        assert_eq!(later.pressure_tendency, Some(PressureTendency::Falling));
+    }
+
+    #[test]
+    fn test_trend_tracked_per_device() {
+        let mut processor = PressureProcessor::new(None, &[], true);
+        processor.process(&mut pressure_measurement(TEST_MAC, 0, 100_000.0));
+
+        let mut other = pressure_measurement(MAC2, 3 * 3600, 100_500.0);
+        processor.process(&mut other);
+        assert_eq!(other.pressure_trend_3h, None);
+    }
+
+    #[test]
+    fn test_trend_handles_timestamp_going_backwards() {
+        let mut processor = PressureProcessor::new(None, &[], true);
+        processor.process(&mut pressure_measurement(TEST_MAC, 3600, 100_000.0));
+
+        let mut m = pressure_measurement(TEST_MAC, 0, 100_100.0);
+        processor.process(&mut m);
+        assert_eq!(m.pressure_trend_3h, None);
+    }
+}
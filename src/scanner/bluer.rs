@@ -2,14 +2,33 @@
 //!
 //! This backend uses the `bluer` crate to communicate with the BlueZ daemon
 //! via D-Bus. It requires the `bluetoothd` daemon to be running.
+//!
+//! By default, the backend runs a BlueZ discovery, which scans actively. In
+//! passive mode, it instead registers an advertisement monitor matching the
+//! Ruuvi manufacturer data, and BlueZ scans passively in the background.
+//!
+//! Tags with older firmware broadcast data formats 2 and 4 as an Eddystone URL
+//! instead, which is read from the Eddystone service data (see [`eddystone`]).
 
+use super::eddystone::{self, EDDYSTONE_UUID};
+use super::params::{ScanParameters, ScanType};
+use super::watchdog::{Watchdog, watch};
 use super::{
-    DecodeError, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, RUUVI_MANUFACTURER_ID,
-    ScanError, decode_ruuvi_data,
+    AdapterId, Backend, DecodeError, FrameDecoder, MANUFACTURER_DATA_TYPE,
+    MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, RUUVI_MANUFACTURER_ID,
+    RUUVI_MANUFACTURER_ID_BYTES, SERVICE_DATA_TYPE, ScanConfig, ScanError, describe_adapter,
 };
 use crate::mac_address::MacAddress;
-use bluer::{Adapter, AdapterEvent, Address, DiscoveryFilter, DiscoveryTransport, Session};
+use bluer::monitor::{Monitor, MonitorEvent, MonitorHandle, MonitorManager, Pattern, Type};
+use bluer::{
+    Adapter, AdapterEvent, AdapterProperty, Address, DeviceEvent, DeviceProperty, DiscoveryFilter,
+    DiscoveryTransport, Session, Uuid, UuidExt,
+};
 use futures::StreamExt;
+use futures::future::ready;
+use futures::stream::{AbortHandle, BoxStream, SelectAll, abortable};
+use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
 impl From<bluer::Error> for ScanError {
@@ -20,95 +39,342 @@ impl From<bluer::Error> for ScanError {
 
 /// Start scanning for RuuviTag devices using the BlueZ D-Bus backend.
 ///
-/// This function initializes the Bluetooth adapter and starts a passive scan
-/// for RuuviTag advertisements. Discovered measurements are sent through the
-/// returned channel. Runs indefinitely until interrupted.
+/// This function initializes the Bluetooth adapter and starts a scan for
+/// RuuviTag advertisements. Discovered measurements are sent through the
+/// returned channel. Runs until the receiver is dropped.
 ///
 /// # Arguments
-/// * `verbose` - If true, decode errors are sent as Err values; otherwise they're silently dropped.
+/// * `config` - Scan settings. Decode errors are sent as Err values only if
+///   `config.verbose` is set. A passive scan type selects the advertisement
+///   monitor; the other scan parameters must be left at their defaults.
+/// * `adapter` - The adapter to scan with, or the default adapter if `None`.
 ///
 /// # Returns
 /// A receiver for measurements (or decode errors if verbose).
-pub async fn start_scan(verbose: bool) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+pub async fn start_scan(
+    config: &ScanConfig,
+    adapter_id: Option<AdapterId>,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let passive = scan_mode(&config.scan)?;
+    let verbose = config.verbose;
     let session = Session::new().await?;
-    let adapter = session.default_adapter().await?;
+    let adapter = match adapter_id {
+        Some(id) => find_adapter(&session, id).await?,
+        None => session.default_adapter().await?,
+    };
     adapter.set_powered(true).await?;
-
-    // Enable `duplicate_data` so BlueZ emits a PropertiesChanged signal for
-    // *every* advertisement, not just the first one or when the payload
-    // changes. Without this, BlueZ deduplicates repeated advertisements and we
-    // receive only a fraction of the broadcasts a tool like `bluetoothctl`
-    // shows. LE-only transport avoids spurious BR/EDR inquiry traffic.
-    adapter
-        .set_discovery_filter(DiscoveryFilter {
-            transport: DiscoveryTransport::Le,
-            duplicate_data: true,
-            ..Default::default()
-        })
-        .await?;
+    let adapter_name: Arc<str> = Arc::from(adapter.name());
 
     let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
 
-    // `discover_devices_with_changes` re-emits a `DeviceAdded` event for a
-    // device each time its properties change, giving us one notification per
-    // advertisement. We filter for RuuviTag manufacturer data in
-    // `process_device`.
-    let mut events = adapter.discover_devices_with_changes().await?;
+    let mut scan = Scan::start(&adapter, passive).await?;
+    let mut watchdog = Watchdog::from_config(config);
+    let decoder = FrameDecoder::new(Some(adapter_name.clone()), config.recorder.clone());
 
     // Spawn a task that owns all Bluetooth state and runs the event loop
-    tokio::spawn(async move {
+    config.spawn(|config| async move {
         // Keep the session alive by moving it into this task
         let _session = session;
 
-        while let Some(event) = events.next().await {
-            if let AdapterEvent::DeviceAdded(address) = event
-                && let Err(e) = process_device(&adapter, address, &tx, verbose).await
-                && verbose
-            {
-                let err = match e {
-                    ScanError::Bluetooth(e) => {
-                        DecodeError::InvalidData(format!("Bluetooth error: {e}"))
+        // The scan ends when the adapter disappears or is powered off; the
+        // supervisor then restarts it.
+        loop {
+            let next = tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                next = watch(watchdog.as_ref(), scan.next()) => next,
+            };
+            let address = match next {
+                Some(Some(address)) => address,
+                Some(None) => break,
+                None => {
+                    // No RuuviTag frames for a while: restart the scan, which
+                    // stops when the discovery stream or monitor is dropped
+                    let Some(watchdog) = watchdog.as_mut() else {
+                        break;
+                    };
+                    if let Err(e) = watchdog.expire_for(Backend::Bluer, adapter_id, &adapter_name) {
+                        config.report_fatal(e);
+                        break;
                     }
-                    ScanError::Decode(e) => e,
-                    ScanError::BackendNotAvailable(e) => {
-                        DecodeError::InvalidData(format!("Backend not available: {e}"))
+                    drop(scan);
+                    scan = match Scan::start(&adapter, passive).await {
+                        Ok(scan) => scan,
+                        Err(_) => break,
+                    };
+                    continue;
+                }
+            };
+
+            match process_device(&adapter, &decoder, address, &tx, verbose).await {
+                Ok(found) => {
+                    if found && let Some(watchdog) = watchdog.as_mut() {
+                        watchdog.feed();
                     }
-                };
-                let _ = tx.send(Err(err)).await;
+                }
+                Err(e) if verbose => {
+                    let err = match e {
+                        ScanError::Bluetooth(e) => {
+                            DecodeError::InvalidData(format!("Bluetooth error: {e}"))
+                        }
+                        ScanError::Decode(e) => e,
+                        ScanError::BackendNotAvailable(e) => {
+                            DecodeError::InvalidData(format!("Backend not available: {e}"))
+                        }
+                        other => DecodeError::InvalidData(other.to_string()),
+                    };
+                    let _ = tx.send(Err(err)).await;
+                }
+                Err(_) => {}
             }
         }
+
+        // Discovery stops and the monitor is unregistered when the task ends
+        // and drops them. bluetoothd also cleans up after clients that
+        // disconnect from D-Bus, so the adapter does not keep scanning after
+        // the listener exits.
     });
 
     Ok(rx)
 }
 
+/// Check that `params` can be used with BlueZ and return whether to scan
+/// passively.
+///
+/// bluetoothd chooses the timing and addresses of its scans itself, so only
+/// the scan type can be chosen.
+fn scan_mode(params: &ScanParameters) -> Result<bool, ScanError> {
+    let defaults = ScanParameters {
+        scan_type: params.scan_type,
+        ..Default::default()
+    };
+    if *params != defaults {
+        return Err(ScanError::UnsupportedScanParameters(
+            "only the scan type can be set with the BlueZ backend".to_string(),
+        ));
+    }
+    Ok(params.scan_type == Some(ScanType::Passive))
+}
+
+/// A running BlueZ scan, reporting devices that may have sent a new
+/// advertisement.
+enum Scan {
+    Discovery(BoxStream<'static, AdapterEvent>),
+    Monitor(PassiveMonitor),
+}
+
+impl Scan {
+    /// Start a discovery, or register an advertisement monitor if `passive`.
+    async fn start(adapter: &Adapter, passive: bool) -> Result<Self, ScanError> {
+        if passive {
+            return Ok(Scan::Monitor(PassiveMonitor::start(adapter).await?));
+        }
+
+        // Enable `duplicate_data` so BlueZ emits a PropertiesChanged signal for
+        // *every* advertisement, not just the first one or when the payload
+        // changes. Without this, BlueZ deduplicates repeated advertisements and
+        // we receive only a fraction of the broadcasts a tool like
+        // `bluetoothctl` shows. LE-only transport avoids spurious BR/EDR
+        // inquiry traffic.
+        adapter
+            .set_discovery_filter(DiscoveryFilter {
+                transport: DiscoveryTransport::Le,
+                duplicate_data: true,
+                ..Default::default()
+            })
+            .await?;
+
+        // `discover_devices_with_changes` re-emits a `DeviceAdded` event for a
+        // device each time its properties change, giving us one notification
+        // per advertisement. We filter for RuuviTag manufacturer data in
+        // `process_device`.
+        let events = adapter.discover_devices_with_changes().await?;
+        Ok(Scan::Discovery(events.boxed()))
+    }
+
+    /// Wait for the next device to process. Returns `None` once the adapter
+    /// is gone or powered off.
+    async fn next(&mut self) -> Option<Address> {
+        match self {
+            Scan::Discovery(events) => loop {
+                match events.next().await? {
+                    AdapterEvent::DeviceAdded(address) => return Some(address),
+                    AdapterEvent::PropertyChanged(AdapterProperty::Powered(false)) => return None,
+                    _ => {}
+                }
+            },
+            Scan::Monitor(monitor) => monitor.next().await,
+        }
+    }
+}
+
+/// Passive scan using a BlueZ advertisement monitor.
+///
+/// BlueZ reports a device matching the monitor only once, when it comes in
+/// range. Its later advertisements update the device's manufacturer data, so
+/// found devices are followed until BlueZ reports them lost.
+struct PassiveMonitor {
+    adapter: Adapter,
+    adapter_events: BoxStream<'static, AdapterEvent>,
+    // Unregisters the monitor when dropped, before the manager goes away
+    handle: MonitorHandle,
+    _manager: MonitorManager,
+    devices: SelectAll<BoxStream<'static, Address>>,
+    tracked: HashMap<Address, AbortHandle>,
+}
+
+impl PassiveMonitor {
+    /// Register a monitor for RuuviTag advertisements with `adapter`.
+    async fn start(adapter: &Adapter) -> Result<Self, ScanError> {
+        let adapter_events = adapter.events().await?.boxed();
+        let manager = adapter.monitor().await?;
+        let handle = manager.register(ruuvi_monitor()).await.map_err(|e| {
+            ScanError::Bluetooth(format!("Failed to register advertisement monitor: {e}"))
+        })?;
+        Ok(Self {
+            adapter: adapter.clone(),
+            adapter_events,
+            handle,
+            _manager: manager,
+            devices: SelectAll::new(),
+            tracked: HashMap::new(),
+        })
+    }
+
+    async fn next(&mut self) -> Option<Address> {
+        loop {
+            tokio::select! {
+                event = self.adapter_events.next() => {
+                    if let AdapterEvent::PropertyChanged(AdapterProperty::Powered(false)) = event? {
+                        return None;
+                    }
+                }
+                event = self.handle.next() => match event? {
+                    MonitorEvent::DeviceFound(id) => {
+                        // The device is processed right away even if following
+                        // it fails; it is then picked up again when found anew
+                        let _ = self.track(id.device).await;
+                        return Some(id.device);
+                    }
+                    MonitorEvent::DeviceLost(id) => {
+                        if let Some(tracked) = self.tracked.remove(&id.device) {
+                            tracked.abort();
+                        }
+                    }
+                    _ => {}
+                },
+                Some(address) = self.devices.next(), if !self.devices.is_empty() => {
+                    return Some(address);
+                }
+            }
+        }
+    }
+
+    /// Follow the manufacturer and service data changes of the device at
+    /// `address`.
+    async fn track(&mut self, address: Address) -> Result<(), ScanError> {
+        let events = self.adapter.device(address)?.events().await?;
+        let changes = events.filter_map(move |event| {
+            ready(match event {
+                DeviceEvent::PropertyChanged(
+                    DeviceProperty::ManufacturerData(_) | DeviceProperty::ServiceData(_),
+                ) => Some(address),
+                _ => None,
+            })
+        });
+        let (changes, tracked) = abortable(changes);
+        if let Some(previous) = self.tracked.insert(address, tracked) {
+            previous.abort();
+        }
+        self.devices.push(changes.boxed());
+        Ok(())
+    }
+}
+
+/// Advertisement monitor matching advertisements with Ruuvi manufacturer data
+/// or Eddystone service data.
+fn ruuvi_monitor() -> Monitor {
+    Monitor {
+        monitor_type: Type::OrPatterns,
+        patterns: Some(vec![
+            Pattern::new(MANUFACTURER_DATA_TYPE, 0, &RUUVI_MANUFACTURER_ID_BYTES),
+            Pattern::new(SERVICE_DATA_TYPE, 0, &EDDYSTONE_UUID.to_le_bytes()),
+        ]),
+        ..Default::default()
+    }
+}
+
+/// Find the adapter matching `id` among the adapters known to BlueZ.
+///
+/// BlueZ names adapters after their kernel device (`hci0`, `hci1`, ...), so
+/// indices map directly to adapter names.
+async fn find_adapter(session: &Session, id: AdapterId) -> Result<Adapter, ScanError> {
+    let mut available = Vec::new();
+    for name in session.adapter_names().await? {
+        let adapter = session.adapter(&name)?;
+        let address: MacAddress = adapter.address().await?.into();
+        let found = match id {
+            AdapterId::Index(_) => name == id.to_string(),
+            AdapterId::Address(a) => a == address,
+        };
+        if found {
+            return Ok(adapter);
+        }
+        available.push(describe_adapter(&name, address));
+    }
+    Err(ScanError::AdapterNotFound {
+        requested: id.to_string(),
+        available,
+    })
+}
+
 /// Process a discovered Bluetooth device and extract RuuviTag measurements.
 ///
-/// This function attempts to read manufacturer data from the device and decode it
-/// as a RuuviTag measurement. Results are sent through the provided channel.
+/// This function attempts to read manufacturer data, or else an Eddystone URL,
+/// from the device and decode it as a RuuviTag measurement. Results are sent
+/// through the provided channel.
+///
+/// Returns whether the device advertised RuuviTag data.
 async fn process_device(
     adapter: &Adapter,
+    decoder: &FrameDecoder,
     address: Address,
     tx: &mpsc::Sender<MeasurementResult>,
     verbose: bool,
-) -> Result<(), ScanError> {
+) -> Result<bool, ScanError> {
     let device = adapter.device(address)?;
     let mac: MacAddress = address.into();
 
-    // Try to get manufacturer-specific data from the device
-    let manufacturer_data = match device.manufacturer_data().await? {
+    // Try to get RuuviTag manufacturer data from the device
+    let manufacturer_data = device
+        .manufacturer_data()
+        .await?
+        .and_then(|mut data| data.remove(&RUUVI_MANUFACTURER_ID));
+
+    // Older firmware broadcasts an Eddystone URL instead
+    let ruuvi_data = match manufacturer_data {
         Some(data) => data,
-        None => return Ok(()), // No manufacturer data available
+        None => match device.service_data().await? {
+            Some(service_data) => match service_data
+                .get(&Uuid::from_u16(EDDYSTONE_UUID))
+                .and_then(|data| eddystone::ruuvi_data(data))
+            {
+                Some(data) => data,
+                None => return Ok(false), // Not a RuuviTag device
+            },
+            None => return Ok(false), // No manufacturer or service data available
+        },
     };
 
-    // Extract RuuviTag data if present
-    let ruuvi_data = match manufacturer_data.get(&RUUVI_MANUFACTURER_ID) {
-        Some(data) => data,
-        None => return Ok(()), // Not a RuuviTag device
+    // The RSSI takes another D-Bus call, so it is only read for recording
+    let rssi = if decoder.is_recording() {
+        device.rssi().await.ok().flatten()
+    } else {
+        None
     };
 
     // Decode and send the measurement
-    match decode_ruuvi_data(mac, ruuvi_data) {
+    match decoder.decode(mac, rssi, None, &ruuvi_data) {
         Ok(measurement) => {
             let _ = tx.send(Ok(measurement)).await;
         }
@@ -118,7 +384,7 @@ async fn process_device(
         _ => {}
     }
 
-    Ok(())
+    Ok(true)
 }
 
 #[cfg(test)]
@@ -131,4 +397,46 @@ mod tests {
         let mac: MacAddress = addr.into();
         assert_eq!(mac, MacAddress([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]));
     }
+
+    #[test]
+    fn test_scan_mode() {
+        assert!(!scan_mode(&ScanParameters::default()).unwrap());
+
+        let active = ScanParameters {
+            scan_type: Some(ScanType::Active),
+            ..Default::default()
+        };
+        assert!(!scan_mode(&active).unwrap());
+
+        let passive = ScanParameters {
+            scan_type: Some(ScanType::Passive),
+            ..Default::default()
+        };
+        assert!(scan_mode(&passive).unwrap());
+    }
+
+    #[test]
+    fn test_scan_mode_rejects_timing() {
+        let params = ScanParameters {
+            scan_type: Some(ScanType::Passive),
+            interval: std::time::Duration::from_millis(100),
+            ..Default::default()
+        };
+        let err = scan_mode(&params).unwrap_err();
+        assert!(matches!(err, ScanError::UnsupportedScanParameters(_)));
+    }
+
+    #[test]
+    fn test_ruuvi_monitor_matches_manufacturer_id() {
+        let monitor = ruuvi_monitor();
+        assert_eq!(monitor.monitor_type, Type::OrPatterns);
+        let patterns = monitor.patterns.unwrap();
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[0].data_type, 0xff);
+        assert_eq!(patterns[0].start_position, 0);
+        assert_eq!(patterns[0].content, vec![0x99, 0x04]);
+        assert_eq!(patterns[1].data_type, 0x16);
+        assert_eq!(patterns[1].start_position, 0);
+        assert_eq!(patterns[1].content, vec![0xAA, 0xFE]);
+    }
 }
@@ -0,0 +1,120 @@
+//! RuuviTag data formats 2 and 4, broadcast as an Eddystone URL.
+//!
+//! The oldest RuuviTag firmware acts as an Eddystone-URL beacon, with the
+//! measurement encoded in URL-safe base64 after `https://ruu.vi/#`:
+//!
+//! ```text
+//! https://ruu.vi/#AjwYAMFc     data format 2
+//! https://ruu.vi/#BDwYAMFcQ    data format 4, ending in the tag ID
+//! ```
+//!
+//! The URL arrives as Eddystone service data rather than Ruuvi manufacturer
+//! data. Its first eight characters decode to six bytes laid out like data
+//! format 3: the data format, humidity, temperature and pressure. In format 4,
+//! a ninth character holds the upper six bits of a byte that identifies the
+//! tag; the lower two bits do not fit in the URL. [`ruuvi_data`] returns these
+//! bytes, with the ID byte appended, and
+//! [`decode_ruuvi_data`](super::decode_ruuvi_data) decodes them like the other
+//! data formats.
+
+/// 16-bit UUID of the Eddystone service.
+pub const EDDYSTONE_UUID: u16 = 0xFEAA;
+
+/// Frame type of Eddystone-URL frames.
+const URL_FRAME_TYPE: u8 = 0x10;
+
+/// The URL RuuviTags broadcast, after the scheme, up to the measurement.
+const RUUVI_URL: &[u8] = b"ruu.vi/#";
+
+/// Extract the RuuviTag data from Eddystone `service_data`, after the service
+/// UUID.
+///
+/// Returns `None` for other Eddystone frames and URLs.
+pub fn ruuvi_data(service_data: &[u8]) -> Option<Vec<u8>> {
+    // The TX power at 0 m and the URL scheme precede the URL
+    let [URL_FRAME_TYPE, _tx_power, _scheme, url @ ..] = service_data else {
+        return None;
+    };
+    let encoded = url.strip_prefix(RUUVI_URL)?;
+    let (encoded, tag_id) = match encoded {
+        [encoded @ .., tag_id] if encoded.len() == 8 => (encoded, Some(*tag_id)),
+        encoded if encoded.len() == 8 => (encoded, None),
+        _ => return None,
+    };
+
+    let mut data = Vec::with_capacity(7);
+    for group in encoded.chunks(4) {
+        let bits = group
+            .iter()
+            .try_fold(0u32, |bits, &c| Some(bits << 6 | u32::from(sextet(c)?)))?;
+        data.extend(&bits.to_be_bytes()[1..]);
+    }
+    if let Some(tag_id) = tag_id {
+        data.push(sextet(tag_id)? << 2);
+    }
+    Some(data)
+}
+
+/// The value of a base64 character, accepting both the URL-safe and the
+/// standard alphabet.
+fn sextet(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'-' | b'+' => Some(62),
+        b'_' | b'/' => Some(63),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Eddystone-URL service data for `https://ruu.vi/#{fragment}`.
+    fn url_frame(fragment: &str) -> Vec<u8> {
+        let mut frame = vec![URL_FRAME_TYPE, 0xEB, 0x03];
+        frame.extend(RUUVI_URL);
+        frame.extend(fragment.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_ruuvi_data_format_2() {
+        assert_eq!(
+            ruuvi_data(&url_frame("AjwYAMFc")),
+            Some(vec![0x02, 0x3C, 0x18, 0x00, 0xC1, 0x5C])
+        );
+    }
+
+    #[test]
+    fn test_ruuvi_data_format_4_with_tag_id() {
+        assert_eq!(
+            ruuvi_data(&url_frame("BDwYAMFcQ")),
+            Some(vec![0x04, 0x3C, 0x18, 0x00, 0xC1, 0x5C, 0x40])
+        );
+        // Both base64 alphabets are accepted
+        assert_eq!(
+            ruuvi_data(&url_frame("BDwYAM-_/")),
+            ruuvi_data(&url_frame("BDwYAM+/_"))
+        );
+    }
+
+    #[test]
+    fn test_ruuvi_data_ignores_other_frames() {
+        // An Eddystone-UID frame
+        let mut uid = vec![0x00, 0xEB];
+        uid.extend([0; 16]);
+        assert_eq!(ruuvi_data(&uid), None);
+
+        let mut other_url = vec![URL_FRAME_TYPE, 0xEB, 0x03];
+        other_url.extend(b"example.com/#AjwYAMFc");
+        assert_eq!(ruuvi_data(&other_url), None);
+
+        assert_eq!(ruuvi_data(&url_frame("AjwYAM")), None);
+        assert_eq!(ruuvi_data(&url_frame("AjwYAMFc!")), None);
+        assert_eq!(ruuvi_data(&url_frame("AjwYAMFcQQ")), None);
+        assert_eq!(ruuvi_data(&[]), None);
+    }
+}
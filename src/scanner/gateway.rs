@@ -0,0 +1,127 @@
+//! Advertisements relayed by Ruuvi Gateways.
+//!
+//! A Ruuvi Gateway forwards the advertisements it receives as raw hex, together
+//! with the RSSI and the time it received them. The mqtt backend receives them
+//! as they are published (see [`super::mqtt`]); the gateway backend polls the
+//! gateway's HTTP API for them (see [`http`]). Either way, the Ruuvi
+//! data is found and decoded here like the HCI backend does.
+
+#[cfg(feature = "gateway")]
+pub mod http;
+
+use super::{DecodeError, FrameDecoder, MeasurementResult, find_ruuvi_data};
+use crate::capture::from_hex;
+use crate::mac_address::MacAddress;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An integer as written by gateway firmware, which writes some numbers,
+/// such as timestamps, as strings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Integer {
+    Number(u64),
+    Text(String),
+}
+
+impl Integer {
+    pub(crate) fn value(&self) -> Option<u64> {
+        match self {
+            Integer::Number(value) => Some(*value),
+            Integer::Text(value) => value.parse().ok(),
+        }
+    }
+
+    /// The time this integer stands for, in seconds since the Unix epoch.
+    pub(crate) fn to_system_time(&self) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(self.value()?))
+    }
+}
+
+/// Decode the RuuviTag data in `advertisement`, the raw hex of
+/// an advertisement of `mac` relayed by a gateway.
+///
+/// Returns `None` for advertisements of other devices. Invalid hex is reported
+/// as a decode error prefixed with `context`, e.g. the MQTT topic.
+pub(crate) fn decode_advertisement(
+    decoder: &FrameDecoder,
+    mac: MacAddress,
+    rssi: Option<i16>,
+    advertisement: &str,
+    timestamp: Option<SystemTime>,
+    context: &str,
+) -> Option<MeasurementResult> {
+    let Some(advertisement) = from_hex(advertisement) else {
+        return Some(Err(DecodeError::InvalidData(format!(
+            "{context}: invalid hex data {advertisement:?}"
+        ))));
+    };
+    let data = find_ruuvi_data(&advertisement)?;
+    Some(
+        decoder
+            .decode(mac, rssi, None, &data)
+            .map(|mut measurement| {
+                if let Some(timestamp) = timestamp {
+                    measurement.timestamp = timestamp;
+                }
+                measurement
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::Format;
+    use crate::test_utils::TEST_MAC;
+
+    pub(super) const ADVERTISEMENT: &str =
+        "0201061BFF99040512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F";
+
+    #[test]
+    fn test_integer() {
+        let number: Integer = serde_json::from_str("1700000000").unwrap();
+        let text: Integer = serde_json::from_str(r#""1700000000""#).unwrap();
+        let invalid: Integer = serde_json::from_str(r#""soon""#).unwrap();
+        assert_eq!(number.value(), Some(1_700_000_000));
+        assert_eq!(
+            text.to_system_time(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(invalid.value(), None);
+    }
+
+    #[test]
+    fn test_decode_advertisement() {
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let measurement = decode_advertisement(
+            &FrameDecoder::default(),
+            TEST_MAC,
+            Some(-62),
+            ADVERTISEMENT,
+            Some(timestamp),
+            "test",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(measurement.mac, TEST_MAC);
+        assert_eq!(measurement.format, Format::V5);
+        assert_eq!(measurement.timestamp, timestamp);
+    }
+
+    #[test]
+    fn test_decode_other_advertisements() {
+        let decoder = FrameDecoder::default();
+        // An iBeacon, without Ruuvi manufacturer data
+        let ibeacon = "0201061AFF4C000215000000000000000000000000000000000000000000C5";
+        assert!(decode_advertisement(&decoder, TEST_MAC, None, ibeacon, None, "test").is_none());
+
+        let err = decode_advertisement(&decoder, TEST_MAC, None, "0201zz", None, "test")
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid data: test: invalid hex data "0201zz""#
+        );
+    }
+}
@@ -0,0 +1,384 @@
+//! Polling of the Ruuvi Gateway HTTP API.
+//!
+//! A Ruuvi Gateway configured for local access serves the latest advertisement
+//! of every tag it hears at `/history`:
+//!
+//! ```text
+//! {"data":{"coordinates":"","timestamp":"1700000005","gw_mac":"C8:25:2D:8E:9C:2C","tags":{
+//!   "D4:E9:1A:3F:D8:C7":{"rssi":-62,"timestamp":"1700000000","data":"0201061BFF9904...","counter":"1234"}}}}
+//! ```
+//!
+//! The gateway backend fetches it periodically and decodes the advertisements.
+//! As the same advertisement is served until the tag is heard again, a tag is
+//! reported again only once its `timestamp` or `counter` has changed. If a poll
+//! fails, a `gateway_poll_failed` event is written to stderr and the next poll
+//! is tried as usual:
+//!
+//! ```text
+//! event=gateway_poll_failed url=http://10.0.0.5/history error="http://10.0.0.5/history: status code 401"
+//! ```
+
+use super::{Integer, decode_advertisement};
+use crate::event::{self, Event};
+use crate::mac_address::MacAddress;
+use crate::scanner::{
+    DecodeError, FrameDecoder, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, ScanConfig,
+    ScanError,
+};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// How long a single request to the gateway may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where and how often to poll a gateway.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GatewayConfig {
+    /// Base URL of the gateway, e.g. `http://10.0.0.5`
+    pub url: String,
+    /// Bearer token for gateways that require authentication
+    pub token: Option<String>,
+    /// Time between polls
+    pub interval: Duration,
+}
+
+impl GatewayConfig {
+    fn history_url(&self) -> String {
+        format!("{}/history", self.url.trim_end_matches('/'))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct History {
+    data: HistoryData,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryData {
+    #[serde(default)]
+    gw_mac: Option<String>,
+    /// Latest advertisement of each tag, by address
+    #[serde(default)]
+    tags: BTreeMap<String, HistoryTag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryTag {
+    /// Raw advertisement as hex
+    data: String,
+    #[serde(default)]
+    rssi: Option<i16>,
+    /// When the gateway received the advertisement, in seconds since the
+    /// Unix epoch
+    #[serde(default)]
+    timestamp: Option<Integer>,
+    /// Number of advertisements the gateway has received from the tag
+    #[serde(default)]
+    counter: Option<Integer>,
+}
+
+/// Identifies the advertisement a tag was last reported with.
+type Seen = (Option<u64>, Option<u64>);
+
+/// Start polling the gateway in `gateway`.
+///
+/// Fails if the first poll fails, e.g. because the URL or token is wrong.
+/// Later failures are reported on stderr and polling continues until the
+/// returned receiver is dropped.
+pub async fn start_polling(
+    gateway: &GatewayConfig,
+    config: &ScanConfig,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let url = gateway.history_url();
+    let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+    let token = gateway.token.clone();
+    let history = fetch(&agent, &url, token.as_deref())
+        .await
+        .map_err(ScanError::Gateway)?;
+
+    let period = gateway.interval;
+    let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    config.spawn(|config| async move {
+        let mut seen = HashMap::new();
+        let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut history = Some(history);
+        'polls: loop {
+            if let Some(history) = history.take() {
+                for result in decode_history(history, &mut seen, &config) {
+                    if (result.is_ok() || config.verbose) && tx.send(result).await.is_err() {
+                        break 'polls;
+                    }
+                }
+            }
+
+            tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                _ = interval.tick() => {}
+            }
+            // A request can take longer than shutdown is willing to wait
+            let fetched = tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                fetched = fetch(&agent, &url, token.as_deref()) => fetched,
+            };
+            match fetched {
+                Ok(fetched) => history = Some(fetched),
+                Err(error) => event::report(&Event::GatewayPollFailed {
+                    url: url.clone(),
+                    error,
+                }),
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Fetch `url` on a blocking thread, as the HTTP client blocks.
+async fn fetch(agent: &ureq::Agent, url: &str, token: Option<&str>) -> Result<History, String> {
+    let (agent, url, token) = (agent.clone(), url.to_string(), token.map(str::to_string));
+    tokio::task::spawn_blocking(move || {
+        let mut request = agent.get(&url);
+        if let Some(token) = token {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        let response = request.call().map_err(|e| e.to_string())?;
+        serde_json::from_reader(response.into_reader()).map_err(|e| format!("{url}: {e}"))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Decode the advertisements in `history` that have not been reported yet.
+fn decode_history(
+    history: History,
+    seen: &mut HashMap<MacAddress, Seen>,
+    config: &ScanConfig,
+) -> Vec<MeasurementResult> {
+    let decoder = FrameDecoder::new(
+        history.data.gw_mac.as_deref().map(Arc::from),
+        config.recorder.clone(),
+    );
+    let mut results = Vec::new();
+    for (mac, tag) in &history.data.tags {
+        let mac: MacAddress = match mac.parse() {
+            Ok(mac) => mac,
+            Err(e) => {
+                results.push(Err(DecodeError::InvalidData(format!("{mac:?}: {e}"))));
+                continue;
+            }
+        };
+        let current = (
+            tag.timestamp.as_ref().and_then(Integer::value),
+            tag.counter.as_ref().and_then(Integer::value),
+        );
+        // Without either, a repeated advertisement cannot be told from a new one
+        if current != (None, None) && seen.insert(mac, current) == Some(current) {
+            continue;
+        }
+        let timestamp = tag.timestamp.as_ref().and_then(Integer::to_system_time);
+        let context = mac.to_string();
+        results.extend(decode_advertisement(
+            &decoder, mac, tag.rssi, &tag.data, timestamp, &context,
+        ));
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::ADVERTISEMENT;
+    use super::*;
+    use crate::test_utils::TEST_MAC;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::time::UNIX_EPOCH;
+
+    fn history(timestamp: u64, counter: u64) -> String {
+        format!(
+            r#"{{"data":{{"coordinates":"","timestamp":"{timestamp}","gw_mac":"C8:25:2D:8E:9C:2C","tags":{{"AA:BB:CC:DD:EE:FF":{{"rssi":-62,"timestamp":"{timestamp}","data":"{ADVERTISEMENT}","counter":"{counter}"}}}}}}}}"#
+        )
+    }
+
+    /// Serve `responses` as status code and body, one per connection, and
+    /// return the request line and headers of each request.
+    fn stand_in(responses: Vec<(u16, String)>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                requests.push(request);
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    fn gateway(url: String, token: Option<&str>) -> GatewayConfig {
+        GatewayConfig {
+            url,
+            token: token.map(str::to_string),
+            interval: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn test_decode_history_skips_seen_advertisements() {
+        let config = ScanConfig::default();
+        let mut seen = HashMap::new();
+        let parse = |body: String| serde_json::from_str::<History>(&body).unwrap();
+
+        let results = decode_history(parse(history(1_700_000_000, 1)), &mut seen, &config);
+        assert_eq!(results.len(), 1);
+        let measurement = results[0].as_ref().unwrap();
+        assert_eq!(measurement.mac, TEST_MAC);
+        assert_eq!(measurement.adapter.as_deref(), Some("C8:25:2D:8E:9C:2C"));
+        assert_eq!(
+            measurement.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+
+        assert!(decode_history(parse(history(1_700_000_000, 1)), &mut seen, &config).is_empty());
+        // The same data received again is counted as a new advertisement
+        assert_eq!(
+            decode_history(parse(history(1_700_000_000, 2)), &mut seen, &config).len(),
+            1
+        );
+        assert_eq!(
+            decode_history(parse(history(1_700_000_010, 2)), &mut seen, &config).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_decode_history_keeps_advertisements_without_timestamp_or_counter() {
+        let body = format!(
+            r#"{{"data":{{"tags":{{"AA:BB:CC:DD:EE:FF":{{"data":"{ADVERTISEMENT}"}}}}}}}}"#
+        );
+        let config = ScanConfig::default();
+        let mut seen = HashMap::new();
+        for _ in 0..2 {
+            let history = serde_json::from_str::<History>(&body).unwrap();
+            assert_eq!(decode_history(history, &mut seen, &config).len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_decode_history_reports_invalid_addresses() {
+        let body = format!(r#"{{"data":{{"tags":{{"gateway":{{"data":"{ADVERTISEMENT}"}}}}}}}}"#);
+        let history = serde_json::from_str::<History>(&body).unwrap();
+        let results = decode_history(history, &mut HashMap::new(), &ScanConfig::default());
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_polls_gateway_with_token() {
+        let (url, server) = stand_in(vec![
+            (200, history(1_700_000_000, 1)),
+            (200, history(1_700_000_000, 1)),
+            (200, history(1_700_000_005, 2)),
+        ]);
+        let mut rx = start_polling(&gateway(url, Some("secret")), &ScanConfig::default())
+            .await
+            .unwrap();
+
+        let first = rx.recv().await.unwrap().unwrap();
+        let second = rx.recv().await.unwrap().unwrap();
+        assert_eq!(
+            second.timestamp.duration_since(first.timestamp).unwrap(),
+            Duration::from_secs(5)
+        );
+        drop(rx);
+
+        let requests = tokio::task::spawn_blocking(move || server.join().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(requests.len(), 3);
+        for request in requests {
+            assert!(request.starts_with("GET /history HTTP/1.1\r\n"));
+            assert!(request.contains("Authorization: Bearer secret\r\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pending_poll_is_abandoned_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let server = std::thread::spawn(move || {
+            let body = history(1_700_000_000, 1);
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            // Never answer the second poll
+            let _stalled = listener.accept().unwrap();
+            let _ = release_rx.recv();
+        });
+        let config = ScanConfig::default();
+        let mut rx = start_polling(&gateway(url, None), &config).await.unwrap();
+
+        rx.recv().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(rx);
+
+        let joined = tokio::time::timeout(Duration::from_secs(2), config.tasks.join()).await;
+        release_tx.send(()).unwrap();
+        tokio::task::spawn_blocking(move || server.join().unwrap())
+            .await
+            .unwrap();
+        assert!(joined.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_first_poll_failure_fails_to_start() {
+        let (url, server) = stand_in(vec![(401, "{}".to_string())]);
+        let err = start_polling(&gateway(format!("{url}/"), None), &ScanConfig::default())
+            .await
+            .unwrap_err();
+        let requests = tokio::task::spawn_blocking(move || server.join().unwrap())
+            .await
+            .unwrap();
+
+        assert!(matches!(err, ScanError::Gateway(_)));
+        assert!(
+            err.to_string().ends_with("/history: status code 401"),
+            "{err}"
+        );
+        assert!(!requests[0].contains("Authorization"));
+    }
+}
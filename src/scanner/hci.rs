@@ -4,18 +4,31 @@
 //! without requiring the BlueZ daemon. It requires CAP_NET_RAW and
 //! CAP_NET_ADMIN capabilities or root privileges.
 
+pub mod dump;
+mod reassembly;
+
+use super::eddystone::EDDYSTONE_UUID;
+use super::params::{
+    CodedPhy, MAX_EXTENDED_SLOTS, MAX_LEGACY_SLOTS, OwnAddressType, ScanParameters, ScanType,
+};
+use super::watchdog::{Watchdog, watch};
 use super::{
-    DecodeError, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, RUUVI_MANUFACTURER_ID,
-    ScanError, decode_ruuvi_data,
+    AdapterId, Backend, DecodeError, FrameDecoder, MEASUREMENT_CHANNEL_BUFFER_SIZE,
+    MeasurementResult, RUUVI_MANUFACTURER_ID, ScanConfig, ScanError, describe_adapter,
+    find_ruuvi_data,
 };
 use crate::mac_address::MacAddress;
+use crate::measurement::Phy;
 use libc::{
     AF_BLUETOOTH, SO_ATTACH_FILTER, SOCK_CLOEXEC, SOCK_RAW, SOL_SOCKET, c_int, c_void, sockaddr,
     socklen_t,
 };
+use reassembly::{DataStatus, FRAGMENT_TIMEOUT, FragmentKey, Reassembler};
 use std::io;
 use std::mem;
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use tokio::io::unix::AsyncFd;
 use tokio::sync::mpsc;
 
@@ -23,6 +36,11 @@ use tokio::sync::mpsc;
 const BTPROTO_HCI: c_int = 1;
 const HCI_FILTER: c_int = 2;
 
+// HCI device ioctls: _IOR('H', 210, int) and _IOR('H', 211, int)
+const HCIGETDEVLIST: libc::c_ulong = 0x800448D2;
+const HCIGETDEVINFO: libc::c_ulong = 0x800448D3;
+const HCI_MAX_DEV: usize = 16;
+
 // HCI packet types
 const HCI_EVENT_PKT: u8 = 0x04;
 
@@ -34,6 +52,9 @@ const EVT_CMD_COMPLETE: u8 = 0x0E;
 const EVT_LE_ADVERTISING_REPORT: u8 = 0x02;
 const EVT_LE_EXTENDED_ADVERTISING_REPORT: u8 = 0x0D;
 
+// Extended advertising report event type bit 4: the report is of a legacy PDU
+const EXT_ADV_EVENT_TYPE_LEGACY: u8 = 1 << 4;
+
 // HCI commands
 const OGF_LE_CTL: u16 = 0x08;
 const OCF_LE_READ_LOCAL_SUPPORTED_FEATURES: u16 = 0x0003;
@@ -43,28 +64,34 @@ const OCF_LE_SET_EXTENDED_SCAN_PARAMETERS: u16 = 0x0041;
 const OCF_LE_SET_EXTENDED_SCAN_ENABLE: u16 = 0x0042;
 
 // LE feature bits (from LE Read Local Supported Features)
+// Bit 6 (byte 0, bit 6) = LL Privacy
+const LE_FEATURE_LL_PRIVACY_BYTE: usize = 0;
+const LE_FEATURE_LL_PRIVACY_BIT: u8 = 1 << 6;
+// Bit 11 (byte 1, bit 3) = LE Coded PHY
+const LE_FEATURE_CODED_PHY_BYTE: usize = 1;
+const LE_FEATURE_CODED_PHY_BIT: u8 = 1 << 3;
 // Bit 12 (byte 1, bit 4) = LE Extended Advertising
 const LE_FEATURE_EXTENDED_ADVERTISING_BYTE: usize = 1;
 const LE_FEATURE_EXTENDED_ADVERTISING_BIT: u8 = 1 << 4;
 
-// Scanning PHYs bitmask for extended scan (bit 0 = LE 1M PHY)
+// Scanning PHYs bitmask for extended scan (bit 0 = LE 1M, bit 2 = LE Coded)
 const LE_1M_PHY: u8 = 0x01;
+const LE_CODED_PHY: u8 = 0x04;
+
+// Status of an LE Set (Extended) Scan Enable command that asks for active
+// scanning from a random address before one has been set
+const HCI_INVALID_COMMAND_PARAMETERS: u8 = 0x12;
 
 // How long to wait for an HCI command's Command Complete event
 const COMMAND_TIMEOUT_MS: u64 = 1000;
 
 // Scan types
 const LE_SCAN_PASSIVE: u8 = 0x00;
-
-// Own address type
-const LE_PUBLIC_ADDRESS: u8 = 0x00;
+const LE_SCAN_ACTIVE: u8 = 0x01;
 
 // Filter policy
 const FILTER_POLICY_ACCEPT_ALL: u8 = 0x00;
 
-// AD types
-const AD_TYPE_MANUFACTURER_DATA: u8 = 0xFF;
-
 // Ruuvi manufacturer ID as little-endian bytes for quick matching
 const RUUVI_MANUFACTURER_ID_LE: [u8; 2] = [
     (RUUVI_MANUFACTURER_ID & 0xFF) as u8,
@@ -79,6 +106,7 @@ const BPF_H: u16 = 0x08; // Half-word (16-bit)
 const BPF_B: u16 = 0x10; // Byte
 const BPF_ABS: u16 = 0x20;
 const BPF_JEQ: u16 = 0x10;
+const BPF_JSET: u16 = 0x40;
 const BPF_K: u16 = 0x00;
 
 /// BPF instruction structure (classic BPF, not eBPF)
@@ -133,6 +161,41 @@ impl HciFilter {
     }
 }
 
+/// Entry of the HCIGETDEVLIST ioctl result (`struct hci_dev_req`)
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct HciDevReq {
+    dev_id: u16,
+    dev_opt: u32,
+}
+
+/// HCIGETDEVLIST ioctl argument (`struct hci_dev_list_req`) with room for
+/// `HCI_MAX_DEV` devices
+#[repr(C)]
+struct HciDevListReq {
+    dev_num: u16,
+    dev_req: [HciDevReq; HCI_MAX_DEV],
+}
+
+/// HCIGETDEVINFO ioctl result (`struct hci_dev_info`)
+#[repr(C)]
+struct HciDevInfo {
+    dev_id: u16,
+    name: [u8; 8],
+    bdaddr: [u8; 6], // Little-endian
+    flags: u32,
+    dev_type: u8,
+    features: [u8; 8],
+    pkt_type: u32,
+    link_policy: u32,
+    link_mode: u32,
+    acl_mtu: u16,
+    acl_pkts: u16,
+    sco_mtu: u16,
+    sco_pkts: u16,
+    stat: [u32; 10],
+}
+
 /// LE Set Scan Parameters command
 #[repr(C, packed)]
 struct LeSetScanParametersCmd {
@@ -150,16 +213,20 @@ struct LeSetScanEnableCmd {
     filter_dup: u8,
 }
 
-/// LE Set Extended Scan Parameters command (Bluetooth 5.x).
+/// Fixed part of the LE Set Extended Scan Parameters command (Bluetooth 5.x).
 ///
-/// This variant carries one parameter block per scanning PHY. We only ever
-/// scan on the LE 1M PHY (`scanning_phys == LE_1M_PHY`), so exactly one
-/// `{scan_type, interval, window}` block follows the PHY bitmask.
+/// One [`LeExtendedScanPhyParams`] block follows for each bit set in
+/// `scanning_phys`.
 #[repr(C, packed)]
 struct LeSetExtendedScanParametersCmd {
     own_address_type: u8,
     filter_policy: u8,
     scanning_phys: u8,
+}
+
+/// Per-PHY part of the LE Set Extended Scan Parameters command.
+#[repr(C, packed)]
+struct LeExtendedScanPhyParams {
     scan_type: u8,
     interval: u16,
     window: u16,
@@ -235,6 +302,65 @@ fn bind_hci_socket(fd: &OwnedFd, dev_id: u16) -> Result<(), ScanError> {
     Ok(())
 }
 
+/// List the HCI devices known to the kernel as `(index, name, address)`.
+fn list_hci_devices(fd: &OwnedFd) -> Result<Vec<(u16, String, MacAddress)>, ScanError> {
+    let mut list = HciDevListReq {
+        dev_num: HCI_MAX_DEV as u16,
+        dev_req: [HciDevReq::default(); HCI_MAX_DEV],
+    };
+    let ret = unsafe { libc::ioctl(fd.as_raw_fd(), HCIGETDEVLIST, &mut list) };
+    if ret < 0 {
+        return Err(ScanError::Bluetooth(format!(
+            "Failed to list HCI devices: {}",
+            io::Error::last_os_error()
+        )));
+    }
+
+    let count = usize::from(list.dev_num).min(HCI_MAX_DEV);
+    let mut devices = Vec::with_capacity(count);
+    for req in &list.dev_req[..count] {
+        // Safety: HciDevInfo is plain old data, so all-zero bytes are a valid value
+        let mut info: HciDevInfo = unsafe { mem::zeroed() };
+        info.dev_id = req.dev_id;
+        let ret = unsafe { libc::ioctl(fd.as_raw_fd(), HCIGETDEVINFO, &mut info) };
+        if ret < 0 {
+            // The device may have been removed since listing
+            continue;
+        }
+        let name_len = info.name.iter().position(|&b| b == 0).unwrap_or(8);
+        let name = String::from_utf8_lossy(&info.name[..name_len]).into_owned();
+        let mut address = info.bdaddr;
+        address.reverse();
+        devices.push((info.dev_id, name, MacAddress(address)));
+    }
+    Ok(devices)
+}
+
+/// Resolve the device index to bind to.
+///
+/// Without an explicit adapter this is hci0, as before adapter selection
+/// existed. Otherwise the adapter must be present in the kernel's device list.
+fn resolve_hci_device(fd: &OwnedFd, adapter: Option<AdapterId>) -> Result<u16, ScanError> {
+    let Some(id) = adapter else {
+        return Ok(0);
+    };
+    let devices = list_hci_devices(fd)?;
+    devices
+        .iter()
+        .find(|(index, _, address)| match id {
+            AdapterId::Index(i) => *index == i,
+            AdapterId::Address(a) => *address == a,
+        })
+        .map(|(index, _, _)| *index)
+        .ok_or_else(|| ScanError::AdapterNotFound {
+            requested: id.to_string(),
+            available: devices
+                .iter()
+                .map(|(_, name, address)| describe_adapter(name, *address))
+                .collect(),
+        })
+}
+
 /// Set HCI socket filter for kernel-level packet filtering.
 ///
 /// This is the first layer of kernel-level filtering. It configures the HCI
@@ -300,11 +426,7 @@ fn apply_hci_filter(fd: &OwnedFd, filter: &HciFilter) -> Result<(), ScanError> {
 /// - Non-advertising LE Meta Events (connection complete, etc.)
 /// - Advertisements from non-Ruuvi devices (Tile trackers, smartwatches, etc.)
 ///
-/// The filter checks:
-/// 1. Packet type is HCI_EVENT_PKT (0x04)
-/// 2. Event code is EVT_LE_META_EVENT (0x3E)
-/// 3. Subevent is EVT_LE_ADVERTISING_REPORT (0x02)
-/// 4. Packet contains Ruuvi manufacturer ID (0x9904) at common positions
+/// See [`ruuvi_bpf_program`] for the checks.
 ///
 /// Combined filtering layers:
 /// ```text
@@ -314,14 +436,55 @@ fn apply_hci_filter(fd: &OwnedFd, filter: &HciFilter) -> Result<(), ScanError> {
 ///           └─[Application]─► Parse and decode
 /// ```
 fn set_bpf_ruuvi_filter(fd: &OwnedFd) -> Result<(), ScanError> {
+    let filter = ruuvi_bpf_program();
+    let prog = SockFprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr(),
+    };
+
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            SOL_SOCKET,
+            SO_ATTACH_FILTER,
+            &prog as *const SockFprog as *const c_void,
+            mem::size_of::<SockFprog>() as socklen_t,
+        )
+    };
+
+    if ret < 0 {
+        return Err(ScanError::Bluetooth(format!(
+            "Failed to set BPF filter: {}",
+            io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Build the BPF program accepting advertising reports with Ruuvi data.
+///
+/// The program accepts a packet if:
+/// 1. Packet type is HCI_EVENT_PKT (0x04)
+/// 2. Event code is EVT_LE_META_EVENT (0x3E)
+/// 3. Subevent is EVT_LE_ADVERTISING_REPORT (0x02) or
+///    EVT_LE_EXTENDED_ADVERTISING_REPORT (0x0D)
+/// 4. Packet contains Ruuvi manufacturer ID (0x9904) or, for the Eddystone
+///    URLs of data formats 2 and 4, the Eddystone service UUID (0xAAFE)
+///    anywhere after the first report header, so reports batched after other
+///    advertisers are kept
+///
+/// Extended reports of non-legacy PDUs, and extended events with several
+/// reports, skip the last check: their data may be a fragment without the
+/// manufacturer ID (see `reassembly`).
+fn ruuvi_bpf_program() -> Vec<SockFilter> {
     // Ruuvi manufacturer ID as big-endian 16-bit value for BPF comparison
     // BPF loads 16-bit values in network byte order (big-endian)
     const RUUVI_ID_BE: u32 = 0x9904;
+    // Eddystone service UUID, likewise: service data (type 0x16) is
+    // [length][0x16][uuid_lo][uuid_hi][data...]
+    const EDDYSTONE_UUID_BE: u32 = 0xAAFE;
 
-    // Build BPF program that checks for Ruuvi manufacturer ID
-    // Classic BPF doesn't support loops, so we check multiple fixed offsets
-    // where manufacturer data typically appears in advertising reports.
-    //
     // HCI LE Advertising Report structure:
     // [0]: Packet type (0x04)
     // [1]: Event code (0x3E)
@@ -332,150 +495,100 @@ fn set_bpf_ruuvi_filter(fd: &OwnedFd) -> Result<(), ScanError> {
     // [6]: Address type
     // [7-12]: Address (6 bytes)
     // [13]: Data length
-    // [14+]: Advertising data (AD structures)
+    // [14+]: Advertising data (AD structures), then further reports
     //
     // AD structure: [length][type][data...]
     // Manufacturer data (type 0xFF): [length][0xFF][mfg_id_lo][mfg_id_hi][data...]
-
+    //
     // Manufacturer data starts at offset 14 in a legacy report but at offset 29
-    // in an extended report (the per-report header is larger). We scan a single
-    // wide range that covers both layouts.
+    // in an extended report (the per-report header is larger). Classic BPF
+    // doesn't support loops, so every offset up to the largest HCI event is
+    // checked. Loads past the end of the packet make the kernel drop it, but
+    // offsets are checked in ascending order, so an ID in the packet is always
+    // found first.
     const FIRST_OFFSET: u32 = 14;
-    const LAST_OFFSET: u32 = 60;
-    let num_offsets = (LAST_OFFSET - FIRST_OFFSET + 1) as usize;
-
-    let mut filter = Vec::with_capacity(num_offsets * 2 + 16);
-
-    // [0,1] Check packet type == HCI_EVENT_PKT (0x04)
-    filter.push(SockFilter {
-        code: BPF_LD | BPF_B | BPF_ABS,
-        jt: 0,
-        jf: 0,
-        k: 0,
-    });
-    filter.push(SockFilter {
-        code: BPF_JMP | BPF_JEQ | BPF_K,
-        jt: 0,
-        jf: 0, // Will be patched to jump to reject
-        k: HCI_EVENT_PKT as u32,
-    });
-
-    // [2,3] Check event code == EVT_LE_META_EVENT (0x3E)
-    filter.push(SockFilter {
-        code: BPF_LD | BPF_B | BPF_ABS,
-        jt: 0,
-        jf: 0,
-        k: 1,
-    });
-    filter.push(SockFilter {
-        code: BPF_JMP | BPF_JEQ | BPF_K,
-        jt: 0,
-        jf: 0, // Will be patched
-        k: EVT_LE_META_EVENT as u32,
-    });
-
-    // [4] Load subevent code, then accept either the legacy or the extended
-    // advertising report subevent.
-    filter.push(SockFilter {
-        code: BPF_LD | BPF_B | BPF_ABS,
-        jt: 0,
-        jf: 0,
-        k: 3,
-    });
-    // [5] subevent == EVT_LE_ADVERTISING_REPORT (0x02): jump to mfg-id checks
-    filter.push(SockFilter {
-        code: BPF_JMP | BPF_JEQ | BPF_K,
-        jt: 0, // Will be patched to jump to checks_start
-        jf: 0, // Fall through to the extended check below
-        k: EVT_LE_ADVERTISING_REPORT as u32,
-    });
-    // [6] subevent == EVT_LE_EXTENDED_ADVERTISING_REPORT (0x0D): otherwise reject
-    filter.push(SockFilter {
-        code: BPF_JMP | BPF_JEQ | BPF_K,
-        jt: 0, // Will be patched to jump to checks_start
-        jf: 0, // Will be patched to jump to reject
-        k: EVT_LE_EXTENDED_ADVERTISING_REPORT as u32,
-    });
-
-    let checks_start = filter.len();
-
-    // Check for the Ruuvi manufacturer ID at each candidate offset.
-    for offset in FIRST_OFFSET..=LAST_OFFSET {
-        // Load 16-bit value at this offset
-        filter.push(SockFilter {
-            code: BPF_LD | BPF_H | BPF_ABS,
-            jt: 0,
-            jf: 0,
-            k: offset,
-        });
-        // Jump to accept if it matches Ruuvi ID
-        filter.push(SockFilter {
-            code: BPF_JMP | BPF_JEQ | BPF_K,
-            jt: 0, // Will be patched to jump to accept
-            jf: 0, // Continue to next check
-            k: RUUVI_ID_BE,
-        });
-    }
-
-    // Reject: return 0 (drop packet)
-    let reject_idx = filter.len();
-    filter.push(SockFilter {
-        code: BPF_RET | BPF_K,
+    const LAST_OFFSET: u32 = 256; // 2-byte load in a 258-byte event
+
+    // Jump offsets are relative to the instruction after the jump and at most
+    // 255, so the shared reject and accept instructions are placed right after
+    // the header checks, and each offset's ID checks bring their own accept.
+    const REJECT: usize = 11;
+    const ACCEPT: usize = 12;
+    const CHECKS_START: usize = 13;
+    let jump_to = |from: usize, target: usize| (target - from - 1) as u8;
+
+    let ld = |size: u16, k: u32| SockFilter {
+        code: BPF_LD | size | BPF_ABS,
         jt: 0,
         jf: 0,
-        k: 0,
-    });
-
-    // Accept: return max packet size
-    let accept_idx = filter.len();
-    filter.push(SockFilter {
+        k,
+    };
+    let jmp = |op: u16, k: u32, jt: u8, jf: u8| SockFilter {
+        code: BPF_JMP | op | BPF_K,
+        jt,
+        jf,
+        k,
+    };
+    let ret = |k: u32| SockFilter {
         code: BPF_RET | BPF_K,
         jt: 0,
         jf: 0,
-        k: 0xFFFF,
-    });
-
-    // Patch jump targets. A BPF jump offset is relative to the instruction
-    // *after* the jump, so the offset to reach `target` from index `i` is
-    // `target - i - 1`.
-    // Packet-type and event-code checks reject on mismatch.
-    filter[1].jf = (reject_idx - 1 - 1) as u8;
-    filter[3].jf = (reject_idx - 3 - 1) as u8;
-    // Subevent dispatch: both report types branch to the mfg-id checks; a
-    // non-advertising LE Meta Event is rejected.
-    filter[5].jt = (checks_start - 5 - 1) as u8;
-    filter[6].jt = (checks_start - 6 - 1) as u8;
-    filter[6].jf = (reject_idx - 6 - 1) as u8;
-
-    // Manufacturer ID checks jump to accept on success
-    for i in 0..num_offsets {
-        let check_idx = checks_start + i * 2 + 1; // The JEQ instruction
-        filter[check_idx].jt = (accept_idx - check_idx - 1) as u8;
-    }
-
-    let prog = SockFprog {
-        len: filter.len() as u16,
-        filter: filter.as_ptr(),
-    };
-
-    let ret = unsafe {
-        libc::setsockopt(
-            fd.as_raw_fd(),
-            SOL_SOCKET,
-            SO_ATTACH_FILTER,
-            &prog as *const SockFprog as *const c_void,
-            mem::size_of::<SockFprog>() as socklen_t,
-        )
+        k,
     };
 
-    if ret < 0 {
-        return Err(ScanError::Bluetooth(format!(
-            "Failed to set BPF filter: {}",
-            io::Error::last_os_error()
-        )));
+    let mut filter = vec![
+        // [0,1] Packet type == HCI_EVENT_PKT (0x04), otherwise reject
+        ld(BPF_B, 0),
+        jmp(BPF_JEQ, HCI_EVENT_PKT as u32, 0, jump_to(1, REJECT)),
+        // [2,3] Event code == EVT_LE_META_EVENT (0x3E), otherwise reject
+        ld(BPF_B, 1),
+        jmp(BPF_JEQ, EVT_LE_META_EVENT as u32, 0, jump_to(3, REJECT)),
+        // [4,5] Subevent == EVT_LE_ADVERTISING_REPORT (0x02): mfg-id checks
+        ld(BPF_B, 3),
+        jmp(
+            BPF_JEQ,
+            EVT_LE_ADVERTISING_REPORT as u32,
+            jump_to(5, CHECKS_START),
+            0,
+        ),
+        // [6] Subevent == EVT_LE_EXTENDED_ADVERTISING_REPORT (0x0D), otherwise reject
+        jmp(
+            BPF_JEQ,
+            EVT_LE_EXTENDED_ADVERTISING_REPORT as u32,
+            0,
+            jump_to(6, REJECT),
+        ),
+        // [7,8] Extended: num_reports == 1, otherwise accept
+        ld(BPF_B, 4),
+        jmp(BPF_JEQ, 1, 0, jump_to(8, ACCEPT)),
+        // [9,10] Extended: legacy PDU bit of the event type set, otherwise accept
+        ld(BPF_B, 5),
+        jmp(
+            BPF_JSET,
+            EXT_ADV_EVENT_TYPE_LEGACY as u32,
+            jump_to(10, CHECKS_START),
+            jump_to(10, ACCEPT),
+        ),
+        // [11] Reject: return 0 (drop packet)
+        ret(0),
+        // [12] Accept: return max packet size
+        ret(0xFFFF),
+    ];
+    debug_assert_eq!(filter.len(), CHECKS_START);
+
+    // Check for the Ruuvi manufacturer ID and the Eddystone UUID at each offset:
+    // load the 16-bit value, accept if either matches, otherwise skip the accept
+    // and go on.
+    for offset in FIRST_OFFSET..=LAST_OFFSET {
+        filter.push(ld(BPF_H, offset));
+        filter.push(jmp(BPF_JEQ, RUUVI_ID_BE, 1, 0));
+        filter.push(jmp(BPF_JEQ, EDDYSTONE_UUID_BE, 0, 1));
+        filter.push(ret(0xFFFF));
     }
 
-    Ok(())
+    // No manufacturer ID or Eddystone UUID found
+    filter.push(ret(0));
+    filter
 }
 
 /// Send an HCI command
@@ -566,91 +679,220 @@ fn send_hci_command_checked(
     ocf: u16,
     params: &[u8],
 ) -> Result<Vec<u8>, ScanError> {
+    let (status, event) = send_hci_command_status(fd, ogf, ocf, params)?;
+    if status != 0 {
+        return Err(command_failed(ogf, ocf, status));
+    }
+
+    Ok(event)
+}
+
+/// Send an HCI command and return its Command Complete status along with the
+/// full event, leaving it to the caller to handle a non-zero status.
+fn send_hci_command_status(
+    fd: &OwnedFd,
+    ogf: u16,
+    ocf: u16,
+    params: &[u8],
+) -> Result<(u8, Vec<u8>), ScanError> {
     let packet = hci_command_packet(ogf, ocf, params);
     send_hci_command(fd, &packet)?;
 
-    let opcode = (ogf << 10) | ocf;
-    let event = read_command_complete(fd, opcode)?;
+    let event = read_command_complete(fd, (ogf << 10) | ocf)?;
 
     // Status is the first return parameter, at byte 6.
     let status = *event
         .get(6)
         .ok_or_else(|| ScanError::Bluetooth("Truncated HCI Command Complete event".to_string()))?;
-    if status != 0 {
-        return Err(ScanError::Bluetooth(format!(
-            "HCI command {opcode:#06x} failed with status {status:#04x}"
-        )));
-    }
+    Ok((status, event))
+}
 
-    Ok(event)
+/// The error for a command that failed with a non-zero `status`.
+fn command_failed(ogf: u16, ocf: u16, status: u8) -> ScanError {
+    let opcode = (ogf << 10) | ocf;
+    ScanError::Bluetooth(format!(
+        "HCI command {opcode:#06x} failed with status {status:#04x}"
+    ))
 }
 
-/// Query whether the controller supports LE Extended Advertising.
+/// Check the status of an LE Set (Extended) Scan Enable command.
 ///
-/// Reads the LE features bitmap and checks the Extended Advertising bit. A
-/// Bluetooth 5 controller (e.g. Intel AX210) reports advertisements via
-/// Extended Advertising Reports once extended scanning is enabled, so we must
-/// drive it with the extended scan commands instead of the legacy ones.
-fn controller_supports_extended_scan(fd: &OwnedFd) -> Result<bool, ScanError> {
+/// Controllers refuse active scanning from a random address, i.e. with
+/// `--own-address-type random` or `resolvable-or-random` without a resolvable
+/// address, until a random address has been set.
+fn check_scan_enable_status(ocf: u16, status: u8) -> Result<(), ScanError> {
+    match status {
+        0 => Ok(()),
+        HCI_INVALID_COMMAND_PARAMETERS => Err(ScanError::UnsupportedScanParameters(
+            "the controller has no random address to send scan requests from".into(),
+        )),
+        status => Err(command_failed(OGF_LE_CTL, ocf, status)),
+    }
+}
+
+/// Read the controller's LE features bitmap.
+///
+/// A truncated response is treated as no features being supported.
+fn read_le_features(fd: &OwnedFd) -> Result<[u8; 8], ScanError> {
     let event =
         send_hci_command_checked(fd, OGF_LE_CTL, OCF_LE_READ_LOCAL_SUPPORTED_FEATURES, &[])?;
 
     // Return params after status (byte 6) are the 8-byte LE features bitmap.
-    let features_start = 7;
-    match event.get(features_start + LE_FEATURE_EXTENDED_ADVERTISING_BYTE) {
-        Some(byte) => Ok(byte & LE_FEATURE_EXTENDED_ADVERTISING_BIT != 0),
-        None => Ok(false),
+    let mut features = [0u8; 8];
+    if let Some(bitmap) = event.get(7..15) {
+        features.copy_from_slice(bitmap);
     }
+    Ok(features)
+}
+
+/// Whether the controller supports LE Extended Advertising.
+///
+/// A Bluetooth 5 controller (e.g. Intel AX210) reports advertisements via
+/// Extended Advertising Reports once extended scanning is enabled, so we must
+/// drive it with the extended scan commands instead of the legacy ones.
+fn supports_extended_scan(features: &[u8; 8]) -> bool {
+    features[LE_FEATURE_EXTENDED_ADVERTISING_BYTE] & LE_FEATURE_EXTENDED_ADVERTISING_BIT != 0
+}
+
+/// Whether the controller supports the LE Coded PHY.
+fn supports_coded_phy(features: &[u8; 8]) -> bool {
+    features[LE_FEATURE_CODED_PHY_BYTE] & LE_FEATURE_CODED_PHY_BIT != 0
+}
+
+/// Whether the controller supports LL Privacy, i.e. resolvable private
+/// addresses generated by the controller.
+fn supports_ll_privacy(features: &[u8; 8]) -> bool {
+    features[LE_FEATURE_LL_PRIVACY_BYTE] & LE_FEATURE_LL_PRIVACY_BIT != 0
+}
+
+/// Check that the controller can send scan requests from `own_address_type`.
+fn check_own_address_type(
+    own_address_type: OwnAddressType,
+    features: &[u8; 8],
+) -> Result<(), ScanError> {
+    match own_address_type {
+        OwnAddressType::ResolvableOrPublic | OwnAddressType::ResolvableOrRandom
+            if !supports_ll_privacy(features) =>
+        {
+            Err(ScanError::UnsupportedScanParameters(
+                "the controller does not support LL Privacy, needed for resolvable private addresses"
+                    .into(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Which set of LE scan commands the controller is driven with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanMode {
+    /// Bluetooth 4.x commands, reporting Advertising Reports
+    Legacy,
+    /// Bluetooth 5.x commands, reporting Extended Advertising Reports
+    Extended,
 }
 
 /// Configure LE scanning, preferring extended scanning when the controller
 /// supports it.
-fn configure_le_scan(fd: &OwnedFd) -> Result<(), ScanError> {
-    if controller_supports_extended_scan(fd)? {
-        configure_extended_le_scan(fd)
+///
+/// Fails if `params` are out of range for the commands used or ask for
+/// features the controller lacks.
+fn configure_le_scan(fd: &OwnedFd, params: &ScanParameters) -> Result<ScanMode, ScanError> {
+    let features = read_le_features(fd)?;
+    check_own_address_type(params.own_address_type, &features)?;
+    let extended = supports_extended_scan(&features);
+    // The Coded PHY can only be scanned with the extended commands
+    let coded_supported = extended && supports_coded_phy(&features);
+    let coded = match params.coded_phy {
+        CodedPhy::Auto => coded_supported,
+        CodedPhy::On if !coded_supported => {
+            return Err(ScanError::UnsupportedScanParameters(
+                "the controller does not support scanning on the LE Coded PHY".into(),
+            ));
+        }
+        CodedPhy::On => true,
+        CodedPhy::Off => false,
+    };
+
+    if extended {
+        configure_extended_le_scan(fd, params, coded)?;
+        Ok(ScanMode::Extended)
     } else {
-        configure_legacy_le_scan(fd)
+        configure_legacy_le_scan(fd, params)?;
+        Ok(ScanMode::Legacy)
+    }
+}
+
+/// Enable or disable LE scanning with the commands of the given mode.
+fn set_le_scan_enable(
+    fd: &OwnedFd,
+    mode: ScanMode,
+    enable: bool,
+    filter_duplicates: bool,
+) -> Result<(), ScanError> {
+    match mode {
+        ScanMode::Legacy => set_legacy_scan_enable(fd, enable, filter_duplicates),
+        ScanMode::Extended => set_extended_scan_enable(fd, enable, filter_duplicates),
+    }
+}
+
+/// Restart LE scanning with the parameters already configured, by disabling
+/// and re-enabling it.
+fn restart_le_scan(fd: &OwnedFd, mode: ScanMode, filter_duplicates: bool) -> Result<(), ScanError> {
+    set_le_scan_enable(fd, mode, false, filter_duplicates)?;
+    set_le_scan_enable(fd, mode, true, filter_duplicates)
+}
+
+/// Value of the `LE_Scan_Type` command parameter. The HCI backend scans
+/// passively unless asked otherwise.
+fn scan_type_code(params: &ScanParameters) -> u8 {
+    match params.scan_type.unwrap_or(ScanType::Passive) {
+        ScanType::Passive => LE_SCAN_PASSIVE,
+        ScanType::Active => LE_SCAN_ACTIVE,
     }
 }
 
 /// Configure legacy (Bluetooth 4.x) LE scanning parameters.
-fn configure_legacy_le_scan(fd: &OwnedFd) -> Result<(), ScanError> {
+fn configure_legacy_le_scan(fd: &OwnedFd, params: &ScanParameters) -> Result<(), ScanError> {
+    let (interval, window) = params.slots(MAX_LEGACY_SLOTS)?;
+
     // Setting scan parameters is rejected with "Command Disallowed" while
     // scanning is already active (e.g. bluetoothd is running a discovery), so
     // disable scanning first. Disabling when already disabled is a harmless
     // no-op.
-    set_legacy_scan_enable(fd, false)?;
-
-    // Set scan parameters: passive scan, 200ms interval, 200ms window
-    // Using longer intervals reduces CPU usage significantly while still
-    // catching RuuviTag broadcasts (which occur every ~1 second)
-    let params = LeSetScanParametersCmd {
-        scan_type: LE_SCAN_PASSIVE,
-        interval: 0x0140, // 200ms in 0.625ms units (0x140 = 320 * 0.625ms)
-        window: 0x0140,   // 200ms in 0.625ms units (0x140 = 320 * 0.625ms)
-        own_address_type: LE_PUBLIC_ADDRESS,
+    set_legacy_scan_enable(fd, false, params.filter_duplicates)?;
+
+    let cmd = LeSetScanParametersCmd {
+        scan_type: scan_type_code(params),
+        interval,
+        window,
+        own_address_type: params.own_address_type.code(),
         filter_policy: FILTER_POLICY_ACCEPT_ALL,
     };
 
     let params_bytes = unsafe {
         std::slice::from_raw_parts(
-            &params as *const LeSetScanParametersCmd as *const u8,
+            &cmd as *const LeSetScanParametersCmd as *const u8,
             mem::size_of::<LeSetScanParametersCmd>(),
         )
     };
 
     send_hci_command_checked(fd, OGF_LE_CTL, OCF_LE_SET_SCAN_PARAMETERS, params_bytes)?;
 
-    set_legacy_scan_enable(fd, true)?;
+    set_legacy_scan_enable(fd, true, params.filter_duplicates)?;
 
     Ok(())
 }
 
 /// Enable or disable legacy LE scanning.
-fn set_legacy_scan_enable(fd: &OwnedFd, enable: bool) -> Result<(), ScanError> {
+fn set_legacy_scan_enable(
+    fd: &OwnedFd,
+    enable: bool,
+    filter_duplicates: bool,
+) -> Result<(), ScanError> {
     let cmd = LeSetScanEnableCmd {
         enable: enable as u8,
-        filter_dup: 0x00, // Don't filter duplicates
+        filter_dup: filter_duplicates as u8,
     };
 
     let bytes = unsafe {
@@ -660,56 +902,97 @@ fn set_legacy_scan_enable(fd: &OwnedFd, enable: bool) -> Result<(), ScanError> {
         )
     };
 
-    send_hci_command_checked(fd, OGF_LE_CTL, OCF_LE_SET_SCAN_ENABLE, bytes)?;
-    Ok(())
+    let (status, _) = send_hci_command_status(fd, OGF_LE_CTL, OCF_LE_SET_SCAN_ENABLE, bytes)?;
+    check_scan_enable_status(OCF_LE_SET_SCAN_ENABLE, status)
 }
 
-/// Configure extended (Bluetooth 5.x) LE scanning parameters.
+/// Build the parameters of the LE Set Extended Scan Parameters command.
 ///
-/// Mirrors the legacy configuration (passive scan, 200ms interval/window on the
-/// LE 1M PHY) using the extended scan commands. Controllers that have been put
-/// into extended mode only report advertisements via Extended Advertising
-/// Reports, so the legacy `LE Set Scan Enable` command would be rejected.
-fn configure_extended_le_scan(fd: &OwnedFd) -> Result<(), ScanError> {
-    // Setting scan parameters is rejected with "Command Disallowed" while
-    // scanning is already active (e.g. bluetoothd is running a discovery), so
-    // disable scanning first. Disabling when already disabled is a harmless
-    // no-op.
-    set_extended_scan_enable(fd, false)?;
+/// One `{scan_type, interval, window}` block follows the PHY bitmask for each
+/// scanning PHY, LE 1M first. Both PHYs use the same timing.
+fn extended_scan_parameters(
+    params: &ScanParameters,
+    coded: bool,
+    interval: u16,
+    window: u16,
+) -> Vec<u8> {
+    let mut phys = LE_1M_PHY;
+    if coded {
+        phys |= LE_CODED_PHY;
+    }
 
-    let params = LeSetExtendedScanParametersCmd {
-        own_address_type: LE_PUBLIC_ADDRESS,
+    let header = LeSetExtendedScanParametersCmd {
+        own_address_type: params.own_address_type.code(),
         filter_policy: FILTER_POLICY_ACCEPT_ALL,
-        scanning_phys: LE_1M_PHY,
-        scan_type: LE_SCAN_PASSIVE,
-        interval: 0x0140, // 200ms in 0.625ms units
-        window: 0x0140,   // 200ms in 0.625ms units
+        scanning_phys: phys,
+    };
+    let phy_params = LeExtendedScanPhyParams {
+        scan_type: scan_type_code(params),
+        interval,
+        window,
     };
 
-    let params_bytes = unsafe {
+    let mut bytes = unsafe {
         std::slice::from_raw_parts(
-            &params as *const LeSetExtendedScanParametersCmd as *const u8,
+            &header as *const LeSetExtendedScanParametersCmd as *const u8,
             mem::size_of::<LeSetExtendedScanParametersCmd>(),
         )
+    }
+    .to_vec();
+    let phy_bytes = unsafe {
+        std::slice::from_raw_parts(
+            &phy_params as *const LeExtendedScanPhyParams as *const u8,
+            mem::size_of::<LeExtendedScanPhyParams>(),
+        )
     };
+    for _ in 0..phys.count_ones() {
+        bytes.extend_from_slice(phy_bytes);
+    }
+    bytes
+}
+
+/// Configure extended (Bluetooth 5.x) LE scanning parameters.
+///
+/// Controllers that have been put into extended mode only report
+/// advertisements via Extended Advertising Reports, so the legacy `LE Set Scan
+/// Enable` command would be rejected.
+///
+/// With `coded`, the controller scans on the LE Coded PHY as well as LE 1M.
+fn configure_extended_le_scan(
+    fd: &OwnedFd,
+    params: &ScanParameters,
+    coded: bool,
+) -> Result<(), ScanError> {
+    let (interval, window) = params.slots(MAX_EXTENDED_SLOTS)?;
 
+    // Setting scan parameters is rejected with "Command Disallowed" while
+    // scanning is already active (e.g. bluetoothd is running a discovery), so
+    // disable scanning first. Disabling when already disabled is a harmless
+    // no-op.
+    set_extended_scan_enable(fd, false, params.filter_duplicates)?;
+
+    let params_bytes = extended_scan_parameters(params, coded, interval, window);
     send_hci_command_checked(
         fd,
         OGF_LE_CTL,
         OCF_LE_SET_EXTENDED_SCAN_PARAMETERS,
-        params_bytes,
+        &params_bytes,
     )?;
 
-    set_extended_scan_enable(fd, true)?;
+    set_extended_scan_enable(fd, true, params.filter_duplicates)?;
 
     Ok(())
 }
 
 /// Enable or disable extended LE scanning (continuous: duration = period = 0).
-fn set_extended_scan_enable(fd: &OwnedFd, enable: bool) -> Result<(), ScanError> {
+fn set_extended_scan_enable(
+    fd: &OwnedFd,
+    enable: bool,
+    filter_duplicates: bool,
+) -> Result<(), ScanError> {
     let cmd = LeSetExtendedScanEnableCmd {
         enable: enable as u8,
-        filter_dup: 0x00, // Don't filter duplicates
+        filter_dup: filter_duplicates as u8,
         duration: 0x0000,
         period: 0x0000,
     };
@@ -721,142 +1004,189 @@ fn set_extended_scan_enable(fd: &OwnedFd, enable: bool) -> Result<(), ScanError>
         )
     };
 
-    send_hci_command_checked(fd, OGF_LE_CTL, OCF_LE_SET_EXTENDED_SCAN_ENABLE, bytes)?;
-    Ok(())
+    let (status, _) =
+        send_hci_command_status(fd, OGF_LE_CTL, OCF_LE_SET_EXTENDED_SCAN_ENABLE, bytes)?;
+    check_scan_enable_status(OCF_LE_SET_EXTENDED_SCAN_ENABLE, status)
 }
 
-/// Quick check if a packet might contain Ruuvi manufacturer data.
+/// Quick check if a packet might contain Ruuvi data.
 ///
 /// This performs a fast scan for the Ruuvi manufacturer ID bytes (0x99 0x04 in LE)
-/// to avoid expensive parsing of non-Ruuvi advertisements.
+/// and the Eddystone service UUID bytes (0xAA 0xFE in LE), which carry data
+/// formats 2 and 4, to avoid expensive parsing of non-Ruuvi advertisements.
 #[inline]
 fn might_be_ruuvi(data: &[u8]) -> bool {
-    data.windows(2).any(|w| w == RUUVI_MANUFACTURER_ID_LE)
+    let eddystone_uuid_le = EDDYSTONE_UUID.to_le_bytes();
+    data.windows(2)
+        .any(|w| w == RUUVI_MANUFACTURER_ID_LE || w == eddystone_uuid_le)
 }
 
-/// Parse a legacy LE Advertising Report (subevent 0x02) and extract RuuviTag data.
-fn parse_advertising_report(data: &[u8], verbose: bool) -> Option<MeasurementResult> {
+/// Parse a legacy LE Advertising Report (subevent 0x02) and extract RuuviTag
+/// data from each of its reports.
+///
+/// If `verbose`, a short event or a truncated report is reported as an error
+/// after the reports decoded before it.
+fn parse_advertising_report(
+    data: &[u8],
+    verbose: bool,
+    decoder: &FrameDecoder,
+) -> Vec<MeasurementResult> {
+    let mut results = Vec::new();
+
     // Minimum size for an advertising report
     if data.len() < 12 {
-        return if verbose {
-            Some(Err(DecodeError::InvalidData(
+        if verbose {
+            results.push(Err(DecodeError::InvalidData(
                 "Advertising report too short".into(),
-            )))
-        } else {
-            None
-        };
+            )));
+        }
+        return results;
     }
 
     // Skip HCI header (1 byte packet type + 1 byte event code + 1 byte param len + 1 byte subevent)
-    let report = &data[4..];
-
-    if report.is_empty() {
-        return None;
-    }
-
-    // Number of reports
-    let num_reports = report[0] as usize;
-    if num_reports == 0 {
-        return None;
-    }
-
-    // Legacy per-report header:
-    //   num_reports(1) event_type(1) addr_type(1) address(6) data_len(1) data(..)
-    // Extract address (6 bytes, in reverse order)
-    if report.len() < 10 {
-        return None;
-    }
-    let mut addr = [0u8; 6];
-    addr.copy_from_slice(&report[3..9]);
-    addr.reverse(); // HCI uses little-endian address
+    // and the number of reports
+    let num_reports = data[4];
+    let mut reports = &data[5..];
+
+    // Legacy per-report layout, repeated `num_reports` times:
+    //   event_type(1) addr_type(1) address(6) data_len(1) data(..) rssi(1)
+    for _ in 0..num_reports {
+        let Some((header, ad_data)) = reports.get(..9).and_then(|header| {
+            let data_len = header[8] as usize;
+            Some((header, reports.get(9..9 + data_len)?))
+        }) else {
+            if verbose {
+                results.push(Err(DecodeError::InvalidData(
+                    "Advertising report truncated".into(),
+                )));
+            }
+            break;
+        };
+        let mut addr = [0u8; 6];
+        addr.copy_from_slice(&header[2..8]);
+        addr.reverse(); // HCI uses little-endian address
+
+        let data_len = ad_data.len();
+        let rssi = reports.get(9 + data_len).map(|&rssi| i16::from(rssi as i8));
+        // Legacy advertising always uses the LE 1M PHY
+        if let Some(ruuvi_data) = find_ruuvi_data(ad_data) {
+            results.push(decoder.decode(MacAddress(addr), rssi, Some(Phy::Le1M), &ruuvi_data));
+        }
 
-    let data_len = report[9] as usize;
-    if report.len() < 10 + data_len {
-        return None;
+        reports = reports.get(9 + data_len + 1..).unwrap_or_default();
     }
 
-    parse_ruuvi_from_ad_data(&report[10..10 + data_len], addr)
+    results
 }
 
-/// Parse an LE Extended Advertising Report (subevent 0x0D) and extract RuuviTag data.
+/// Parse an LE Extended Advertising Report (subevent 0x0D) and extract RuuviTag
+/// data from each of its reports.
 ///
 /// Bluetooth 5 controllers report advertisements with this event once extended
 /// scanning is enabled. Its per-report header is larger than the legacy one and
-/// carries PHY/SID/TX-power fields before the advertising data.
-fn parse_extended_advertising_report(data: &[u8], _verbose: bool) -> Option<MeasurementResult> {
-    // Skip HCI header (pkt type + event code + param len + subevent)
-    let report = data.get(4..)?;
+/// carries PHY/SID/TX-power fields before the advertising data. Data split
+/// across several reports is joined by `reassembler` before decoding.
+///
+/// If `verbose`, a short event or a truncated report is reported as an error
+/// after the reports decoded before it.
+fn parse_extended_advertising_report(
+    data: &[u8],
+    verbose: bool,
+    decoder: &FrameDecoder,
+    reassembler: &mut Reassembler,
+    now: Instant,
+) -> Vec<MeasurementResult> {
+    let mut results = Vec::new();
 
-    // Number of reports
-    let num_reports = *report.first()?;
-    if num_reports == 0 {
-        return None;
-    }
+    // Skip HCI header (pkt type + event code + param len + subevent)
+    let Some((&num_reports, mut reports)) = data.get(4..).and_then(<[u8]>::split_first) else {
+        if verbose {
+            results.push(Err(DecodeError::InvalidData(
+                "Extended advertising report too short".into(),
+            )));
+        }
+        return results;
+    };
 
-    // Extended per-report header (relative to `report`):
-    //   [0]      num_reports
-    //   [1..3]   event_type (2)
-    //   [3]      address_type
-    //   [4..10]  address (6)
-    //   [10]     primary_phy
-    //   [11]     secondary_phy
-    //   [12]     advertising_sid
-    //   [13]     tx_power
-    //   [14]     rssi
-    //   [15..17] periodic_advertising_interval (2)
-    //   [17]     direct_address_type
-    //   [18..24] direct_address (6)
-    //   [24]     data_length
-    //   [25..]   data
-    if report.len() < 25 {
-        return None;
-    }
-    let mut addr = [0u8; 6];
-    addr.copy_from_slice(&report[4..10]);
-    addr.reverse(); // HCI uses little-endian address
+    // Extended per-report layout, repeated `num_reports` times:
+    //   [0..2]   event_type (2)
+    //   [2]      address_type
+    //   [3..9]   address (6)
+    //   [9]      primary_phy
+    //   [10]     secondary_phy
+    //   [11]     advertising_sid
+    //   [12]     tx_power
+    //   [13]     rssi
+    //   [14..16] periodic_advertising_interval (2)
+    //   [16]     direct_address_type
+    //   [17..23] direct_address (6)
+    //   [23]     data_length
+    //   [24..]   data
+    for _ in 0..num_reports {
+        let Some((header, fragment)) = reports.get(..24).and_then(|header| {
+            let data_len = header[23] as usize;
+            Some((header, reports.get(24..24 + data_len)?))
+        }) else {
+            if verbose {
+                results.push(Err(DecodeError::InvalidData(
+                    "Extended advertising report truncated".into(),
+                )));
+            }
+            break;
+        };
+        let data_len = fragment.len();
+        reports = &reports[24 + data_len..];
+
+        let event_type = u16::from_le_bytes([header[0], header[1]]);
+        let mut key = FragmentKey {
+            address: [0u8; 6],
+            address_type: header[2],
+            sid: header[11],
+        };
+        key.address.copy_from_slice(&header[3..9]);
+        let status = DataStatus::from_event_type(event_type);
 
-    let data_len = report[24] as usize;
-    if report.len() < 25 + data_len {
-        return None;
+        let Some(ad_data) = reassembler.push(key, status, fragment, now) else {
+            continue;
+        };
+        let mut addr = key.address;
+        addr.reverse(); // HCI uses little-endian address
+        if let Some(ruuvi_data) = find_ruuvi_data(&ad_data) {
+            let phy = phy_from_code(header[9]);
+            results.push(decoder.decode(
+                MacAddress(addr),
+                rssi_from_code(header[13]),
+                phy,
+                &ruuvi_data,
+            ));
+        }
     }
 
-    parse_ruuvi_from_ad_data(&report[25..25 + data_len], addr)
+    results
 }
 
-/// Walk the AD structures of an advertisement and decode any RuuviTag
-/// manufacturer data found.
-fn parse_ruuvi_from_ad_data(ad_data: &[u8], addr: [u8; 6]) -> Option<MeasurementResult> {
-    let mut offset = 0;
-    while offset + 2 <= ad_data.len() {
-        let len = ad_data[offset] as usize;
-        if len == 0 || offset + 1 + len > ad_data.len() {
-            break;
-        }
-
-        let ad_type = ad_data[offset + 1];
-
-        if ad_type == AD_TYPE_MANUFACTURER_DATA && len >= 3 {
-            // Extract manufacturer ID (little-endian)
-            let mfg_id = u16::from_le_bytes([ad_data[offset + 2], ad_data[offset + 3]]);
-
-            if mfg_id == RUUVI_MANUFACTURER_ID {
-                // Found RuuviTag data
-                let ruuvi_data = &ad_data[offset + 4..offset + 1 + len];
-                return Some(decode_ruuvi_data(MacAddress(addr), ruuvi_data));
-            }
-        }
-
-        offset += 1 + len;
+/// Map a `Primary_PHY`/`Secondary_PHY` value of an extended report to a [`Phy`].
+fn phy_from_code(code: u8) -> Option<Phy> {
+    match code {
+        0x01 => Some(Phy::Le1M),
+        0x02 => Some(Phy::Le2M),
+        0x03 => Some(Phy::LeCoded),
+        _ => None,
     }
+}
 
-    None
+/// Map the `RSSI` of an extended report to dBm; 127 means it is not available.
+fn rssi_from_code(code: u8) -> Option<i16> {
+    (code != 0x7F).then_some(i16::from(code as i8))
 }
 
 /// Start scanning for RuuviTag devices using raw HCI sockets.
 ///
 /// This function opens a raw HCI socket, configures LE scanning, and
 /// processes advertising reports. Discovered measurements are sent through the
-/// returned channel. Runs indefinitely until interrupted.
+/// returned channel. Runs until the receiver is dropped, after which LE
+/// scanning is disabled again so the controller does not keep scanning after
+/// the listener exits.
 ///
 /// # Kernel-Level Filtering
 ///
@@ -868,18 +1198,27 @@ fn parse_ruuvi_from_ad_data(ad_data: &[u8], addr: [u8; 6]) -> Option<Measurement
 /// not for the many other BLE devices that may be in the environment.
 ///
 /// # Arguments
-/// * `verbose` - If true, decode errors are sent as Err values; otherwise they're silently dropped.
+/// * `config` - Scan settings. Decode errors are sent as Err values only if
+///   `config.verbose` is set.
+/// * `adapter` - The HCI device to scan with, or hci0 if `None`.
 ///
 /// # Returns
 /// A receiver for measurements (or decode errors if verbose).
 ///
 /// # Requirements
 /// - CAP_NET_RAW and CAP_NET_ADMIN capabilities or root privileges
-/// - An available HCI device (typically hci0)
-pub async fn start_scan(verbose: bool) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+/// - An available HCI device (hci0 unless another adapter is selected)
+pub async fn start_scan(
+    config: &ScanConfig,
+    adapter: Option<AdapterId>,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let verbose = config.verbose;
+
     // Open and configure HCI socket for receiving events
     let fd = open_hci_socket()?;
-    bind_hci_socket(&fd, 0)?; // Bind to hci0 to receive advertising events
+    let dev_id = resolve_hci_device(&fd, adapter)?;
+    let adapter_name: Arc<str> = Arc::from(format!("hci{}", dev_id));
+    bind_hci_socket(&fd, dev_id)?; // Bind to the adapter to receive advertising events
     set_hci_filter(&fd)?;
     set_bpf_ruuvi_filter(&fd)?; // Kernel-level filtering for Ruuvi packets
 
@@ -887,9 +1226,12 @@ pub async fn start_scan(verbose: bool) -> Result<mpsc::Receiver<MeasurementResul
     // It needs a filter that lets Command Complete events through so we can read
     // back command results and detect Bluetooth 5 extended-advertising support.
     let cmd_fd = open_hci_socket()?;
-    bind_hci_socket(&cmd_fd, 0)?; // Bind to hci0
+    bind_hci_socket(&cmd_fd, dev_id)?;
     set_command_hci_filter(&cmd_fd)?;
-    configure_le_scan(&cmd_fd)?;
+    let mode = configure_le_scan(&cmd_fd, &config.scan)?;
+    let filter_duplicates = config.scan.filter_duplicates;
+    let mut watchdog = Watchdog::from_config(config);
+    let decoder = FrameDecoder::new(Some(adapter_name.clone()), config.recorder.clone());
 
     let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
 
@@ -897,16 +1239,38 @@ pub async fn start_scan(verbose: bool) -> Result<mpsc::Receiver<MeasurementResul
     let async_fd = AsyncFd::new(fd)
         .map_err(|e| ScanError::Bluetooth(format!("Failed to create async fd: {}", e)))?;
 
-    // Spawn a task to read and process HCI events
-    tokio::spawn(async move {
-        let _cmd_fd = cmd_fd; // Keep command socket alive
+    // Spawn a task to read and process HCI events. The task ends, closing the
+    // channel, when the socket fails, e.g. because the adapter was reset or
+    // removed; the supervisor in `scanner::supervisor` then restarts the scan.
+    // It also ends when the receiver is dropped on shutdown.
+    config.spawn(|config| async move {
         let mut buf = [0u8; 258]; // Max HCI event size
-
-        loop {
-            // Wait for the socket to be readable
-            let mut guard = match async_fd.readable().await {
-                Ok(guard) => guard,
-                Err(_) => break,
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+
+        'events: loop {
+            // Wait for the socket to be readable, or for the receiver to go away
+            let readable = tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                readable = watch(watchdog.as_ref(), async_fd.readable()) => readable,
+            };
+            let mut guard = match readable {
+                Some(Ok(guard)) => guard,
+                Some(Err(_)) => break,
+                None => {
+                    // No RuuviTag frames for a while: restart scanning
+                    let Some(watchdog) = watchdog.as_mut() else {
+                        break;
+                    };
+                    if let Err(e) = watchdog.expire_for(Backend::Hci, adapter, &adapter_name) {
+                        config.report_fatal(e);
+                        break;
+                    }
+                    if restart_le_scan(&cmd_fd, mode, filter_duplicates).is_err() {
+                        break;
+                    }
+                    continue;
+                }
             };
 
             // Drain all available packets before waiting again
@@ -926,28 +1290,45 @@ pub async fn start_scan(verbose: bool) -> Result<mpsc::Receiver<MeasurementResul
                     }
                 }) {
                     Ok(Ok(n)) if n > 0 => n,
-                    Ok(Ok(_)) => break,  // EOF or empty read
-                    Ok(Err(_)) => break, // Read error
-                    Err(_) => break,     // WouldBlock - no more data
+                    Ok(Ok(_)) => break 'events, // EOF: the device went away
+                    Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Ok(Err(_)) => break 'events, // Read error, e.g. ENETDOWN after a reset
+                    Err(_) => break,             // WouldBlock - no more data
                 };
 
                 // Check if this is an LE advertising report that might be from a
                 // RuuviTag. Controllers emit legacy reports (0x02) or, in
                 // extended/Bluetooth 5 mode, extended reports (0x0D).
                 if n >= 4 && buf[0] == HCI_EVENT_PKT && buf[1] == EVT_LE_META_EVENT {
+                    if let Some(dump) = &config.hci_dump {
+                        dump.write_event(&buf[..n], SystemTime::now());
+                    }
                     let subevent = buf[3];
-                    // Quick check for Ruuvi manufacturer ID before expensive parsing
-                    let result = if !might_be_ruuvi(&buf[..n]) {
-                        None
-                    } else if subevent == EVT_LE_ADVERTISING_REPORT {
-                        parse_advertising_report(&buf[..n], verbose)
+                    let results = if subevent == EVT_LE_ADVERTISING_REPORT {
+                        // Quick check for Ruuvi IDs before expensive parsing
+                        if might_be_ruuvi(&buf[..n]) {
+                            parse_advertising_report(&buf[..n], verbose, &decoder)
+                        } else {
+                            Vec::new()
+                        }
                     } else if subevent == EVT_LE_EXTENDED_ADVERTISING_REPORT {
-                        parse_extended_advertising_report(&buf[..n], verbose)
+                        // Fragments after the first need not contain the
+                        // manufacturer ID, so always parse extended reports
+                        parse_extended_advertising_report(
+                            &buf[..n],
+                            verbose,
+                            &decoder,
+                            &mut reassembler,
+                            Instant::now(),
+                        )
                     } else {
-                        None
+                        Vec::new()
                     };
 
-                    if let Some(result) = result {
+                    for result in results {
+                        if let Some(watchdog) = watchdog.as_mut() {
+                            watchdog.feed();
+                        }
                         match &result {
                             Ok(_) => {
                                 let _ = tx.send(result).await;
@@ -961,6 +1342,10 @@ pub async fn start_scan(verbose: bool) -> Result<mpsc::Receiver<MeasurementResul
                 }
             }
         }
+
+        // Leave the controller idle rather than scanning for nobody. This fails
+        // harmlessly when the adapter is already gone.
+        let _ = set_le_scan_enable(&cmd_fd, mode, false, filter_duplicates);
     });
 
     Ok(rx)
@@ -969,6 +1354,8 @@ pub async fn start_scan(verbose: bool) -> Result<mpsc::Receiver<MeasurementResul
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::measurement::Format;
+    use crate::scanner::MANUFACTURER_DATA_TYPE;
 
     #[test]
     fn test_hci_filter_setup() {
@@ -983,6 +1370,17 @@ mod tests {
         assert_eq!(filter.event_mask[1], 1 << (EVT_LE_META_EVENT % 32));
     }
 
+    #[test]
+    fn test_hci_dev_info_layout() {
+        // Must match the kernel's struct hci_dev_info, which HCIGETDEVINFO copies in full
+        assert_eq!(mem::size_of::<HciDevInfo>(), 92);
+        assert_eq!(mem::offset_of!(HciDevInfo, bdaddr), 10);
+        assert_eq!(mem::offset_of!(HciDevInfo, flags), 16);
+        assert_eq!(mem::offset_of!(HciDevInfo, pkt_type), 32);
+        assert_eq!(mem::offset_of!(HciDevInfo, stat), 52);
+        assert_eq!(mem::size_of::<HciDevReq>(), 8);
+    }
+
     #[test]
     fn test_hci_command_packet() {
         let packet = hci_command_packet(OGF_LE_CTL, OCF_LE_SET_SCAN_ENABLE, &[0x01, 0x00]);
@@ -991,6 +1389,79 @@ mod tests {
         assert_eq!(packet.len(), 6); // Header + 2 params
     }
 
+    #[test]
+    fn test_extended_scan_parameters_per_phy() {
+        let params = ScanParameters {
+            scan_type: Some(ScanType::Active),
+            own_address_type: OwnAddressType::Random,
+            ..Default::default()
+        };
+        assert_eq!(
+            extended_scan_parameters(&params, false, 0x0140, 0x00A0),
+            vec![0x01, 0x00, 0x01, 0x01, 0x40, 0x01, 0xA0, 0x00]
+        );
+
+        let params = ScanParameters::default();
+        assert_eq!(
+            extended_scan_parameters(&params, true, 0x0140, 0x0140),
+            vec![
+                0x00, 0x00, 0x05, // Public address, accept all, 1M + Coded
+                0x00, 0x40, 0x01, 0x40, 0x01, // LE 1M
+                0x00, 0x40, 0x01, 0x40, 0x01, // LE Coded
+            ]
+        );
+    }
+
+    #[test]
+    fn test_le_feature_bits() {
+        // Extended Advertising (bit 12) and Coded PHY (bit 11)
+        let features = [0x00, 0x18, 0, 0, 0, 0, 0, 0];
+        assert!(supports_extended_scan(&features));
+        assert!(supports_coded_phy(&features));
+
+        let features = [0xFF, 0x10, 0, 0, 0, 0, 0, 0];
+        assert!(supports_extended_scan(&features));
+        assert!(!supports_coded_phy(&features));
+
+        // LL Privacy (bit 6)
+        assert!(supports_ll_privacy(&[0x40, 0, 0, 0, 0, 0, 0, 0]));
+        assert!(!supports_ll_privacy(&[0xBF, 0xFF, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_check_own_address_type() {
+        let privacy = [0x40, 0, 0, 0, 0, 0, 0, 0];
+        let no_privacy = [0x00, 0x18, 0, 0, 0, 0, 0, 0];
+        for own_address_type in [OwnAddressType::Public, OwnAddressType::Random] {
+            assert!(check_own_address_type(own_address_type, &no_privacy).is_ok());
+        }
+        for own_address_type in [
+            OwnAddressType::ResolvableOrPublic,
+            OwnAddressType::ResolvableOrRandom,
+        ] {
+            assert!(check_own_address_type(own_address_type, &privacy).is_ok());
+            assert!(matches!(
+                check_own_address_type(own_address_type, &no_privacy),
+                Err(ScanError::UnsupportedScanParameters(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_check_scan_enable_status() {
+        assert!(check_scan_enable_status(OCF_LE_SET_SCAN_ENABLE, 0).is_ok());
+        // No random address set
+        assert!(matches!(
+            check_scan_enable_status(OCF_LE_SET_EXTENDED_SCAN_ENABLE, 0x12),
+            Err(ScanError::UnsupportedScanParameters(_))
+        ));
+        let err = check_scan_enable_status(OCF_LE_SET_SCAN_ENABLE, 0x0C).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bluetooth error: HCI command 0x200c failed with status 0x0c"
+        );
+    }
+
     #[test]
     fn test_might_be_ruuvi_positive() {
         // Packet containing Ruuvi manufacturer ID (0x0499 in little-endian = 0x99 0x04)
@@ -998,6 +1469,13 @@ mod tests {
         assert!(might_be_ruuvi(&packet));
     }
 
+    #[test]
+    fn test_might_be_ruuvi_eddystone() {
+        // Packet containing Eddystone service data (0xFEAA in little-endian = 0xAA 0xFE)
+        let packet = [0x04, 0x3E, 0x1A, 0x02, 0x01, 0x00, 0x16, 0xAA, 0xFE, 0x10];
+        assert!(might_be_ruuvi(&packet));
+    }
+
     #[test]
     fn test_might_be_ruuvi_negative() {
         // Packet without Ruuvi manufacturer ID
@@ -1020,11 +1498,18 @@ mod tests {
         data
     }
 
+    #[test]
+    fn test_rssi_from_code() {
+        assert_eq!(rssi_from_code(0xC3), Some(-61));
+        assert_eq!(rssi_from_code(0x14), Some(20));
+        assert_eq!(rssi_from_code(0x7F), None);
+    }
+
     #[test]
     fn test_parse_extended_advertising_report() {
         let payload = ruuvi_rawv2_payload();
         // AD structure: [len][type=0xFF][payload...]
-        let mut ad = vec![(payload.len() + 1) as u8, AD_TYPE_MANUFACTURER_DATA];
+        let mut ad = vec![(payload.len() + 1) as u8, MANUFACTURER_DATA_TYPE];
         ad.extend_from_slice(&payload);
 
         // HCI header + extended report header + data
@@ -1046,13 +1531,379 @@ mod tests {
         pkt.extend_from_slice(&ad);
 
         assert!(might_be_ruuvi(&pkt));
-        let result = parse_extended_advertising_report(&pkt, false);
-        assert!(result.is_some(), "expected a RuuviTag measurement");
-        let measurement = result.unwrap().expect("payload should decode");
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let mut results = parse_extended_advertising_report(
+            &pkt,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            Instant::now(),
+        );
+        assert_eq!(results.len(), 1, "expected a RuuviTag measurement");
+        let measurement = results.remove(0).expect("payload should decode");
         // Address is little-endian on the wire, so it reverses on decode.
         assert_eq!(
             measurement.mac,
             MacAddress([0x06, 0x05, 0x04, 0x03, 0x02, 0x01])
         );
+        assert_eq!(measurement.phy, Some(Phy::Le1M));
+
+        // The same advertisement received on the LE Coded PHY
+        pkt[4 + 10] = 0x03;
+        let mut results = parse_extended_advertising_report(
+            &pkt,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            Instant::now(),
+        );
+        assert_eq!(results.remove(0).unwrap().phy, Some(Phy::LeCoded));
+    }
+
+    // Extended Advertising Report events carrying the RAWv2 example payload of
+    // the Ruuvi documentation (24.3 °C, 53.49 %, 100044 Pa) from D4:E9:1A:3F:D8:C7.
+
+    /// First fragment of a non-legacy advertisement on the LE Coded PHY: event
+    /// type 0x0020 (more data to follow), 16 of 31 bytes of data.
+    pub(super) const EXTENDED_FRAGMENT_1: [u8; 45] = [
+        0x04, 0x3E, 0x2A, 0x0D, 0x01, 0x20, 0x00, 0x01, 0xC7, 0xD8, 0x3F, 0x1A, 0xE9, 0xD4, 0x03,
+        0x03, 0x01, 0x7F, 0xC5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x02,
+        0x01, 0x06, 0x1B, 0xFF, 0x99, 0x04, 0x05, 0x12, 0xFC, 0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04,
+    ];
+
+    /// Last fragment of the same advertisement: event type 0x0000 (complete),
+    /// the remaining 15 bytes, without the manufacturer ID.
+    pub(super) const EXTENDED_FRAGMENT_2: [u8; 44] = [
+        0x04, 0x3E, 0x29, 0x0D, 0x01, 0x00, 0x00, 0x01, 0xC7, 0xD8, 0x3F, 0x1A, 0xE9, 0xD4, 0x03,
+        0x03, 0x01, 0x7F, 0xC5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0F, 0xFF,
+        0xFC, 0x04, 0x0C, 0xAC, 0x36, 0x42, 0x00, 0xCD, 0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F,
+    ];
+
+    /// Two complete legacy-PDU reports in one event, the second one from
+    /// F6:05:04:03:02:01.
+    const EXTENDED_TWO_REPORTS: [u8; 115] = [
+        0x04, 0x3E, 0x70, 0x0D, 0x02, 0x10, 0x00, 0x01, 0xC7, 0xD8, 0x3F, 0x1A, 0xE9, 0xD4, 0x01,
+        0x00, 0x00, 0x7F, 0xC5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F, 0x02,
+        0x01, 0x06, 0x1B, 0xFF, 0x99, 0x04, 0x05, 0x12, 0xFC, 0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04,
+        0xFF, 0xFC, 0x04, 0x0C, 0xAC, 0x36, 0x42, 0x00, 0xCD, 0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F,
+        0x10, 0x00, 0x01, 0x01, 0x02, 0x03, 0x04, 0x05, 0xF6, 0x01, 0x00, 0x00, 0x7F, 0xC5, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F, 0x02, 0x01, 0x06, 0x1B, 0xFF, 0x99,
+        0x04, 0x05, 0x12, 0xFC, 0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04, 0xFF, 0xFC, 0x04, 0x0C, 0xAC,
+        0x36, 0x42, 0x00, 0xCD, 0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F,
+    ];
+
+    pub(super) const EXAMPLE_MAC: MacAddress = MacAddress([0xD4, 0xE9, 0x1A, 0x3F, 0xD8, 0xC7]);
+
+    #[test]
+    fn test_parse_fragmented_extended_report() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let now = Instant::now();
+
+        let results = parse_extended_advertising_report(
+            &EXTENDED_FRAGMENT_1,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            now,
+        );
+        assert!(results.is_empty());
+        assert!(!might_be_ruuvi(&EXTENDED_FRAGMENT_2));
+        let mut results = parse_extended_advertising_report(
+            &EXTENDED_FRAGMENT_2,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            now,
+        );
+
+        assert_eq!(results.len(), 1);
+        let measurement = results.remove(0).unwrap();
+        assert_eq!(measurement.mac, EXAMPLE_MAC);
+        assert_eq!(measurement.phy, Some(Phy::LeCoded));
+        assert_eq!(measurement.temperature, Some(24.3));
+        assert_eq!(measurement.pressure, Some(100044.0));
+    }
+
+    #[test]
+    fn test_parse_fragmented_extended_report_times_out() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let now = Instant::now();
+
+        parse_extended_advertising_report(
+            &EXTENDED_FRAGMENT_1,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            now,
+        );
+        // The rest arrives too late to be joined, and is not a Ruuvi
+        // advertisement on its own
+        let results = parse_extended_advertising_report(
+            &EXTENDED_FRAGMENT_2,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            now + FRAGMENT_TIMEOUT,
+        );
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_parse_extended_report_with_several_reports() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let results = parse_extended_advertising_report(
+            &EXTENDED_TWO_REPORTS,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            Instant::now(),
+        );
+
+        let macs: Vec<MacAddress> = results.into_iter().map(|r| r.unwrap().mac).collect();
+        assert_eq!(
+            macs,
+            vec![
+                EXAMPLE_MAC,
+                MacAddress([0xF6, 0x05, 0x04, 0x03, 0x02, 0x01])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_truncated_extended_report() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        // Claims two reports but carries only the first one
+        let mut event = EXTENDED_TWO_REPORTS[..60].to_vec();
+        event.extend_from_slice(&[0x10, 0x00, 0x01]);
+
+        let results = parse_extended_advertising_report(
+            &event,
+            false,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            Instant::now(),
+        );
+        assert_eq!(results.len(), 1);
+
+        // Verbose parsing reports the missing report after the decoded one
+        let results = parse_extended_advertising_report(
+            &event,
+            true,
+            &FrameDecoder::default(),
+            &mut Reassembler::new(FRAGMENT_TIMEOUT),
+            Instant::now(),
+        );
+        assert!(matches!(
+            results[..],
+            [Ok(_), Err(DecodeError::InvalidData(_))]
+        ));
+
+        let results = parse_extended_advertising_report(
+            &event[..4],
+            true,
+            &FrameDecoder::default(),
+            &mut reassembler,
+            Instant::now(),
+        );
+        assert!(matches!(results[..], [Err(DecodeError::InvalidData(_))]));
+    }
+
+    // LE Advertising Report events with several reports, as batched by
+    // controllers, of ADV_NONCONN_IND advertisements with random addresses. The
+    // RuuviTag reports carry the RAWv2 example payload of the Ruuvi documentation.
+
+    /// A controller batching three advertisers into one event, the RuuviTag
+    /// last: its manufacturer ID is at offset 99.
+    const LEGACY_RUUVI_LAST: [u8; 126] = [
+        0x04, 0x3E, 0x7B, 0x02, 0x03, 0x03, 0x01, 0x3A, 0x2B, 0x1C, 0x0D, 0x5E, 0x6F, 0x1E, 0x1A,
+        0xFF, 0x4C, 0x00, 0x02, 0x15, 0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60,
+        0xD0, 0xF5, 0xA7, 0x10, 0x96, 0xE0, 0x00, 0x00, 0x00, 0x00, 0xC5, 0x02, 0x01, 0x06, 0xB4,
+        0x03, 0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x1E, 0x1A, 0xFF, 0x4C, 0x00, 0x02, 0x15,
+        0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96,
+        0xE0, 0x00, 0x00, 0x00, 0x00, 0xC5, 0x02, 0x01, 0x06, 0xAE, 0x03, 0x01, 0xC7, 0xD8, 0x3F,
+        0x1A, 0xE9, 0xD4, 0x1F, 0x02, 0x01, 0x06, 0x1B, 0xFF, 0x99, 0x04, 0x05, 0x12, 0xFC, 0x53,
+        0x94, 0xC3, 0x7C, 0x00, 0x04, 0xFF, 0xFC, 0x04, 0x0C, 0xAC, 0x36, 0x42, 0x00, 0xCD, 0xCB,
+        0xB8, 0x33, 0x4C, 0x88, 0x4F, 0xC5,
+    ];
+
+    /// Two RuuviTags, D4:E9:1A:3F:D8:C7 and F6:05:04:03:02:01, around another
+    /// advertiser.
+    pub(super) const LEGACY_TWO_RUUVI: [u8; 127] = [
+        0x04, 0x3E, 0x7C, 0x02, 0x03, 0x03, 0x01, 0xC7, 0xD8, 0x3F, 0x1A, 0xE9, 0xD4, 0x1F, 0x02,
+        0x01, 0x06, 0x1B, 0xFF, 0x99, 0x04, 0x05, 0x12, 0xFC, 0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04,
+        0xFF, 0xFC, 0x04, 0x0C, 0xAC, 0x36, 0x42, 0x00, 0xCD, 0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F,
+        0xC5, 0x03, 0x01, 0x3A, 0x2B, 0x1C, 0x0D, 0x5E, 0x6F, 0x1E, 0x1A, 0xFF, 0x4C, 0x00, 0x02,
+        0x15, 0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10,
+        0x96, 0xE0, 0x00, 0x00, 0x00, 0x00, 0xC5, 0x02, 0x01, 0x06, 0xB4, 0x03, 0x01, 0x01, 0x02,
+        0x03, 0x04, 0x05, 0xF6, 0x1F, 0x02, 0x01, 0x06, 0x1B, 0xFF, 0x99, 0x04, 0x05, 0x12, 0xFC,
+        0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04, 0xFF, 0xFC, 0x04, 0x0C, 0xAC, 0x36, 0x42, 0x00, 0xCD,
+        0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F, 0xBE,
+    ];
+
+    /// Two iBeacons and no RuuviTag.
+    const LEGACY_NO_RUUVI: [u8; 85] = [
+        0x04, 0x3E, 0x52, 0x02, 0x02, 0x03, 0x01, 0x3A, 0x2B, 0x1C, 0x0D, 0x5E, 0x6F, 0x1E, 0x1A,
+        0xFF, 0x4C, 0x00, 0x02, 0x15, 0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60,
+        0xD0, 0xF5, 0xA7, 0x10, 0x96, 0xE0, 0x00, 0x00, 0x00, 0x00, 0xC5, 0x02, 0x01, 0x06, 0xB4,
+        0x03, 0x01, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x1E, 0x1A, 0xFF, 0x4C, 0x00, 0x02, 0x15,
+        0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96,
+        0xE0, 0x00, 0x00, 0x00, 0x00, 0xC5, 0x02, 0x01, 0x06, 0xAE,
+    ];
+
+    /// A RuuviTag broadcasting data format 4 as an Eddystone URL
+    /// (`https://ruu.vi/#BDwYAMFcQ`), at D4:E9:1A:3F:D8:C7.
+    fn legacy_eddystone_report() -> Vec<u8> {
+        let mut ad_data = vec![0x02, 0x01, 0x06, 0x03, 0x03, 0xAA, 0xFE];
+        ad_data.extend([0x17, 0x16, 0xAA, 0xFE, 0x10, 0xEB, 0x03]);
+        ad_data.extend(b"ruu.vi/#BDwYAMFcQ");
+
+        let mut report = vec![0x04, 0x3E, 0x00, 0x02, 0x01, 0x03, 0x01];
+        report.extend([0xC7, 0xD8, 0x3F, 0x1A, 0xE9, 0xD4]);
+        report.push(ad_data.len() as u8);
+        report.extend(ad_data);
+        report.push(0xC5); // RSSI
+        report[2] = (report.len() - 3) as u8;
+        report
+    }
+
+    /// Run a classic BPF program on a packet, like the kernel does for a socket.
+    fn run_bpf(program: &[SockFilter], packet: &[u8]) -> u32 {
+        let mut acc = 0u32;
+        let mut pc = 0;
+        loop {
+            let ins = program[pc];
+            let k = ins.k as usize;
+            match ins.code {
+                c if c == BPF_LD | BPF_B | BPF_ABS => match packet.get(k) {
+                    Some(&byte) => acc = byte.into(),
+                    None => return 0, // Out of bounds loads drop the packet
+                },
+                c if c == BPF_LD | BPF_H | BPF_ABS => match packet.get(k..k + 2) {
+                    Some(half) => acc = u16::from_be_bytes([half[0], half[1]]).into(),
+                    None => return 0,
+                },
+                c if c == BPF_JMP | BPF_JEQ | BPF_K || c == BPF_JMP | BPF_JSET | BPF_K => {
+                    let taken = if c & BPF_JSET != 0 {
+                        acc & ins.k != 0
+                    } else {
+                        acc == ins.k
+                    };
+                    pc += 1 + if taken { ins.jt } else { ins.jf } as usize;
+                    continue;
+                }
+                c if c == BPF_RET | BPF_K => return ins.k,
+                c => panic!("unexpected BPF instruction {c:#x}"),
+            }
+            pc += 1;
+        }
+    }
+
+    #[test]
+    fn test_bpf_program_jumps_stay_in_bounds() {
+        let program = ruuvi_bpf_program();
+        assert!(program.len() <= 4096); // BPF_MAXINSNS
+        for (pc, ins) in program.iter().enumerate() {
+            if ins.code & 0x07 == BPF_JMP {
+                assert!(pc + 1 + (ins.jt as usize) < program.len());
+                assert!(pc + 1 + (ins.jf as usize) < program.len());
+            }
+        }
+        assert_eq!(program.last().unwrap().code, BPF_RET | BPF_K);
+    }
+
+    #[test]
+    fn test_bpf_program_accepts_ruuvi_reports() {
+        let program = ruuvi_bpf_program();
+        let accepts = |packet: &[u8]| run_bpf(&program, packet) != 0;
+
+        assert!(accepts(&LEGACY_RUUVI_LAST));
+        assert!(accepts(&LEGACY_TWO_RUUVI));
+        assert!(!accepts(&LEGACY_NO_RUUVI));
+        assert!(accepts(&EXTENDED_TWO_REPORTS));
+        assert!(accepts(&EXTENDED_FRAGMENT_1));
+        // Fragments of extended PDUs pass even without the manufacturer ID
+        assert!(accepts(&EXTENDED_FRAGMENT_2));
+
+        // A legacy PDU reported by extended scanning is checked for the ID
+        let mut legacy_pdu = EXTENDED_FRAGMENT_2;
+        legacy_pdu[5] = 0x10;
+        assert!(!accepts(&legacy_pdu));
+
+        // Other LE Meta Events and other events are dropped
+        let mut connection_complete = LEGACY_RUUVI_LAST;
+        connection_complete[3] = 0x01;
+        assert!(!accepts(&connection_complete));
+        assert!(!accepts(&[
+            HCI_EVENT_PKT,
+            EVT_CMD_COMPLETE,
+            0x04,
+            0x01,
+            0x0C,
+            0x20,
+            0x00
+        ]));
+    }
+
+    #[test]
+    fn test_parse_legacy_report_with_several_reports() {
+        let results = parse_advertising_report(&LEGACY_RUUVI_LAST, false, &FrameDecoder::default());
+        assert_eq!(results.len(), 1);
+        let measurement = results.into_iter().next().unwrap().unwrap();
+        assert_eq!(measurement.mac, EXAMPLE_MAC);
+        assert_eq!(measurement.phy, Some(Phy::Le1M));
+        assert_eq!(measurement.temperature, Some(24.3));
+
+        let macs: Vec<MacAddress> =
+            parse_advertising_report(&LEGACY_TWO_RUUVI, false, &FrameDecoder::default())
+                .into_iter()
+                .map(|r| r.unwrap().mac)
+                .collect();
+        assert_eq!(
+            macs,
+            vec![
+                EXAMPLE_MAC,
+                MacAddress([0xF6, 0x05, 0x04, 0x03, 0x02, 0x01])
+            ]
+        );
+
+        assert!(
+            parse_advertising_report(&LEGACY_NO_RUUVI, false, &FrameDecoder::default()).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_parse_legacy_eddystone_report() {
+        let report = legacy_eddystone_report();
+        assert_ne!(run_bpf(&ruuvi_bpf_program(), &report), 0);
+        assert!(might_be_ruuvi(&report));
+
+        let results = parse_advertising_report(&report, false, &FrameDecoder::default());
+        assert_eq!(results.len(), 1);
+        let measurement = results.into_iter().next().unwrap().unwrap();
+        assert_eq!(measurement.mac, EXAMPLE_MAC);
+        assert_eq!(measurement.format, Format::V4);
+        assert_eq!(measurement.temperature, Some(24.0));
+        assert_eq!(measurement.tag_id, Some(0x40));
+    }
+
+    #[test]
+    fn test_parse_truncated_legacy_report() {
+        // Cut inside the third report: the RuuviTag in the first is still
+        // decoded, and the second is another advertiser
+        let results =
+            parse_advertising_report(&LEGACY_TWO_RUUVI[..100], false, &FrameDecoder::default());
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_ok());
+
+        let results =
+            parse_advertising_report(&LEGACY_TWO_RUUVI[..100], true, &FrameDecoder::default());
+        assert!(matches!(
+            &results[..],
+            [Ok(_), Err(DecodeError::InvalidData(e))] if e == "Advertising report truncated"
+        ));
+
+        let results =
+            parse_advertising_report(&LEGACY_TWO_RUUVI[..8], true, &FrameDecoder::default());
+        assert!(matches!(results[..], [Err(DecodeError::InvalidData(_))]));
     }
 }
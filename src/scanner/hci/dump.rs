@@ -0,0 +1,180 @@
+//! Replay of HCI traffic from btsnoop and pcap files.
+//!
+//! The `hci-dump` backend reads a file written with `--hci-dump`, by `btmon -w`
+//! or by Wireshark, and runs its LE advertising report events through the same
+//! parsers and decoding as the HCI backend. Measurements are timestamped with
+//! the time the events were captured.
+
+use super::reassembly::{FRAGMENT_TIMEOUT, Reassembler};
+use super::{
+    EVT_LE_ADVERTISING_REPORT, EVT_LE_EXTENDED_ADVERTISING_REPORT, EVT_LE_META_EVENT,
+    HCI_EVENT_PKT, parse_advertising_report, parse_extended_advertising_report,
+};
+use crate::hci_dump::DumpReader;
+use crate::scanner::replay::Pacer;
+use crate::scanner::{
+    DecodeError, FrameDecoder, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, ScanConfig,
+    ScanError,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tokio::sync::mpsc;
+
+/// Start replaying the HCI traffic in the btsnoop or pcap file at `path`.
+///
+/// The returned channel is closed once the whole file has been replayed. A
+/// file that ends in the middle of a packet is reported as a decode error if
+/// `config.verbose` is set.
+pub async fn start_replay(
+    path: &Path,
+    realtime: bool,
+    config: &ScanConfig,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let name = path.display().to_string();
+    let mut reader = File::open(path)
+        .and_then(|file| DumpReader::new(BufReader::new(file)))
+        .map_err(|e| ScanError::Replay(format!("{name}: {e}")))?;
+    let verbose = config.verbose;
+
+    let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    config.spawn(|config| async move {
+        let mut pacer = Pacer::new(realtime);
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        // Fragments time out by the capture's clock, not by how fast it is read
+        let started = Instant::now();
+        let mut first: Option<SystemTime> = None;
+
+        'packets: loop {
+            let packet = match reader.next_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => break,
+                Err(e) => {
+                    if verbose {
+                        let err = DecodeError::InvalidData(format!("{name}: {e}"));
+                        let _ = tx.send(Err(err)).await;
+                    }
+                    break;
+                }
+            };
+            let data = &packet.data;
+            if data.len() < 4 || data[0] != HCI_EVENT_PKT || data[1] != EVT_LE_META_EVENT {
+                continue;
+            }
+            if !pacer.wait(packet.timestamp, &tx).await {
+                break;
+            }
+
+            let first = *first.get_or_insert(packet.timestamp);
+            let now = started + packet.timestamp.duration_since(first).unwrap_or_default();
+            let adapter = packet.adapter.map(|index| Arc::from(format!("hci{index}")));
+            let decoder = FrameDecoder::new(adapter, config.recorder.clone());
+            let results = match data[3] {
+                EVT_LE_ADVERTISING_REPORT => parse_advertising_report(data, verbose, &decoder),
+                EVT_LE_EXTENDED_ADVERTISING_REPORT => parse_extended_advertising_report(
+                    data,
+                    verbose,
+                    &decoder,
+                    &mut reassembler,
+                    now,
+                ),
+                _ => continue,
+            };
+
+            for result in results {
+                let result = result.map(|mut measurement| {
+                    measurement.timestamp = packet.timestamp;
+                    measurement
+                });
+                if (result.is_ok() || verbose) && tx.send(result).await.is_err() {
+                    break 'packets;
+                }
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{
+        EXAMPLE_MAC, EXTENDED_FRAGMENT_1, EXTENDED_FRAGMENT_2, LEGACY_TWO_RUUVI,
+    };
+    use super::*;
+    use crate::hci_dump::{DumpFormat, DumpWriter};
+    use crate::mac_address::MacAddress;
+    use crate::test_utils::{TempFile, collect};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Write `events` to a dump file unique to the calling test, 100 ms apart.
+    fn dump(test: &str, format: DumpFormat, events: &[&[u8]]) -> TempFile {
+        let path = TempFile::new(&format!("{test}.dump"));
+        let writer = DumpWriter::create(&path, format).unwrap();
+        for (i, event) in events.iter().enumerate() {
+            writer.write_event(
+                event,
+                UNIX_EPOCH + Duration::from_millis(100 * (i as u64 + 1)),
+            );
+        }
+        path
+    }
+
+    #[tokio::test]
+    async fn test_replay_legacy_reports() {
+        for format in [DumpFormat::Btsnoop, DumpFormat::Pcap] {
+            let path = dump("hci-dump-legacy", format, &[&LEGACY_TWO_RUUVI]);
+            let rx = start_replay(&path, false, &ScanConfig::default())
+                .await
+                .unwrap();
+            let results = collect(rx).await;
+
+            let measurements: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+            assert_eq!(measurements.len(), 2);
+            assert_eq!(measurements[0].mac, EXAMPLE_MAC);
+            assert_eq!(
+                measurements[1].mac,
+                MacAddress([0xF6, 0x05, 0x04, 0x03, 0x02, 0x01])
+            );
+            assert_eq!(
+                measurements[0].timestamp,
+                UNIX_EPOCH + Duration::from_millis(100)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_reassembles_extended_reports() {
+        let path = dump(
+            "hci-dump-extended",
+            DumpFormat::Btsnoop,
+            &[&EXTENDED_FRAGMENT_1, &EXTENDED_FRAGMENT_2],
+        );
+        let rx = start_replay(&path, false, &ScanConfig::default())
+            .await
+            .unwrap();
+        let results = collect(rx).await;
+
+        // The fragments are 100 ms apart, within the reassembly timeout
+        assert_eq!(results.len(), 1);
+        let measurement = results[0].as_ref().unwrap();
+        assert_eq!(measurement.mac, EXAMPLE_MAC);
+        assert_eq!(
+            measurement.timestamp,
+            UNIX_EPOCH + Duration::from_millis(200)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_rejects_other_files() {
+        let path = TempFile::new("hci-dump-invalid.dump");
+        std::fs::write(&path, "{}\n").unwrap();
+        let err = start_replay(&path, false, &ScanConfig::default())
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().ends_with(": not a btsnoop or pcap file"));
+    }
+}
@@ -0,0 +1,242 @@
+//! Reassembly of extended advertising data split across several HCI events.
+//!
+//! Controllers report extended advertisements whose data does not fit into one
+//! LE Extended Advertising Report event as a chain of reports. All but the last
+//! report of a chain are marked "incomplete, more data to follow" in the data
+//! status bits of their event type. Fragments are collected per advertiser,
+//! identified by address and advertising SID, until the chain completes. Chains
+//! whose remaining fragments never arrive are dropped after a timeout.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the remaining fragments of a chain.
+pub const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest extended advertising data allowed by the specification.
+const MAX_ADVERTISING_DATA: usize = 1650;
+
+/// Most chains collected at the same time; the oldest is dropped beyond that.
+const MAX_PENDING: usize = 32;
+
+/// Data status of an extended advertising report (event type bits 5-6).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataStatus {
+    /// The report completes the advertising data.
+    Complete,
+    /// More reports with the rest of the data follow.
+    MoreToFollow,
+    /// The data was truncated; the rest will not be reported.
+    Truncated,
+}
+
+impl DataStatus {
+    /// Read the data status from a report's event type.
+    pub fn from_event_type(event_type: u16) -> Self {
+        match (event_type >> 5) & 0b11 {
+            0b00 => DataStatus::Complete,
+            0b01 => DataStatus::MoreToFollow,
+            // 0b11 is reserved; treat it like truncated data
+            _ => DataStatus::Truncated,
+        }
+    }
+}
+
+/// Identifies the advertiser a fragment belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentKey {
+    /// Advertiser address as sent by the controller (little-endian)
+    pub address: [u8; 6],
+    /// Advertiser address type
+    pub address_type: u8,
+    /// Advertising set ID
+    pub sid: u8,
+}
+
+/// Advertising data collected so far for one chain.
+#[derive(Debug)]
+struct Pending {
+    data: Vec<u8>,
+    started: Instant,
+}
+
+/// Collects fragmented extended advertising data per advertiser.
+#[derive(Debug)]
+pub struct Reassembler {
+    pending: HashMap<FragmentKey, Pending>,
+    timeout: Duration,
+}
+
+impl Reassembler {
+    /// Create a reassembler dropping incomplete chains after `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            pending: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Add the data of one report received at `now`.
+    ///
+    /// Returns the complete advertising data once the report completes a
+    /// chain, borrowing `data` when the report was not fragmented.
+    pub fn push<'a>(
+        &mut self,
+        key: FragmentKey,
+        status: DataStatus,
+        data: &'a [u8],
+        now: Instant,
+    ) -> Option<Cow<'a, [u8]>> {
+        if !self.pending.is_empty() {
+            let timeout = self.timeout;
+            self.pending
+                .retain(|_, pending| now.duration_since(pending.started) < timeout);
+        }
+
+        match status {
+            DataStatus::Complete => match self.pending.remove(&key) {
+                Some(mut pending) => {
+                    pending.data.extend_from_slice(data);
+                    Some(Cow::Owned(pending.data))
+                }
+                None => Some(Cow::Borrowed(data)),
+            },
+            DataStatus::MoreToFollow => {
+                if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING {
+                    self.drop_oldest();
+                }
+                let pending = self.pending.entry(key).or_insert_with(|| Pending {
+                    data: Vec::new(),
+                    started: now,
+                });
+                if pending.data.len() + data.len() > MAX_ADVERTISING_DATA {
+                    self.pending.remove(&key);
+                    return None;
+                }
+                pending.data.extend_from_slice(data);
+                None
+            }
+            DataStatus::Truncated => {
+                self.pending.remove(&key);
+                None
+            }
+        }
+    }
+
+    /// Drop the chain that started first.
+    fn drop_oldest(&mut self) {
+        let oldest = self
+            .pending
+            .iter()
+            .min_by_key(|(_, pending)| pending.started)
+            .map(|(key, _)| *key);
+        if let Some(key) = oldest {
+            self.pending.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: FragmentKey = FragmentKey {
+        address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+        address_type: 0x01,
+        sid: 0x02,
+    };
+
+    #[test]
+    fn test_data_status_from_event_type() {
+        assert_eq!(DataStatus::from_event_type(0x0013), DataStatus::Complete);
+        assert_eq!(
+            DataStatus::from_event_type(0x0020),
+            DataStatus::MoreToFollow
+        );
+        assert_eq!(DataStatus::from_event_type(0x0040), DataStatus::Truncated);
+    }
+
+    #[test]
+    fn test_unfragmented_data_is_borrowed() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let data = [0x02, 0x01, 0x06];
+
+        let complete = reassembler.push(KEY, DataStatus::Complete, &data, Instant::now());
+        assert!(matches!(complete, Some(Cow::Borrowed(d)) if d == data));
+    }
+
+    #[test]
+    fn test_fragments_are_joined_per_advertiser() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let other = FragmentKey { sid: 0x03, ..KEY };
+        let now = Instant::now();
+
+        assert!(
+            reassembler
+                .push(KEY, DataStatus::MoreToFollow, &[1, 2], now)
+                .is_none()
+        );
+        assert!(
+            reassembler
+                .push(other, DataStatus::MoreToFollow, &[9], now)
+                .is_none()
+        );
+        assert!(
+            reassembler
+                .push(KEY, DataStatus::MoreToFollow, &[3], now)
+                .is_none()
+        );
+
+        let complete = reassembler.push(KEY, DataStatus::Complete, &[4], now);
+        assert_eq!(complete.as_deref(), Some(&[1, 2, 3, 4][..]));
+        let complete = reassembler.push(other, DataStatus::Complete, &[8], now);
+        assert_eq!(complete.as_deref(), Some(&[9, 8][..]));
+    }
+
+    #[test]
+    fn test_truncated_chain_is_dropped() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let now = Instant::now();
+
+        reassembler.push(KEY, DataStatus::MoreToFollow, &[1, 2], now);
+        assert!(
+            reassembler
+                .push(KEY, DataStatus::Truncated, &[3], now)
+                .is_none()
+        );
+        // A new advertisement starts from scratch
+        let complete = reassembler.push(KEY, DataStatus::Complete, &[5], now);
+        assert_eq!(complete.as_deref(), Some(&[5][..]));
+    }
+
+    #[test]
+    fn test_stale_fragments_expire() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let now = Instant::now();
+
+        reassembler.push(KEY, DataStatus::MoreToFollow, &[1, 2], now);
+        let later = now + FRAGMENT_TIMEOUT;
+        let complete = reassembler.push(KEY, DataStatus::Complete, &[3], later);
+        assert_eq!(complete.as_deref(), Some(&[3][..]));
+    }
+
+    #[test]
+    fn test_pending_chains_are_bounded() {
+        let mut reassembler = Reassembler::new(FRAGMENT_TIMEOUT);
+        let now = Instant::now();
+
+        for sid in 0..=MAX_PENDING as u8 {
+            let key = FragmentKey { sid, ..KEY };
+            let at = now + Duration::from_millis(sid.into());
+            reassembler.push(key, DataStatus::MoreToFollow, &[sid], at);
+        }
+        assert_eq!(reassembler.pending.len(), MAX_PENDING);
+        // The oldest chain made room for the newest
+        assert!(
+            !reassembler
+                .pending
+                .contains_key(&FragmentKey { sid: 0, ..KEY })
+        );
+    }
+}
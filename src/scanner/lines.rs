@@ -0,0 +1,352 @@
+//! Ruuvi payloads read as text lines from another program.
+//!
+//! The hex backend does not use the radio. It reads one advertisement per line
+//! from stdin, a file or FIFO, or a TCP connection, e.g. from a BLE gateway
+//! that forwards what it receives:
+//!
+//! ```text
+//! D4:E9:1A:3F:D8:C7,0512fc5394c37c0004fffc040cac364200cdcbb8334c884f
+//! D4:E9:1A:3F:D8:C7,99040512fc5394c37c0004fffc040cac364200cdcbb8334c884f,-61
+//! ```
+//!
+//! Each line holds the tag's address, its manufacturer data as hex, and
+//! optionally the RSSI in dBm, separated by commas. The manufacturer data may
+//! include the Ruuvi company ID (`9904`) or start right after it. Blank lines
+//! and lines starting with `#` are skipped. Measurements are timestamped when
+//! their line is read.
+
+use super::{
+    DecodeError, FrameDecoder, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult,
+    RUUVI_MANUFACTURER_ID_BYTES, ScanConfig, ScanError,
+};
+use crate::capture::from_hex;
+use crate::mac_address::MacAddress;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::TcpStream;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// Where the hex backend reads lines from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineSource {
+    /// Standard input
+    Stdin,
+    /// A file or FIFO
+    File(std::path::PathBuf),
+    /// A TCP connection to `host:port`
+    Tcp(String),
+}
+
+impl LineSource {
+    /// The source named by `--input`: stdin if absent or `-`, a TCP
+    /// connection for `tcp://HOST:PORT`, and a file otherwise.
+    pub fn from_input(input: Option<&Path>) -> Self {
+        let Some(input) = input else {
+            return LineSource::Stdin;
+        };
+        match input.to_str() {
+            Some("-") => LineSource::Stdin,
+            Some(input) if input.starts_with("tcp://") => {
+                LineSource::Tcp(input["tcp://".len()..].to_string())
+            }
+            _ => LineSource::File(input.to_path_buf()),
+        }
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Read + Send>> {
+        match self {
+            LineSource::Stdin => Ok(Box::new(io::stdin())),
+            LineSource::File(path) => Ok(Box::new(File::open(path)?)),
+            LineSource::Tcp(address) => Ok(Box::new(TcpStream::connect(address)?)),
+        }
+    }
+}
+
+impl std::fmt::Display for LineSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineSource::Stdin => write!(f, "stdin"),
+            LineSource::File(path) => write!(f, "{}", path.display()),
+            LineSource::Tcp(address) => write!(f, "tcp://{address}"),
+        }
+    }
+}
+
+/// Start reading lines from `source`.
+///
+/// The returned channel is closed when the input ends, e.g. at the end of a
+/// file or when the TCP connection is closed. Failing to open or read the
+/// input is reported as a fatal [`ScanError::Input`]. Lines that cannot be
+/// parsed are sent as decode errors if `config.verbose` is set, like frames
+/// that fail to decode.
+pub async fn start_lines(
+    source: LineSource,
+    config: &ScanConfig,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let name = source.to_string();
+    let verbose = config.verbose;
+    let decoder = FrameDecoder::new(None, config.recorder.clone());
+
+    // Reading blocks, and opening a FIFO blocks until it has a writer, so
+    // that happens on a thread of its own. The thread is left behind on
+    // shutdown if it is still waiting for input.
+    let (line_tx, mut line_rx) =
+        mpsc::channel::<io::Result<String>>(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    std::thread::spawn(move || read_lines(&source, &line_tx));
+
+    let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    config.spawn(|config| async move {
+        let mut number = 0;
+        loop {
+            let line = tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                line = line_rx.recv() => match line {
+                    Some(Ok(line)) => line,
+                    Some(Err(e)) => {
+                        config.report_fatal(ScanError::Input(format!("{name}: {e}")));
+                        break;
+                    }
+                    None => break,
+                },
+            };
+            number += 1;
+
+            let result = match parse_line(&line) {
+                Ok(Some(line)) => decoder.decode(line.mac, line.rssi, None, &line.data),
+                Ok(None) => continue,
+                Err(e) => Err(DecodeError::InvalidData(format!("{name}:{number}: {e}"))),
+            };
+            if (result.is_ok() || verbose) && tx.send(result).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Send the lines of `source` to `tx` until it ends or `tx` is closed.
+fn read_lines(source: &LineSource, tx: &mpsc::Sender<io::Result<String>>) {
+    let mut reader = match source.open() {
+        Ok(input) => BufReader::new(input),
+        Err(e) => {
+            let _ = tx.blocking_send(Err(e));
+            return;
+        }
+    };
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let line = match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return,
+            Ok(_) => Ok(String::from_utf8_lossy(&line).into_owned()),
+            Err(e) => Err(e),
+        };
+        let failed = line.is_err();
+        if tx.blocking_send(line).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// An advertisement read from a line.
+#[derive(Debug, PartialEq)]
+struct Line {
+    mac: MacAddress,
+    rssi: Option<i16>,
+    /// Manufacturer data after the company ID
+    data: Vec<u8>,
+}
+
+/// Parse a `MAC,HEX[,RSSI]` line. Returns `None` for blank lines and comments.
+fn parse_line(line: &str) -> Result<Option<Line>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut fields = line.split(',').map(str::trim);
+    let mac = fields.next().unwrap_or_default();
+    let mac: MacAddress = mac.parse().map_err(|e| format!("{e}"))?;
+    let hex = fields
+        .next()
+        .ok_or_else(|| "expected MAC,HEX[,RSSI]".to_string())?;
+    let data = from_hex(hex).ok_or_else(|| format!("invalid hex data {hex:?}"))?;
+    let rssi = match fields.next() {
+        Some(rssi) => Some(rssi.parse().map_err(|_| format!("invalid RSSI {rssi:?}"))?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return Err("expected MAC,HEX[,RSSI]".to_string());
+    }
+
+    let data = match data.strip_prefix(&RUUVI_MANUFACTURER_ID_BYTES) {
+        Some(data) => data.to_vec(),
+        None => data,
+    };
+    Ok(Some(Line { mac, rssi, data }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::Format;
+    use crate::test_utils::{TEST_MAC, TempFile, collect};
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    const V5_DATA: &str = "0512fc5394c37c0004fffc040cac364200cdcbb8334c884f";
+
+    /// Write `contents` to a file unique to the calling test.
+    fn input(test: &str, contents: &str) -> TempFile {
+        let path = TempFile::new(&format!("lines-{test}.txt"));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let line = parse_line(&format!("AA:BB:CC:DD:EE:FF,{V5_DATA}\n"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            line,
+            Line {
+                mac: TEST_MAC,
+                rssi: None,
+                data: from_hex(V5_DATA).unwrap(),
+            }
+        );
+
+        let with_company_id = parse_line(&format!(" AA:BB:CC:DD:EE:FF , 9904{V5_DATA} , -61 "))
+            .unwrap()
+            .unwrap();
+        assert_eq!(with_company_id.rssi, Some(-61));
+        assert_eq!(with_company_id.data, line.data);
+
+        assert_eq!(parse_line("  \r\n"), Ok(None));
+        assert_eq!(parse_line("# gateway 1"), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_line_errors() {
+        assert_eq!(
+            parse_line("AA:BB:CC:DD:EE:FF"),
+            Err("expected MAC,HEX[,RSSI]".to_string())
+        );
+        assert_eq!(
+            parse_line("AA:BB:CC:DD:EE:FF,0512f"),
+            Err(r#"invalid hex data "0512f""#.to_string())
+        );
+        assert_eq!(
+            parse_line("AA:BB:CC:DD:EE:FF,05,loud"),
+            Err(r#"invalid RSSI "loud""#.to_string())
+        );
+        assert_eq!(
+            parse_line("AA:BB:CC:DD:EE:FF,05,-61,1"),
+            Err("expected MAC,HEX[,RSSI]".to_string())
+        );
+        assert!(parse_line("not-a-mac,05").is_err());
+    }
+
+    #[test]
+    fn test_line_source_from_input() {
+        assert_eq!(LineSource::from_input(None), LineSource::Stdin);
+        assert_eq!(
+            LineSource::from_input(Some(Path::new("-"))),
+            LineSource::Stdin
+        );
+        assert_eq!(
+            LineSource::from_input(Some(Path::new("tcp://gateway:8080"))),
+            LineSource::Tcp("gateway:8080".to_string())
+        );
+        assert_eq!(
+            LineSource::from_input(Some(Path::new("/run/ruuvi.fifo"))),
+            LineSource::File("/run/ruuvi.fifo".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lines_from_file() {
+        let path = input(
+            "file",
+            &format!("# header\nAA:BB:CC:DD:EE:FF,{V5_DATA},-61\nAA:BB:CC:DD:EE:FF,zz\n"),
+        );
+        let config = ScanConfig {
+            verbose: true,
+            ..Default::default()
+        };
+        let results = collect(
+            start_lines(LineSource::File(path.to_path_buf()), &config)
+                .await
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(results.len(), 2);
+        let measurement = results[0].as_ref().unwrap();
+        assert_eq!(measurement.mac, TEST_MAC);
+        assert_eq!(measurement.format, Format::V5);
+        assert_eq!(measurement.temperature, Some(24.3));
+        let err = results[1].as_ref().unwrap_err().to_string();
+        assert!(err.ends_with(r#":3: invalid hex data "zz""#), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_lines_skip_errors_unless_verbose() {
+        let path = input("quiet", &format!("garbage\nAA:BB:CC:DD:EE:FF,{V5_DATA}\n"));
+        let results = collect(
+            start_lines(LineSource::File(path.to_path_buf()), &ScanConfig::default())
+                .await
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_lines_from_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "AA:BB:CC:DD:EE:FF,{V5_DATA}").unwrap();
+        });
+
+        let results = collect(
+            start_lines(LineSource::Tcp(address), &ScanConfig::default())
+                .await
+                .unwrap(),
+        )
+        .await;
+        server.join().unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap().mac, TEST_MAC);
+    }
+
+    #[tokio::test]
+    async fn test_missing_input_is_fatal() {
+        let (fatal_tx, mut fatal_rx) = mpsc::unbounded_channel();
+        let config = ScanConfig {
+            fatal_errors: Some(fatal_tx),
+            ..Default::default()
+        };
+        let rx = start_lines(LineSource::File("/nonexistent/lines".into()), &config)
+            .await
+            .unwrap();
+        drop(config);
+
+        assert!(collect(rx).await.is_empty());
+        let err = fatal_rx.recv().await.unwrap();
+        assert!(matches!(err, ScanError::Input(_)));
+        assert!(
+            err.to_string()
+                .starts_with("Input error: /nonexistent/lines: ")
+        );
+    }
+}
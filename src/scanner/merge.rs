@@ -0,0 +1,203 @@
+//! Merging of measurement streams from several adapters.
+//!
+//! When scanning with more than one adapter, a RuuviTag in range of several
+//! of them is received once per adapter. [`Deduplicator`] collapses these
+//! copies so each advertisement is emitted once, by the adapter that received
+//! it first.
+
+use super::{MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult};
+use crate::mac_address::MacAddress;
+use crate::measurement::Measurement;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How long after a reading identical readings from the same tag are
+/// considered copies of the same advertisement.
+///
+/// Copies received by different adapters arrive within milliseconds, while
+/// RuuviTags advertise new data at most every 100 ms and usually much less
+/// often.
+pub const DUPLICATE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Collapses copies of the same advertisement received by several adapters.
+pub struct Deduplicator {
+    window: Duration,
+    last_seen: HashMap<MacAddress, (Measurement, Instant)>,
+}
+
+impl Deduplicator {
+    /// Create a deduplicator treating identical readings within `window` as copies.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Check whether a measurement is new, or a copy of one already emitted.
+    ///
+    /// Readings are compared without their timestamp and adapter, which
+    /// differ between copies of the same advertisement.
+    pub fn is_new(&mut self, measurement: &Measurement) -> bool {
+        self.is_new_at(measurement, Instant::now())
+    }
+
+    fn is_new_at(&mut self, measurement: &Measurement, now: Instant) -> bool {
+        if let Some((last, seen_at)) = self.last_seen.get(&measurement.mac)
+            && now.duration_since(*seen_at) < self.window
+            && same_reading(last, measurement)
+        {
+            return false;
+        }
+        self.last_seen
+            .insert(measurement.mac, (measurement.clone(), now));
+        true
+    }
+}
+
+/// Whether two measurements carry the same sensor data.
+fn same_reading(a: &Measurement, b: &Measurement) -> bool {
+    let a = Measurement {
+        timestamp: b.timestamp,
+        adapter: b.adapter.clone(),
+        ..a.clone()
+    };
+    a == *b
+}
+
+/// Merge measurement streams into one, optionally collapsing duplicates.
+///
+/// Decode errors are passed through as is. The merged stream ends once all
+/// input streams have ended. Dropping the merged receiver drops the input
+/// receivers too, so their backends stop scanning.
+pub fn merge(
+    receivers: Vec<mpsc::Receiver<MeasurementResult>>,
+    mut deduplicator: Option<Deduplicator>,
+) -> mpsc::Receiver<MeasurementResult> {
+    let (merged_tx, mut merged_rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    for mut rx in receivers {
+        let merged_tx = merged_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let result = tokio::select! {
+                    biased;
+                    _ = merged_tx.closed() => break,
+                    result = rx.recv() => result,
+                };
+                let Some(result) = result else {
+                    break;
+                };
+                if merged_tx.send(result).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(merged_tx);
+
+    let (tx, out) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    tokio::spawn(async move {
+        loop {
+            let result = tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                result = merged_rx.recv() => result,
+            };
+            let Some(result) = result else {
+                break;
+            };
+            if let (Ok(measurement), Some(deduplicator)) = (&result, deduplicator.as_mut())
+                && !deduplicator.is_new(measurement)
+            {
+                continue;
+            }
+            if tx.send(result).await.is_err() {
+                break;
+            }
+        }
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TEST_MAC, base_measurement};
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    fn reading(adapter: &str, temperature: f64) -> Measurement {
+        let mut m = base_measurement(TEST_MAC, SystemTime::now());
+        m.adapter = Some(Arc::from(adapter));
+        m.temperature = Some(temperature);
+        m
+    }
+
+    #[test]
+    fn test_copies_from_other_adapters_are_collapsed() {
+        let mut dedup = Deduplicator::new(DUPLICATE_WINDOW);
+        let now = Instant::now();
+
+        assert!(dedup.is_new_at(&reading("hci0", 21.0), now));
+        assert!(!dedup.is_new_at(&reading("hci1", 21.0), now));
+        // Different data is a new advertisement
+        assert!(dedup.is_new_at(&reading("hci1", 21.5), now));
+    }
+
+    #[test]
+    fn test_identical_readings_after_window_are_kept() {
+        let mut dedup = Deduplicator::new(DUPLICATE_WINDOW);
+        let now = Instant::now();
+
+        assert!(dedup.is_new_at(&reading("hci0", 21.0), now));
+        assert!(dedup.is_new_at(&reading("hci0", 21.0), now + DUPLICATE_WINDOW));
+    }
+
+    #[tokio::test]
+    async fn test_merge_collapses_duplicates() {
+        let (tx0, rx0) = mpsc::channel(4);
+        let (tx1, rx1) = mpsc::channel(4);
+        tx0.send(Ok(reading("hci0", 21.0))).await.unwrap();
+        tx1.send(Ok(reading("hci1", 21.0))).await.unwrap();
+        tx1.send(Ok(reading("hci1", 22.0))).await.unwrap();
+        drop((tx0, tx1));
+
+        let mut rx = merge(vec![rx0, rx1], Some(Deduplicator::new(DUPLICATE_WINDOW)));
+        let mut temperatures = Vec::new();
+        while let Some(result) = rx.recv().await {
+            temperatures.push(result.unwrap().temperature.unwrap());
+        }
+        temperatures.sort_by(f64::total_cmp);
+
+        assert_eq!(temperatures, vec![21.0, 22.0]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_duplicates_without_deduplicator() {
+        let (tx0, rx0) = mpsc::channel(4);
+        let (tx1, rx1) = mpsc::channel(4);
+        tx0.send(Ok(reading("hci0", 21.0))).await.unwrap();
+        tx1.send(Ok(reading("hci1", 21.0))).await.unwrap();
+        drop((tx0, tx1));
+
+        let mut rx = merge(vec![rx0, rx1], None);
+        let mut count = 0;
+        while rx.recv().await.is_some() {
+            count += 1;
+        }
+
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_merged_stream_drops_inputs() {
+        let (tx0, rx0) = mpsc::channel(1);
+        let (tx1, rx1) = mpsc::channel(1);
+
+        drop(merge(vec![rx0, rx1], None));
+
+        tx0.closed().await;
+        tx1.closed().await;
+    }
+}
@@ -6,20 +6,41 @@
 #[cfg(feature = "bluer")]
 pub mod bluer;
 
+pub mod eddystone;
+#[cfg(any(feature = "mqtt", feature = "gateway"))]
+pub mod gateway;
 #[cfg(feature = "hci")]
 pub mod hci;
 
+pub mod lines;
+pub mod merge;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod params;
+pub mod replay;
+pub mod simulate;
+pub mod supervisor;
+pub mod watchdog;
+
+use crate::capture::Recorder;
+use crate::hci_dump::DumpWriter;
 use crate::mac_address::MacAddress;
-use crate::measurement::{Format, Measurement};
+use crate::measurement::{Format, Measurement, Phy};
+use params::ScanParameters;
 use ruuvi_decoders::{e1, v5, v6};
-use std::time::SystemTime;
+#[cfg(any(feature = "hci", feature = "mqtt", feature = "gateway"))]
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
 /// Error types for decoding RuuviTag data.
 #[derive(Error, Debug, Clone, PartialEq)]
 pub enum DecodeError {
-    /// Unsupported RuuviTag data format (e.g., V2, V3, V4 when only V5 is supported)
+    /// Unsupported RuuviTag data format (e.g., the encrypted format 8)
     #[error("Unsupported format: {0}")]
     UnsupportedFormat(String),
     /// Invalid or corrupted data that cannot be decoded
@@ -46,6 +67,186 @@ pub enum ScanError {
     #[allow(dead_code)]
     #[error("Backend '{0}' not available (not compiled in)")]
     BackendNotAvailable(String),
+    /// The requested Bluetooth adapter does not exist
+    #[error(
+        "Bluetooth adapter '{requested}' not found (available: {})",
+        if available.is_empty() { "none".to_string() } else { available.join(", ") }
+    )]
+    AdapterNotFound {
+        /// The adapter that was asked for
+        requested: String,
+        /// Adapters present on the system, e.g. `hci0 (AA:BB:CC:DD:EE:FF)`
+        available: Vec<String>,
+    },
+    /// The scan parameters are invalid or not supported by the controller
+    #[error("Unsupported scan parameters: {0}")]
+    UnsupportedScanParameters(String),
+    /// A capture could not be replayed
+    #[error("Replay error: {0}")]
+    Replay(String),
+    /// The input of the hex backend could not be read
+    #[error("Input error: {0}")]
+    Input(String),
+    /// The MQTT broker could not be used
+    #[error("MQTT error: {0}")]
+    Mqtt(String),
+    /// The Ruuvi Gateway could not be polled
+    #[error("Gateway error: {0}")]
+    Gateway(String),
+    /// The simulated tags could not be started
+    #[error("Simulation error: {0}")]
+    Simulate(String),
+    /// The no-data watchdog gave up after restarting the scan did not help
+    #[error(
+        "No RuuviTag advertisements received on {adapter} for {idle:?} after {restarts} scan restarts"
+    )]
+    NoData {
+        /// Name of the adapter, e.g. `hci0`
+        adapter: String,
+        /// How long the watchdog waits for frames
+        idle: Duration,
+        /// Number of scan restarts that did not help
+        restarts: u32,
+    },
+}
+
+/// Identifies a Bluetooth adapter by name (`hci1`) or controller address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterId {
+    /// Kernel device index, i.e. `N` in `hciN`
+    Index(u16),
+    /// Controller (public) address
+    Address(MacAddress),
+}
+
+impl std::fmt::Display for AdapterId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdapterId::Index(index) => write!(f, "hci{}", index),
+            AdapterId::Address(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+impl std::str::FromStr for AdapterId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(index) = s.strip_prefix("hci") {
+            return index
+                .parse()
+                .map(AdapterId::Index)
+                .map_err(|_| format!("Invalid adapter name: {}", s));
+        }
+        s.parse().map(AdapterId::Address).map_err(|_| {
+            format!(
+                "Invalid adapter: {} (expected e.g. hci1 or a controller address)",
+                s
+            )
+        })
+    }
+}
+
+/// Describe an adapter for [`ScanError::AdapterNotFound`], e.g. `hci0 (AA:BB:CC:DD:EE:FF)`.
+#[cfg(any(feature = "bluer", feature = "hci"))]
+fn describe_adapter(name: &str, address: MacAddress) -> String {
+    format!("{} ({})", name, address)
+}
+
+/// Settings shared by all scanner backends.
+#[derive(Debug, Clone, Default)]
+pub struct ScanConfig {
+    /// If true, decode errors are sent as Err values; otherwise they're silently dropped.
+    pub verbose: bool,
+    /// Adapters to scan with; the system default adapter if empty.
+    pub adapters: Vec<AdapterId>,
+    /// When scanning with several adapters, emit every copy of an
+    /// advertisement instead of only the first one received.
+    pub keep_duplicates: bool,
+    /// LE scan timing and type, for backends that configure the controller.
+    pub scan: ScanParameters,
+    /// Restart scanning if no RuuviTag frames arrive for this long.
+    pub watchdog: Option<Duration>,
+    /// Number of watchdog restarts without frames before giving up.
+    pub watchdog_retries: u32,
+    /// Where backends report errors that end scanning for good, such as the
+    /// watchdog giving up. Such errors are not retried by the supervisor.
+    pub fatal_errors: Option<mpsc::UnboundedSender<ScanError>>,
+    /// Where backends record every received Ruuvi frame, if anywhere.
+    pub recorder: Option<Recorder>,
+    /// Where the HCI backend writes the advertising report events it reads,
+    /// if anywhere.
+    pub hci_dump: Option<DumpWriter>,
+    /// File the replay and hci-dump backends replay, or what the hex backend
+    /// reads lines from (see [`lines::LineSource::from_input`]).
+    pub input: Option<PathBuf>,
+    /// Replay with the spacing the input was recorded with, instead of as
+    /// fast as possible.
+    pub replay_realtime: bool,
+    /// Broker and topic the mqtt backend subscribes to.
+    #[cfg(feature = "mqtt")]
+    pub mqtt: mqtt::MqttConfig,
+    /// Gateway the gateway backend polls.
+    #[cfg(feature = "gateway")]
+    pub gateway: gateway::http::GatewayConfig,
+    /// Virtual tags the simulate backend makes up.
+    pub simulate: simulate::SimulateConfig,
+    /// The tasks backends scan in, so shutdown can wait for them to stop.
+    pub tasks: ScanTasks,
+}
+
+/// Handles of the tasks backends scan in.
+///
+/// Backends spawn their scan task with [`ScanTasks::spawn`], so that
+/// [`ScanTasks::join`] can wait for them to clean up, e.g. to disable LE
+/// scanning, after their receiver has been dropped. Clones share the tasks.
+#[derive(Debug, Clone, Default)]
+pub struct ScanTasks(Arc<Mutex<Vec<JoinHandle<()>>>>);
+
+impl ScanTasks {
+    /// Spawn a scan task.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(task);
+        let mut handles = self.0.lock().unwrap();
+        handles.retain(|handle| !handle.is_finished());
+        handles.push(handle);
+    }
+
+    /// Wait until all scan tasks have finished, including those spawned while
+    /// waiting, e.g. by a supervisor restarting a backend.
+    pub async fn join(&self) {
+        loop {
+            let handles = std::mem::take(&mut *self.0.lock().unwrap());
+            if handles.is_empty() {
+                return;
+            }
+            for handle in handles {
+                let _ = handle.await;
+            }
+        }
+    }
+}
+
+impl ScanConfig {
+    /// Report an error that ends scanning for good.
+    pub fn report_fatal(&self, error: ScanError) {
+        if let Some(fatal_errors) = &self.fatal_errors {
+            let _ = fatal_errors.send(error);
+        }
+    }
+
+    /// Spawn a backend's scan task in [`ScanConfig::tasks`], handing it a copy
+    /// of the config.
+    pub fn spawn<F, Fut>(&self, task: F)
+    where
+        F: FnOnce(ScanConfig) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task(self.clone()));
+    }
 }
 
 /// Ruuvi Innovations manufacturer ID (little-endian bytes for pattern matching).
@@ -53,20 +254,38 @@ pub enum ScanError {
 /// Bluetooth LE advertisements use little-endian byte order for manufacturer IDs.
 /// This is the byte representation of 0x0499 used for filtering advertisements.
 /// See: https://github.com/ruuvi/ruuvi-sensor-protocols
-#[cfg(feature = "bluer")]
 pub const RUUVI_MANUFACTURER_ID_BYTES: [u8; 2] = [0x99, 0x04];
 
 /// Ruuvi Innovations manufacturer ID for data lookup.
 ///
 /// This is the big-endian representation (0x0499) used when looking up
 /// manufacturer-specific data from device advertisements.
-#[cfg(any(feature = "bluer", feature = "hci"))]
+#[cfg(any(
+    feature = "bluer",
+    feature = "hci",
+    feature = "mqtt",
+    feature = "gateway"
+))]
 pub const RUUVI_MANUFACTURER_ID: u16 = 0x0499;
 
 /// Bluetooth manufacturer-specific data type (AD type 0xFF)
-#[cfg(feature = "bluer")]
+#[cfg(any(
+    feature = "bluer",
+    feature = "hci",
+    feature = "mqtt",
+    feature = "gateway"
+))]
 pub const MANUFACTURER_DATA_TYPE: u8 = 0xff;
 
+/// Bluetooth service data type for 16-bit UUIDs (AD type 0x16)
+#[cfg(any(
+    feature = "bluer",
+    feature = "hci",
+    feature = "mqtt",
+    feature = "gateway"
+))]
+pub const SERVICE_DATA_TYPE: u8 = 0x16;
+
 /// Channel buffer size for measurement results.
 pub const MEASUREMENT_CHANNEL_BUFFER_SIZE: usize = 100;
 
@@ -79,6 +298,21 @@ pub enum Backend {
     /// Raw HCI socket backend (direct kernel access, no daemon required)
     #[cfg(feature = "hci")]
     Hci,
+    /// Replay a capture recorded with `--record` instead of scanning
+    Replay,
+    /// Replay HCI traffic from a btsnoop or pcap file instead of scanning
+    #[cfg(feature = "hci")]
+    HciDump,
+    /// Read `MAC,HEX` lines from stdin, a file or TCP instead of scanning
+    Hex,
+    /// Subscribe to advertisements published by Ruuvi Gateways over MQTT
+    #[cfg(feature = "mqtt")]
+    Mqtt,
+    /// Poll the HTTP API of a Ruuvi Gateway
+    #[cfg(feature = "gateway")]
+    Gateway,
+    /// Make up frames of virtual tags, for demos and load tests
+    Simulate,
 }
 
 impl Default for Backend {
@@ -92,6 +326,18 @@ impl Default for Backend {
     }
 }
 
+impl Backend {
+    /// Whether the backend replays a file instead of scanning.
+    pub fn is_replay(self) -> bool {
+        match self {
+            Backend::Replay => true,
+            #[cfg(feature = "hci")]
+            Backend::HciDump => true,
+            _ => false,
+        }
+    }
+}
+
 impl std::fmt::Display for Backend {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -99,8 +345,15 @@ impl std::fmt::Display for Backend {
             Backend::Bluer => write!(f, "bluer"),
             #[cfg(feature = "hci")]
             Backend::Hci => write!(f, "hci"),
-            #[cfg(not(any(feature = "bluer", feature = "hci")))]
-            _ => unreachable!("Backend enum has no variants when no backend features are enabled"),
+            Backend::Replay => write!(f, "replay"),
+            #[cfg(feature = "hci")]
+            Backend::HciDump => write!(f, "hci-dump"),
+            Backend::Hex => write!(f, "hex"),
+            #[cfg(feature = "mqtt")]
+            Backend::Mqtt => write!(f, "mqtt"),
+            #[cfg(feature = "gateway")]
+            Backend::Gateway => write!(f, "gateway"),
+            Backend::Simulate => write!(f, "simulate"),
         }
     }
 }
@@ -114,15 +367,126 @@ impl std::str::FromStr for Backend {
             "bluer" | "bluez" => Ok(Backend::Bluer),
             #[cfg(feature = "hci")]
             "hci" | "raw" => Ok(Backend::Hci),
+            "replay" => Ok(Backend::Replay),
+            #[cfg(feature = "hci")]
+            "hci-dump" => Ok(Backend::HciDump),
+            "hex" => Ok(Backend::Hex),
+            #[cfg(feature = "mqtt")]
+            "mqtt" => Ok(Backend::Mqtt),
+            #[cfg(feature = "gateway")]
+            "gateway" => Ok(Backend::Gateway),
+            "simulate" => Ok(Backend::Simulate),
             _ => Err(format!("Unknown backend: {}", s)),
         }
     }
 }
 
+/// Ruuvi manufacturer data as received by a backend, before decoding.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    /// Address of the advertiser
+    pub mac: MacAddress,
+    /// Adapter that received the frame
+    pub adapter: Option<&'a Arc<str>>,
+    /// Received signal strength in dBm, if the backend knows it
+    pub rssi: Option<i16>,
+    /// PHY the frame was received on, if the backend knows it
+    pub phy: Option<Phy>,
+    /// Manufacturer data after the company ID
+    pub data: &'a [u8],
+}
+
+/// Decodes the frames received by one adapter, recording them if asked to.
+#[derive(Debug, Clone, Default)]
+pub struct FrameDecoder {
+    adapter: Option<Arc<str>>,
+    recorder: Option<Recorder>,
+}
+
+impl FrameDecoder {
+    /// Create a decoder for frames received by `adapter`.
+    pub fn new(adapter: Option<Arc<str>>, recorder: Option<Recorder>) -> Self {
+        Self { adapter, recorder }
+    }
+
+    /// Whether frames are recorded, so backends can skip looking up details
+    /// such as the RSSI that are only needed for the recording.
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Decode the Ruuvi `data` received from `mac`, as returned by e.g.
+    /// [`find_ruuvi_data`].
+    pub fn decode(
+        &self,
+        mac: MacAddress,
+        rssi: Option<i16>,
+        phy: Option<Phy>,
+        data: &[u8],
+    ) -> MeasurementResult {
+        let result = decode_ruuvi_data(mac, data).map(|mut measurement| {
+            measurement.adapter = self.adapter.clone();
+            measurement.phy = phy;
+            measurement
+        });
+        if let Some(recorder) = &self.recorder {
+            let frame = Frame {
+                mac,
+                adapter: self.adapter.as_ref(),
+                rssi,
+                phy,
+                data,
+            };
+            recorder.record(&frame, &result);
+        }
+        result
+    }
+}
+
+/// Walk the AD structures of an advertisement and return the RuuviTag data
+/// found: the Ruuvi manufacturer data without the company ID or, for data
+/// formats 2 and 4, the data in an Eddystone URL (see [`eddystone`]).
+#[cfg(any(feature = "hci", feature = "mqtt", feature = "gateway"))]
+pub fn find_ruuvi_data(ad_data: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let mut offset = 0;
+    while offset + 2 <= ad_data.len() {
+        let len = ad_data[offset] as usize;
+        if len == 0 || offset + 1 + len > ad_data.len() {
+            break;
+        }
+
+        let ad_type = ad_data[offset + 1];
+
+        if ad_type == MANUFACTURER_DATA_TYPE && len >= 3 {
+            // Extract manufacturer ID (little-endian)
+            let mfg_id = u16::from_le_bytes([ad_data[offset + 2], ad_data[offset + 3]]);
+
+            if mfg_id == RUUVI_MANUFACTURER_ID {
+                // Found RuuviTag data
+                return Some(Cow::Borrowed(&ad_data[offset + 4..offset + 1 + len]));
+            }
+        }
+
+        if ad_type == SERVICE_DATA_TYPE && len >= 3 {
+            let uuid = u16::from_le_bytes([ad_data[offset + 2], ad_data[offset + 3]]);
+            if uuid == eddystone::EDDYSTONE_UUID
+                && let Some(data) = eddystone::ruuvi_data(&ad_data[offset + 4..offset + 1 + len])
+            {
+                return Some(Cow::Owned(data));
+            }
+        }
+
+        offset += 1 + len;
+    }
+
+    None
+}
+
 /// Decode manufacturer data from a RuuviTag into a Measurement.
 ///
 /// This function converts raw manufacturer data bytes into a structured `Measurement`
-/// with all values in standard SI units. Supports RuuviTag V5 and V6 formats.
+/// with all values in standard SI units. Supports RuuviTag V2 to V6 and E1 formats; V2
+/// and V4 as extracted from their Eddystone URL (see [`eddystone`]).
 ///
 /// # Arguments
 /// * `mac` - The MAC address of the device
@@ -140,16 +504,122 @@ pub fn decode_ruuvi_data(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
     }
 
     match data[0] {
+        2 | 4 => decode_url_measurement(mac, data),
+        3 => decode_v3_measurement(mac, data),
         5 => decode_v5_measurement(mac, data),
         6 => decode_v6_measurement(mac, data),
         0xE1 => decode_e1_measurement(mac, data),
         _ => Err(DecodeError::UnsupportedFormat(format!(
-            "RuuviTag data format {} (only V5, V6 and E1 supported)",
+            "RuuviTag data format {} (only V2 to V6 and E1 supported)",
             data[0]
         ))),
     }
 }
 
+/// Length of data format 3 manufacturer data, which has no MAC address.
+const V3_LENGTH: usize = 14;
+
+/// Temperature, humidity and pressure as laid out in data formats 2 to 4.
+///
+/// Humidity is in 0.5% steps, and temperature is whole degrees with a sign bit
+/// followed by hundredths. Pressure is in Pa above 50000 Pa, as in V5.
+fn decode_rawv1_weather(data: &[u8]) -> (f64, f64, f64) {
+    let temperature = f64::from(data[2] & 0x7F) + f64::from(data[3]) / 100.0;
+    let temperature = if data[2] & 0x80 != 0 {
+        -temperature
+    } else {
+        temperature
+    };
+    let humidity = f64::from(data[1]) * 0.5;
+    let pressure = f64::from(u16::from_be_bytes([data[4], data[5]])) + 50_000.0;
+    (temperature, humidity, pressure)
+}
+
+/// Decode data formats 2 and 4 from the data of their Eddystone URL (see
+/// [`eddystone`]). Format 4 ends in a byte identifying the tag.
+fn decode_url_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, DecodeError> {
+    if !(6..=7).contains(&data.len()) {
+        return Err(DecodeError::InvalidData(format!(
+            "Data format {} is 6 or 7 bytes, got {}",
+            data[0],
+            data.len()
+        )));
+    }
+    let (temperature, humidity, pressure) = decode_rawv1_weather(data);
+
+    Ok(Measurement {
+        mac,
+        format: if data[0] == 2 { Format::V2 } else { Format::V4 },
+        adapter: None,
+        phy: None,
+        timestamp: SystemTime::now(),
+        temperature: Some(temperature),
+        humidity: Some(humidity),
+        pressure: Some(pressure),
+        pressure_sea_level: None,
+        pressure_trend_3h: None,
+        pressure_tendency: None,
+        battery: None,
+        tx_power: None,
+        movement_counter: None,
+        measurement_sequence: None,
+        acceleration: None,
+        pm1_0: None,
+        pm2_5: None,
+        pm4_0: None,
+        pm10_0: None,
+        co2: None,
+        voc_index: None,
+        nox_index: None,
+        luminosity: None,
+        tag_id: data.get(6).copied(),
+    })
+}
+
+/// Decode data format 3 (RAWv1), which `ruuvi_decoders` does not support.
+///
+/// Acceleration is as in V5; battery voltage is in millivolts. The format has
+/// no "not available" values.
+fn decode_v3_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, DecodeError> {
+    if data.len() != V3_LENGTH {
+        return Err(DecodeError::InvalidData(format!(
+            "Data format 3 is {V3_LENGTH} bytes, got {}",
+            data.len()
+        )));
+    }
+    let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+    let acceleration_at = |i: usize| f64::from(i16::from_be_bytes([data[i], data[i + 1]])) / 1000.0;
+    let (temperature, humidity, pressure) = decode_rawv1_weather(data);
+
+    Ok(Measurement {
+        mac,
+        format: Format::V3,
+        adapter: None,
+        phy: None,
+        timestamp: SystemTime::now(),
+        temperature: Some(temperature),
+        humidity: Some(humidity),
+        pressure: Some(pressure),
+        pressure_sea_level: None,
+        pressure_trend_3h: None,
+        pressure_tendency: None,
+        battery: Some(f64::from(u16_at(12)) / 1000.0),
+        tx_power: None,
+        movement_counter: None,
+        measurement_sequence: None,
+        acceleration: Some((acceleration_at(6), acceleration_at(8), acceleration_at(10))),
+        pm1_0: None,
+        pm2_5: None,
+        pm4_0: None,
+        pm10_0: None,
+        co2: None,
+        voc_index: None,
+        nox_index: None,
+        luminosity: None,
+        tag_id: None,
+    })
+}
+
 fn decode_v5_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, DecodeError> {
     match v5::decode(data) {
         Ok(tag) => {
@@ -167,10 +637,15 @@ fn decode_v5_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
             Ok(Measurement {
                 mac,
                 format: Format::V5,
+                adapter: None,
+                phy: None,
                 timestamp: SystemTime::now(),
                 temperature: tag.temperature,
                 humidity: tag.humidity,
                 pressure: tag.pressure,
+                pressure_sea_level: None,
+                pressure_trend_3h: None,
+                pressure_tendency: None,
                 battery: battery_potential,
                 tx_power: tag.tx_power,
                 movement_counter: tag.movement_counter.map(u32::from),
@@ -184,6 +659,7 @@ fn decode_v5_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
                 voc_index: None,
                 nox_index: None,
                 luminosity: None,
+                tag_id: None,
             })
         }
         Err(e) => Err(DecodeError::DecoderError(format!(
@@ -197,11 +673,16 @@ fn decode_v6_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
         Ok(tag) => Ok(Measurement {
             mac,
             format: Format::V6,
+            adapter: None,
+            phy: None,
             timestamp: SystemTime::now(),
             temperature: tag.temperature,
             humidity: tag.humidity,
             // Decoder returns hPa; store as Pa to stay consistent with v5 handling.
             pressure: tag.pressure.map(|hpa| hpa * 100.0),
+            pressure_sea_level: None,
+            pressure_trend_3h: None,
+            pressure_tendency: None,
             battery: None,
             tx_power: None,
             movement_counter: None,
@@ -215,6 +696,7 @@ fn decode_v6_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
             voc_index: tag.voc_index.map(f64::from),
             nox_index: tag.nox_index.map(f64::from),
             luminosity: tag.luminosity,
+            tag_id: None,
         }),
         Err(e) => Err(DecodeError::DecoderError(format!(
             "Failed to decode RuuviTag data: {e:?}"
@@ -227,11 +709,16 @@ fn decode_e1_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
         Ok(tag) => Ok(Measurement {
             mac,
             format: Format::E1,
+            adapter: None,
+            phy: None,
             timestamp: SystemTime::now(),
             temperature: tag.temperature,
             humidity: tag.humidity,
             // Decoder returns hPa; store as Pa to stay consistent with v5/v6 handling.
             pressure: tag.pressure.map(|hpa| hpa * 100.0),
+            pressure_sea_level: None,
+            pressure_trend_3h: None,
+            pressure_tendency: None,
             battery: None,
             tx_power: None,
             movement_counter: None,
@@ -245,6 +732,7 @@ fn decode_e1_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
             voc_index: tag.voc_index.map(f64::from),
             nox_index: tag.nox_index.map(f64::from),
             luminosity: tag.luminosity,
+            tag_id: None,
         }),
         Err(e) => Err(DecodeError::DecoderError(format!(
             "Failed to decode RuuviTag data: {e:?}"
@@ -252,34 +740,135 @@ fn decode_e1_measurement(mac: MacAddress, data: &[u8]) -> Result<Measurement, De
     }
 }
 
+/// Backends that scan with Bluetooth adapters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AdapterBackend {
+    #[cfg(feature = "bluer")]
+    Bluer,
+    #[cfg(feature = "hci")]
+    Hci,
+}
+
+impl From<AdapterBackend> for Backend {
+    fn from(backend: AdapterBackend) -> Self {
+        match backend {
+            #[cfg(feature = "bluer")]
+            AdapterBackend::Bluer => Backend::Bluer,
+            #[cfg(feature = "hci")]
+            AdapterBackend::Hci => Backend::Hci,
+        }
+    }
+}
+
 /// Start scanning for RuuviTag devices using the specified backend.
 ///
 /// This is the main entry point for creating a scanner. It dispatches to the
 /// appropriate backend implementation based on the `backend` parameter.
 ///
+/// Backends that replay, read or make up frames run once, with the settings
+/// for them in `config`. Backends that scan with Bluetooth adapters run one
+/// scan per adapter and their measurements are merged into the returned
+/// channel. Copies of the same advertisement received by several adapters are
+/// collapsed unless `config.keep_duplicates` is set. Scanning fails if any of
+/// the adapters cannot be started. Once started, each adapter's scan is
+/// supervised and restarted if it stops (see [`supervisor`]).
+///
 /// # Arguments
 /// * `backend` - The scanner backend to use
-/// * `verbose` - If true, decode errors are sent as Err values; otherwise they're silently dropped.
+/// * `config` - Settings for the scan, such as the adapter to use
 ///
 /// # Returns
 /// A receiver for measurements (or decode errors if verbose).
 pub async fn start_scan(
     backend: Backend,
-    verbose: bool,
+    config: &ScanConfig,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let backend = match backend {
+        #[cfg(feature = "bluer")]
+        Backend::Bluer => AdapterBackend::Bluer,
+        #[cfg(feature = "hci")]
+        Backend::Hci => AdapterBackend::Hci,
+        Backend::Replay => {
+            return replay::start_replay(replay_input(config)?, config.replay_realtime, config)
+                .await;
+        }
+        #[cfg(feature = "hci")]
+        Backend::HciDump => {
+            return hci::dump::start_replay(replay_input(config)?, config.replay_realtime, config)
+                .await;
+        }
+        Backend::Hex => {
+            let source = lines::LineSource::from_input(config.input.as_deref());
+            return lines::start_lines(source, config).await;
+        }
+        #[cfg(feature = "mqtt")]
+        Backend::Mqtt => return mqtt::start_mqtt(&config.mqtt, config).await,
+        #[cfg(feature = "gateway")]
+        Backend::Gateway => return gateway::http::start_polling(&config.gateway, config).await,
+        Backend::Simulate => return simulate::start_simulation(&config.simulate, config).await,
+    };
+
+    if config.adapters.len() <= 1 {
+        let adapter = config.adapters.first().copied();
+        return supervisor::start_supervised(backend, config, adapter).await;
+    }
+
+    let mut receivers = Vec::with_capacity(config.adapters.len());
+    for &adapter in &config.adapters {
+        receivers.push(supervisor::start_supervised(backend, config, Some(adapter)).await?);
+    }
+    let deduplicator =
+        (!config.keep_duplicates).then(|| merge::Deduplicator::new(merge::DUPLICATE_WINDOW));
+    Ok(merge::merge(receivers, deduplicator))
+}
+
+/// The file to replay, which the command line requires for the replay backends.
+fn replay_input(config: &ScanConfig) -> Result<&std::path::Path, ScanError> {
+    config
+        .input
+        .as_deref()
+        .ok_or_else(|| ScanError::Replay("no input to replay".to_string()))
+}
+
+/// Start scanning on a single adapter, or the default adapter if `None`.
+async fn start_adapter_scan(
+    backend: AdapterBackend,
+    config: &ScanConfig,
+    adapter: Option<AdapterId>,
 ) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
     match backend {
         #[cfg(feature = "bluer")]
-        Backend::Bluer => bluer::start_scan(verbose).await,
+        AdapterBackend::Bluer => bluer::start_scan(config, adapter).await,
         #[cfg(feature = "hci")]
-        Backend::Hci => hci::start_scan(verbose).await,
+        AdapterBackend::Hci => hci::start_scan(config, adapter).await,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::capture::from_hex;
     use crate::test_utils::TEST_MAC;
     use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test]
+    async fn test_scan_tasks_join_waits_for_nested_tasks() {
+        let tasks = ScanTasks::default();
+        let done = Arc::new(AtomicBool::new(false));
+        let (inner_tasks, inner_done) = (tasks.clone(), done.clone());
+        tasks.spawn(async move {
+            tokio::task::yield_now().await;
+            inner_tasks.spawn(async move {
+                tokio::task::yield_now().await;
+                inner_done.store(true, Ordering::SeqCst);
+            });
+        });
+
+        tasks.join().await;
+
+        assert!(done.load(Ordering::SeqCst));
+    }
 
     fn v5_payload() -> Vec<u8> {
         // Example V5 data (without manufacturer ID prefix)
@@ -307,6 +896,33 @@ mod tests {
         ]
     }
 
+    #[cfg(any(feature = "hci", feature = "mqtt", feature = "gateway"))]
+    #[test]
+    fn test_find_ruuvi_data() {
+        let mut advertisement = vec![0x02, 0x01, 0x06, 0x03, MANUFACTURER_DATA_TYPE, 0x4C, 0x00];
+        advertisement.extend([0x04, MANUFACTURER_DATA_TYPE, 0x99, 0x04, 0x05]);
+        assert_eq!(
+            find_ruuvi_data(&advertisement).as_deref(),
+            Some(&[0x05][..])
+        );
+
+        // Eddystone-URL service data of a tag broadcasting data format 2
+        let mut eddystone = vec![0x03, 0x03, 0xAA, 0xFE, 0x16, SERVICE_DATA_TYPE, 0xAA, 0xFE];
+        eddystone.extend([0x10, 0xEB, 0x03]);
+        eddystone.extend(b"ruu.vi/#AjwYAMFc");
+        assert_eq!(
+            find_ruuvi_data(&eddystone).as_deref(),
+            Some(&[0x02, 0x3C, 0x18, 0x00, 0xC1, 0x5C][..])
+        );
+
+        // A truncated AD structure ends the walk
+        assert_eq!(
+            find_ruuvi_data(&advertisement[..advertisement.len() - 1]),
+            None
+        );
+        assert_eq!(find_ruuvi_data(&[]), None);
+    }
+
     #[test]
     fn test_decode_ruuvi_data_v5() {
         let measurement = decode_ruuvi_data(TEST_MAC, &v5_payload()).unwrap();
@@ -361,12 +977,82 @@ mod tests {
         assert!(measurement.acceleration.is_none());
     }
 
+    #[test]
+    fn test_decode_ruuvi_data_v3() {
+        // Reference payloads from the data format 3 specification
+        let valid = from_hex("03291A1ECE1EFC18F94202CA0B53").unwrap();
+        let measurement = decode_ruuvi_data(TEST_MAC, &valid).unwrap();
+        assert_eq!(measurement.format, Format::V3);
+        assert_eq!(measurement.humidity, Some(20.5));
+        assert_eq!(measurement.temperature, Some(26.3));
+        assert_eq!(measurement.pressure, Some(102_766.0));
+        assert_eq!(measurement.acceleration, Some((-1.0, -1.726, 0.714)));
+        assert_eq!(measurement.battery, Some(2.899));
+        assert!(measurement.measurement_sequence.is_none());
+        assert!(measurement.tx_power.is_none());
+
+        let maximum = from_hex("03FF7F63FFFF7FFF7FFF7FFFFFFF").unwrap();
+        let measurement = decode_ruuvi_data(TEST_MAC, &maximum).unwrap();
+        assert_eq!(measurement.humidity, Some(127.5));
+        assert_eq!(measurement.temperature, Some(127.99));
+        assert_eq!(measurement.pressure, Some(115_535.0));
+        assert_eq!(measurement.battery, Some(65.535));
+
+        let minimum = from_hex("0300FF6300008001800180010000").unwrap();
+        let measurement = decode_ruuvi_data(TEST_MAC, &minimum).unwrap();
+        assert_eq!(measurement.temperature, Some(-127.99));
+        assert_eq!(measurement.pressure, Some(50_000.0));
+        assert_eq!(measurement.acceleration, Some((-32.767, -32.767, -32.767)));
+
+        let err = decode_ruuvi_data(TEST_MAC, &valid[..10]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data: Data format 3 is 14 bytes, got 10"
+        );
+    }
+
+    #[test]
+    fn test_decode_ruuvi_data_url_formats() {
+        let format_2 = [0x02, 0x3C, 0x18, 0x00, 0xC1, 0x5C];
+        let measurement = decode_ruuvi_data(TEST_MAC, &format_2).unwrap();
+        assert_eq!(measurement.format, Format::V2);
+        assert_eq!(measurement.humidity, Some(30.0));
+        assert_eq!(measurement.temperature, Some(24.0));
+        assert_eq!(measurement.pressure, Some(99_500.0));
+        assert_eq!(measurement.tag_id, None);
+        assert!(measurement.battery.is_none());
+
+        let format_4 = [0x04, 0x3C, 0x98, 0x32, 0xC1, 0x5C, 0x40];
+        let measurement = decode_ruuvi_data(TEST_MAC, &format_4).unwrap();
+        assert_eq!(measurement.format, Format::V4);
+        assert_eq!(measurement.temperature, Some(-24.5));
+        assert_eq!(measurement.tag_id, Some(0x40));
+
+        let err = decode_ruuvi_data(TEST_MAC, &format_2[..4]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid data: Data format 2 is 6 or 7 bytes, got 4"
+        );
+    }
+
     #[test]
     fn test_decode_ruuvi_data_invalid() {
         let data: Vec<u8> = vec![0x00, 0x01, 0x02]; // Invalid/too short data
         assert!(decode_ruuvi_data(TEST_MAC, &data).is_err());
     }
 
+    #[test]
+    fn test_frame_decoder_sets_adapter_and_phy() {
+        let decoder = FrameDecoder::new(Some(Arc::from("hci1")), None);
+        assert!(!decoder.is_recording());
+
+        let measurement = decoder
+            .decode(TEST_MAC, Some(-70), Some(Phy::LeCoded), &v5_payload())
+            .unwrap();
+        assert_eq!(measurement.adapter.as_deref(), Some("hci1"));
+        assert_eq!(measurement.phy, Some(Phy::LeCoded));
+    }
+
     #[test]
     fn test_decode_ruuvi_data_v6() {
         let measurement = decode_ruuvi_data(TEST_MAC, &v6_payload()).unwrap();
@@ -409,6 +1095,10 @@ mod tests {
         assert_eq!(Backend::from_str("bluez").unwrap(), Backend::Bluer);
         assert_eq!(Backend::from_str("hci").unwrap(), Backend::Hci);
         assert_eq!(Backend::from_str("raw").unwrap(), Backend::Hci);
+        assert_eq!(Backend::from_str("replay").unwrap(), Backend::Replay);
+        assert_eq!(Backend::from_str("hci-dump").unwrap(), Backend::HciDump);
+        assert_eq!(Backend::from_str("hex").unwrap(), Backend::Hex);
+        assert_eq!(Backend::from_str("simulate").unwrap(), Backend::Simulate);
         assert!(Backend::from_str("invalid").is_err());
     }
 
@@ -416,5 +1106,39 @@ mod tests {
     fn test_backend_display() {
         assert_eq!(format!("{}", Backend::Bluer), "bluer");
         assert_eq!(format!("{}", Backend::Hci), "hci");
+        assert_eq!(format!("{}", Backend::Replay), "replay");
+        assert_eq!(format!("{}", Backend::HciDump), "hci-dump");
+        assert_eq!(format!("{}", Backend::Hex), "hex");
+        assert_eq!(format!("{}", Backend::Simulate), "simulate");
+    }
+
+    #[test]
+    fn test_adapter_id_from_str() {
+        assert_eq!(AdapterId::from_str("hci1").unwrap(), AdapterId::Index(1));
+        assert_eq!(
+            AdapterId::from_str("AA:BB:CC:DD:EE:FF").unwrap(),
+            AdapterId::Address(TEST_MAC)
+        );
+        assert!(AdapterId::from_str("hcix").is_err());
+        assert!(AdapterId::from_str("usb0").is_err());
+        assert_eq!(AdapterId::Index(1).to_string(), "hci1");
+    }
+
+    #[test]
+    fn test_adapter_not_found_lists_available() {
+        let err = ScanError::AdapterNotFound {
+            requested: "hci1".to_string(),
+            available: vec![describe_adapter("hci0", TEST_MAC)],
+        };
+        assert_eq!(
+            err.to_string(),
+            "Bluetooth adapter 'hci1' not found (available: hci0 (AA:BB:CC:DD:EE:FF))"
+        );
+
+        let err = ScanError::AdapterNotFound {
+            requested: "hci1".to_string(),
+            available: vec![],
+        };
+        assert!(err.to_string().ends_with("(available: none)"));
     }
 }
@@ -0,0 +1,384 @@
+//! Ingestion of advertisements relayed by Ruuvi Gateways over MQTT.
+//!
+//! A Ruuvi Gateway publishes every advertisement it receives to
+//! `ruuvi/<gateway mac>/<tag mac>`, with a JSON body holding the raw
+//! advertisement as hex:
+//!
+//! ```text
+//! {"gw_mac":"C8:25:2D:8E:9C:2C","rssi":-62,"aoa":[],"gwts":1700000001,"ts":1700000000,"data":"0201061BFF99040512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F","coords":""}
+//! ```
+//!
+//! The mqtt backend subscribes to these topics and decodes the Ruuvi
+//! manufacturer data of each advertisement like the HCI backend does, so
+//! measurements from gateways go through the same pipeline as those received
+//! locally. The gateway is reported as the measurement's adapter, and `ts` as
+//! its timestamp. Copies of an advertisement relayed by several gateways are
+//! collapsed unless `keep_duplicates` is set.
+//!
+//! If the connection to the broker is lost, a disconnect event is written to
+//! stderr and the backend reconnects:
+//!
+//! ```text
+//! event=mqtt_disconnected broker=localhost:1883 error="I/O: Connection refused (os error 111)"
+//! ```
+
+use super::gateway::{Integer, decode_advertisement};
+use super::merge::{DUPLICATE_WINDOW, Deduplicator};
+use super::{
+    DecodeError, FrameDecoder, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, ScanConfig,
+    ScanError,
+};
+use crate::mac_address::MacAddress;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Keep-alive interval of the broker connection.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Where to find the gateways' messages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MqttConfig {
+    /// Host name or address of the broker
+    pub host: String,
+    /// Port of the broker
+    pub port: u16,
+    /// Topic filter to subscribe to, e.g. `ruuvi/#`
+    pub topic: String,
+    /// User name and password to log in with, if the broker requires them
+    pub credentials: Option<(String, String)>,
+}
+
+/// Body of a message published by a Ruuvi Gateway.
+#[derive(Debug, Deserialize)]
+struct GatewayMessage {
+    /// Raw advertisement as hex
+    data: String,
+    /// Gateway that received the advertisement
+    #[serde(default)]
+    gw_mac: Option<String>,
+    #[serde(default)]
+    rssi: Option<i16>,
+    /// When the gateway received the advertisement, in seconds since the
+    /// Unix epoch
+    #[serde(default)]
+    ts: Option<Integer>,
+}
+
+/// Connect to the broker in `mqtt` and start decoding the advertisements
+/// published by gateways.
+///
+/// Fails if the first connection attempt fails. Once connected, the backend
+/// reconnects whenever the connection is lost, until the returned receiver is
+/// dropped.
+pub async fn start_mqtt(
+    mqtt: &MqttConfig,
+    config: &ScanConfig,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let broker = format!("{}:{}", mqtt.host, mqtt.port);
+    let client_id = format!("ruuvitag-listener-{}", std::process::id());
+    let mut options = MqttOptions::new(client_id, &mqtt.host, mqtt.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    if let Some((username, password)) = &mqtt.credentials {
+        options.set_credentials(username, password);
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, MEASUREMENT_CHANNEL_BUFFER_SIZE);
+
+    // Subscriptions are made on every connection, as they do not survive
+    // reconnecting with a clean session
+    connect(&mut eventloop)
+        .await
+        .map_err(|e| ScanError::Mqtt(format!("{broker}: {e}")))?;
+    subscribe(&client, &mqtt.topic)?;
+
+    let topic = mqtt.topic.clone();
+    let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    config.spawn(|config| async move {
+        let mut deduplicator =
+            (!config.keep_duplicates).then(|| Deduplicator::new(DUPLICATE_WINDOW));
+        loop {
+            let event = tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                event = eventloop.poll() => event,
+            };
+            let publish = match event {
+                Ok(Event::Incoming(Packet::Publish(publish))) => publish,
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    if let Err(e) = subscribe(&client, &topic) {
+                        config.report_fatal(e);
+                        break;
+                    }
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    crate::event::report(&crate::event::Event::MqttDisconnected {
+                        broker: broker.clone(),
+                        error: e.to_string(),
+                    });
+                    tokio::select! {
+                        _ = tx.closed() => break,
+                        _ = tokio::time::sleep(RECONNECT_DELAY) => continue,
+                    }
+                }
+            };
+
+            let Some(result) = decode_message(&publish.topic, &publish.payload, &config) else {
+                continue;
+            };
+            if let (Ok(measurement), Some(deduplicator)) = (&result, deduplicator.as_mut())
+                && !deduplicator.is_new(measurement)
+            {
+                continue;
+            }
+            if (result.is_ok() || config.verbose) && tx.send(result).await.is_err() {
+                break;
+            }
+        }
+
+        let _ = client.try_disconnect();
+    });
+
+    Ok(rx)
+}
+
+/// Poll `eventloop` until the broker has accepted the connection.
+async fn connect(eventloop: &mut EventLoop) -> Result<(), rumqttc::ConnectionError> {
+    loop {
+        if let Event::Incoming(Packet::ConnAck(_)) = eventloop.poll().await? {
+            return Ok(());
+        }
+    }
+}
+
+fn subscribe(client: &AsyncClient, topic: &str) -> Result<(), ScanError> {
+    client
+        .try_subscribe(topic, QoS::AtMostOnce)
+        .map_err(|e| ScanError::Mqtt(format!("subscribing to {topic}: {e}")))
+}
+
+/// Decode a message published to `topic` by a gateway.
+///
+/// Returns `None` for messages that do not carry a RuuviTag advertisement,
+/// such as the gateway's status messages or advertisements of other devices.
+fn decode_message(topic: &str, payload: &[u8], config: &ScanConfig) -> Option<MeasurementResult> {
+    // Gateways also publish e.g. `ruuvi/<gateway mac>/gw_status`
+    let mac: MacAddress = topic.rsplit('/').next()?.parse().ok()?;
+    let message: GatewayMessage = match serde_json::from_slice(payload) {
+        Ok(message) => message,
+        Err(e) => return Some(Err(DecodeError::InvalidData(format!("{topic}: {e}")))),
+    };
+    let decoder = FrameDecoder::new(
+        message.gw_mac.as_deref().map(Arc::from),
+        config.recorder.clone(),
+    );
+    decode_advertisement(
+        &decoder,
+        mac,
+        message.rssi,
+        &message.data,
+        message.ts.as_ref().and_then(Integer::to_system_time),
+        topic,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::Format;
+    use crate::test_utils::TEST_MAC;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::UNIX_EPOCH;
+
+    const TOPIC: &str = "ruuvi/C8:25:2D:8E:9C:2C/AA:BB:CC:DD:EE:FF";
+    const ADVERTISEMENT: &str = "0201061BFF99040512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F";
+
+    fn message(data: &str) -> String {
+        format!(
+            r#"{{"gw_mac":"C8:25:2D:8E:9C:2C","rssi":-62,"aoa":[],"gwts":1700000001,"ts":"1700000000","data":"{data}","coords":""}}"#
+        )
+    }
+
+    #[test]
+    fn test_decode_gateway_message() {
+        let measurement = decode_message(
+            TOPIC,
+            message(ADVERTISEMENT).as_bytes(),
+            &ScanConfig::default(),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(measurement.mac, TEST_MAC);
+        assert_eq!(measurement.format, Format::V5);
+        assert_eq!(measurement.temperature, Some(24.3));
+        assert_eq!(measurement.adapter.as_deref(), Some("C8:25:2D:8E:9C:2C"));
+        assert_eq!(
+            measurement.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+
+    #[test]
+    fn test_numeric_timestamp() {
+        let payload = format!(r#"{{"ts":1700000000,"data":"{ADVERTISEMENT}"}}"#);
+        let measurement = decode_message(TOPIC, payload.as_bytes(), &ScanConfig::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            measurement.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert_eq!(measurement.adapter, None);
+    }
+
+    #[test]
+    fn test_skip_other_messages() {
+        let config = ScanConfig::default();
+        let status = "ruuvi/C8:25:2D:8E:9C:2C/gw_status";
+        assert!(decode_message(status, br#"{"state":"online"}"#, &config).is_none());
+        // An iBeacon, without Ruuvi manufacturer data
+        let other = message("0201061AFF4C000215000000000000000000000000000000000000000000C5");
+        assert!(decode_message(TOPIC, other.as_bytes(), &config).is_none());
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let config = ScanConfig::default();
+        let err = decode_message(TOPIC, b"{", &config).unwrap().unwrap_err();
+        assert!(err.to_string().contains(TOPIC));
+        let err = decode_message(TOPIC, message("0201zz").as_bytes(), &config)
+            .unwrap()
+            .unwrap_err();
+        assert!(err.to_string().ends_with(r#"invalid hex data "0201zz""#));
+    }
+
+    /// Read one MQTT control packet from `stream`: its type and its body.
+    fn read_packet(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; 1];
+        stream.read_exact(&mut header)?;
+        let (mut length, mut shift) = (0usize, 0);
+        loop {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte)?;
+            length |= usize::from(byte[0] & 0x7f) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body)?;
+        Ok((header[0] >> 4, body))
+    }
+
+    fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+        let length = 2 + topic.len() + payload.len();
+        assert!(length < 16384);
+        let mut packet = vec![0x30];
+        if length < 128 {
+            packet.push(length as u8);
+        } else {
+            packet.extend([(length % 128) as u8 | 0x80, (length / 128) as u8]);
+        }
+        packet.extend((topic.len() as u16).to_be_bytes());
+        packet.extend(topic.as_bytes());
+        packet.extend(payload);
+        packet
+    }
+
+    /// Accept one client, subscribe it, and publish `messages` to it. Returns
+    /// the topic filter subscribed to once the client has disconnected.
+    fn fake_broker(
+        messages: Vec<(&'static str, String)>,
+    ) -> (u16, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (kind, _) = read_packet(&mut stream).unwrap();
+            assert_eq!(kind, 1, "expected CONNECT");
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+
+            let (kind, body) = read_packet(&mut stream).unwrap();
+            assert_eq!(kind, 8, "expected SUBSCRIBE");
+            let topic_length = usize::from(u16::from_be_bytes([body[2], body[3]]));
+            let topic = String::from_utf8(body[4..4 + topic_length].to_vec()).unwrap();
+            stream
+                .write_all(&[0x90, 0x03, body[0], body[1], 0x00])
+                .unwrap();
+
+            for (topic, payload) in messages {
+                stream
+                    .write_all(&publish_packet(topic, payload.as_bytes()))
+                    .unwrap();
+            }
+            while read_packet(&mut stream).is_ok() {}
+            topic
+        });
+        (port, broker)
+    }
+
+    #[tokio::test]
+    async fn test_mqtt_backend_decodes_published_advertisements() {
+        let (port, broker) = fake_broker(vec![
+            ("ruuvi/C8:25:2D:8E:9C:2C/gw_status", "{}".to_string()),
+            (TOPIC, message(ADVERTISEMENT)),
+            // The same advertisement relayed by a second gateway
+            (
+                "ruuvi/C8:25:2D:8E:9C:2D/AA:BB:CC:DD:EE:FF",
+                message(ADVERTISEMENT),
+            ),
+        ]);
+        let mqtt = MqttConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            topic: "ruuvi/#".to_string(),
+            credentials: None,
+        };
+
+        let mut rx = start_mqtt(&mqtt, &ScanConfig::default()).await.unwrap();
+        let measurement = rx.recv().await.unwrap().unwrap();
+        assert_eq!(measurement.mac, TEST_MAC);
+        assert_eq!(measurement.format, Format::V5);
+
+        // The copy from the second gateway was collapsed
+        let next = tokio::time::timeout(Duration::from_millis(100), rx.recv()).await;
+        assert!(next.is_err());
+
+        drop(rx);
+        let topic = tokio::task::spawn_blocking(move || broker.join().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(topic, "ruuvi/#");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_broker_fails_to_start() {
+        // Nothing listens on a port that was just released
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mqtt = MqttConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            topic: "ruuvi/#".to_string(),
+            credentials: None,
+        };
+        let err = start_mqtt(&mqtt, &ScanConfig::default()).await.unwrap_err();
+        assert!(matches!(err, ScanError::Mqtt(_)));
+        assert!(
+            err.to_string()
+                .starts_with(&format!("MQTT error: 127.0.0.1:{port}: "))
+        );
+    }
+}
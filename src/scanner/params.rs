@@ -0,0 +1,187 @@
+//! LE scan parameters for backends that drive the controller directly.
+//!
+//! The HCI backend programs these into the controller with the LE Set (Extended)
+//! Scan Parameters and Enable commands. Timing is given as durations and
+//! converted to the controller's 0.625 ms units when scanning starts, because
+//! the valid range depends on whether the legacy or extended commands are used.
+
+use super::ScanError;
+use std::time::Duration;
+
+/// Length of one scan interval/window unit.
+const SLOT: Duration = Duration::from_micros(625);
+
+/// Shortest scan interval or window, 2.5 ms.
+const MIN_SLOTS: u16 = 0x0004;
+
+/// Longest scan interval or window of the legacy scan commands, 10.24 s.
+pub const MAX_LEGACY_SLOTS: u16 = 0x4000;
+
+/// Longest scan interval or window of the extended scan commands, 40.96 s.
+pub const MAX_EXTENDED_SLOTS: u16 = 0xFFFF;
+
+/// Whether the controller asks advertisers for more data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ScanType {
+    /// Only listen to advertisements
+    Passive,
+    /// Send scan requests, receiving scan responses as well
+    Active,
+}
+
+/// Address the controller uses for scan requests when scanning actively.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OwnAddressType {
+    /// The controller's public address
+    #[default]
+    Public,
+    /// The random address set on the controller
+    Random,
+    /// A resolvable private address, falling back to the public address
+    ResolvableOrPublic,
+    /// A resolvable private address, falling back to the random address
+    ResolvableOrRandom,
+}
+
+impl OwnAddressType {
+    /// Value of the `Own_Address_Type` command parameter.
+    pub fn code(self) -> u8 {
+        match self {
+            OwnAddressType::Public => 0x00,
+            OwnAddressType::Random => 0x01,
+            OwnAddressType::ResolvableOrPublic => 0x02,
+            OwnAddressType::ResolvableOrRandom => 0x03,
+        }
+    }
+}
+
+/// Whether to scan on the LE Coded PHY (long range) in addition to LE 1M.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CodedPhy {
+    /// Scan on the Coded PHY if the controller supports it
+    #[default]
+    Auto,
+    /// Scan on the Coded PHY, failing if the controller does not support it
+    On,
+    /// Scan on LE 1M only
+    Off,
+}
+
+/// LE scan parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanParameters {
+    /// Time between the starts of consecutive scan windows.
+    pub interval: Duration,
+    /// How long the controller listens during each interval.
+    pub window: Duration,
+    /// Passive or active scanning; `None` uses the backend's default.
+    pub scan_type: Option<ScanType>,
+    /// Address used for scan requests.
+    pub own_address_type: OwnAddressType,
+    /// Let the controller drop repeated advertisements of the same device.
+    pub filter_duplicates: bool,
+    /// Whether to also scan on the LE Coded PHY (long range).
+    pub coded_phy: CodedPhy,
+}
+
+impl Default for ScanParameters {
+    /// Passive scanning for 200 ms every 200 ms, i.e. continuously. RuuviTags
+    /// advertise about once a second, so this catches nearly every broadcast.
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(200),
+            window: Duration::from_millis(200),
+            scan_type: None,
+            own_address_type: OwnAddressType::Public,
+            filter_duplicates: false,
+            coded_phy: CodedPhy::Auto,
+        }
+    }
+}
+
+impl ScanParameters {
+    /// Convert the interval and window to 0.625 ms units, checking that both
+    /// are between 2.5 ms and `max_slots` units and that the window fits in
+    /// the interval.
+    pub fn slots(&self, max_slots: u16) -> Result<(u16, u16), ScanError> {
+        let interval = to_slots("interval", self.interval, max_slots)?;
+        let window = to_slots("window", self.window, max_slots)?;
+        if window > interval {
+            return Err(ScanError::UnsupportedScanParameters(format!(
+                "scan window {:?} is longer than scan interval {:?}",
+                self.window, self.interval
+            )));
+        }
+        Ok((interval, window))
+    }
+}
+
+/// Convert `duration` to the nearest number of 0.625 ms units.
+fn to_slots(name: &str, duration: Duration, max_slots: u16) -> Result<u16, ScanError> {
+    let slots = (duration.as_micros() + SLOT.as_micros() / 2) / SLOT.as_micros();
+    match u16::try_from(slots) {
+        Ok(slots) if (MIN_SLOTS..=max_slots).contains(&slots) => Ok(slots),
+        _ => Err(ScanError::UnsupportedScanParameters(format!(
+            "scan {} {:?} is outside {:?}..={:?}",
+            name,
+            duration,
+            SLOT * MIN_SLOTS.into(),
+            SLOT * max_slots.into()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_slots() {
+        assert_eq!(
+            ScanParameters::default().slots(MAX_LEGACY_SLOTS).unwrap(),
+            (0x0140, 0x0140)
+        );
+    }
+
+    #[test]
+    fn test_slots_round_to_nearest() {
+        let params = ScanParameters {
+            interval: Duration::from_millis(101),
+            window: Duration::from_millis(30),
+            ..Default::default()
+        };
+        // 101 ms = 161.6 units, 30 ms = 48 units
+        assert_eq!(params.slots(MAX_LEGACY_SLOTS).unwrap(), (162, 48));
+    }
+
+    #[test]
+    fn test_slots_out_of_range() {
+        let params = ScanParameters {
+            interval: Duration::from_secs(20),
+            window: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let err = params.slots(MAX_LEGACY_SLOTS).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsupported scan parameters: scan interval 20s is outside 2.5ms..=10.24s"
+        );
+        assert_eq!(params.slots(MAX_EXTENDED_SLOTS).unwrap(), (32000, 320));
+
+        let params = ScanParameters {
+            window: Duration::from_millis(1),
+            ..Default::default()
+        };
+        assert!(params.slots(MAX_LEGACY_SLOTS).is_err());
+    }
+
+    #[test]
+    fn test_window_longer_than_interval() {
+        let params = ScanParameters {
+            interval: Duration::from_millis(100),
+            window: Duration::from_millis(200),
+            ..Default::default()
+        };
+        assert!(params.slots(MAX_LEGACY_SLOTS).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Replay of captures recorded with `--record`.
+//!
+//! The replay backend reads a capture file (see [`crate::capture`]) and decodes
+//! its frames again, so they pass through the same pipeline as frames received
+//! over Bluetooth. Measurements keep the timestamps they were recorded with.
+//! Frames are replayed as fast as possible, or in real time with the spacing
+//! they were recorded with.
+
+use super::{
+    DecodeError, FrameDecoder, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, ScanConfig,
+    ScanError,
+};
+use crate::capture::{Record, from_hex};
+use crate::mac_address::MacAddress;
+use crate::measurement::Phy;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Start replaying the capture at `path`.
+///
+/// The returned channel is closed once the whole capture has been replayed.
+/// Lines that cannot be replayed are sent as decode errors if
+/// `config.verbose` is set, like frames that fail to decode.
+pub async fn start_replay(
+    path: &Path,
+    realtime: bool,
+    config: &ScanConfig,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let file =
+        File::open(path).map_err(|e| ScanError::Replay(format!("{}: {}", path.display(), e)))?;
+    let name = path.display().to_string();
+    let verbose = config.verbose;
+
+    let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    config.spawn(|config| async move {
+        let mut pacer = Pacer::new(realtime);
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    if verbose {
+                        let err = DecodeError::InvalidData(format!("{name}: {e}"));
+                        let _ = tx.send(Err(err)).await;
+                    }
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = match serde_json::from_str::<Record>(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    if verbose {
+                        let err = DecodeError::InvalidData(format!("{name}:{}: {e}", index + 1));
+                        let _ = tx.send(Err(err)).await;
+                    }
+                    continue;
+                }
+            };
+
+            let timestamp = UNIX_EPOCH + Duration::from_nanos(record.timestamp);
+            if !pacer.wait(timestamp, &tx).await {
+                break;
+            }
+
+            let result = match replay_record(&record, &config) {
+                Ok(result) => result,
+                Err(e) => Err(DecodeError::InvalidData(format!(
+                    "{name}:{}: {e}",
+                    index + 1
+                ))),
+            };
+            if (result.is_ok() || verbose) && tx.send(result).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Spaces out replayed frames the way they were captured, when replaying in
+/// real time.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    realtime: bool,
+    /// Capture time of the first frame, and when it was replayed
+    clock: Option<(SystemTime, Instant)>,
+}
+
+impl Pacer {
+    pub(crate) fn new(realtime: bool) -> Self {
+        Self {
+            realtime,
+            clock: None,
+        }
+    }
+
+    /// Wait until the frame captured at `timestamp` is due. Returns `false` if
+    /// `tx` was closed in the meantime.
+    pub(crate) async fn wait<T>(&mut self, timestamp: SystemTime, tx: &mpsc::Sender<T>) -> bool {
+        if !self.realtime {
+            return true;
+        }
+        let (start, started) = *self.clock.get_or_insert((timestamp, Instant::now()));
+        let offset = timestamp.duration_since(start).unwrap_or_default();
+        tokio::select! {
+            biased;
+            _ = tx.closed() => false,
+            _ = tokio::time::sleep_until(started + offset) => true,
+        }
+    }
+}
+
+/// Decode a recorded frame again, with the timestamp it was recorded with.
+///
+/// The outer error describes a malformed record; the inner result is the
+/// outcome of decoding the recorded data.
+fn replay_record(record: &Record, config: &ScanConfig) -> Result<MeasurementResult, String> {
+    let mac: MacAddress = record.mac.parse().map_err(|e| format!("{e}"))?;
+    let data =
+        from_hex(&record.data).ok_or_else(|| format!("invalid hex data {:?}", record.data))?;
+    let phy = match record.phy.as_deref() {
+        Some(phy) => Some(parse_phy(phy).ok_or_else(|| format!("unknown PHY {phy:?}"))?),
+        None => None,
+    };
+
+    let decoder = FrameDecoder::new(
+        record.adapter.as_deref().map(Arc::from),
+        config.recorder.clone(),
+    );
+    let timestamp = UNIX_EPOCH + Duration::from_nanos(record.timestamp);
+    Ok(decoder
+        .decode(mac, record.rssi, phy, &data)
+        .map(|mut measurement| {
+            measurement.timestamp = timestamp;
+            measurement
+        }))
+}
+
+/// Parse a PHY as written by its `Display` implementation.
+fn parse_phy(phy: &str) -> Option<Phy> {
+    match phy {
+        "1M" => Some(Phy::Le1M),
+        "2M" => Some(Phy::Le2M),
+        "coded" => Some(Phy::LeCoded),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::Format;
+    use crate::test_utils::{TempFile, collect};
+    use std::io::Write;
+
+    const V5_DATA: &str = "0512fc5394c37c0004fffc040cac364200cdcbb8334c884f";
+
+    /// Write `lines` to a capture file unique to the calling test.
+    fn capture(test: &str, lines: &[String]) -> TempFile {
+        let path = TempFile::new(&format!("{test}.jsonl"));
+        let mut file = File::create(&path).unwrap();
+        for line in lines {
+            writeln!(file, "{line}").unwrap();
+        }
+        path
+    }
+
+    fn line(timestamp_s: u64, data: &str) -> String {
+        format!(
+            r#"{{"timestamp":{},"mac":"D4:E9:1A:3F:D8:C7","adapter":"hci1","rssi":-61,"phy":"coded","data":"{}"}}"#,
+            timestamp_s * 1_000_000_000,
+            data
+        )
+    }
+
+    #[tokio::test]
+    async fn test_replay_decodes_recorded_frames() {
+        let path = capture("decode", &[line(1_700_000_000, V5_DATA)]);
+        let rx = start_replay(&path, false, &ScanConfig::default())
+            .await
+            .unwrap();
+        let results = collect(rx).await;
+
+        assert_eq!(results.len(), 1);
+        let measurement = results[0].as_ref().unwrap();
+        assert_eq!(measurement.format, Format::V5);
+        assert_eq!(measurement.mac.to_string(), "D4:E9:1A:3F:D8:C7");
+        assert_eq!(measurement.adapter.as_deref(), Some("hci1"));
+        assert_eq!(measurement.phy, Some(Phy::LeCoded));
+        assert_eq!(
+            measurement.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert_eq!(measurement.temperature, Some(24.3));
+    }
+
+    #[tokio::test]
+    async fn test_replay_reports_bad_lines_only_when_verbose() {
+        let lines = [
+            "not json".to_string(),
+            line(1, "zz"),
+            line(2, "07"),
+            line(3, V5_DATA),
+        ];
+        let path = capture("errors", &lines);
+
+        let quiet = collect(
+            start_replay(&path, false, &ScanConfig::default())
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(quiet.len(), 1);
+        assert!(quiet[0].is_ok());
+
+        let config = ScanConfig {
+            verbose: true,
+            ..Default::default()
+        };
+        let verbose = collect(start_replay(&path, false, &config).await.unwrap()).await;
+
+        assert_eq!(verbose.len(), 4);
+        let errors: Vec<String> = verbose[..3]
+            .iter()
+            .map(|r| r.as_ref().unwrap_err().to_string())
+            .collect();
+        assert!(errors[0].ends_with(":1: expected ident at line 1 column 2"));
+        assert!(errors[1].ends_with(r#":2: invalid hex data "zz""#));
+        assert!(errors[2].starts_with("Unsupported format"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_realtime_replay_keeps_spacing() {
+        let path = capture("realtime", &[line(100, V5_DATA), line(130, V5_DATA)]);
+        let start = Instant::now();
+        let mut rx = start_replay(&path, true, &ScanConfig::default())
+            .await
+            .unwrap();
+
+        let first = rx.recv().await.unwrap().unwrap();
+        assert_eq!(Instant::now() - start, Duration::ZERO);
+        let second = rx.recv().await.unwrap().unwrap();
+        assert_eq!(Instant::now() - start, Duration::from_secs(30));
+        assert!(rx.recv().await.is_none());
+
+        assert_eq!(
+            second.timestamp.duration_since(first.timestamp).unwrap(),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_capture_fails_to_start() {
+        let err = start_replay(
+            Path::new("/nonexistent/capture.jsonl"),
+            false,
+            &ScanConfig::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, ScanError::Replay(_)));
+    }
+
+    #[test]
+    fn test_parse_phy_round_trip() {
+        for phy in [Phy::Le1M, Phy::Le2M, Phy::LeCoded] {
+            assert_eq!(parse_phy(&phy.to_string()), Some(phy));
+        }
+        assert_eq!(parse_phy("3M"), None);
+    }
+}
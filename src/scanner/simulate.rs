@@ -0,0 +1,457 @@
+//! Synthetic RuuviTags for demos and load tests.
+//!
+//! The simulate backend does not use the radio. It makes up virtual tags that
+//! broadcast V5, V6 and E1 frames in turn, with addresses counting up from
+//! `C0:FF:EE:00:00:01`. Temperature and humidity follow a daily curve that is
+//! warmest and driest at 17:00 UTC, pressure drifts, and the measurement
+//! sequence and, now and then, the movement counter increment. Each frame is
+//! encoded into the bytes a real tag would send and decoded like a received
+//! frame, so the decode path is exercised too.
+//!
+//! Frames can be dropped (`--simulate-loss`) or cut short so that they fail to
+//! decode (`--simulate-corrupt`). A dropped frame still uses up a sequence
+//! number, like a frame the tag sent but the adapter did not receive.
+
+use super::{
+    FrameDecoder, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult, ScanConfig, ScanError,
+};
+use crate::encoder::encode_ruuvi_data;
+use crate::mac_address::MacAddress;
+use crate::measurement::{Format, Measurement};
+use std::f64::consts::TAU;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Length of the daily curve in seconds.
+const DAY: f64 = 86_400.0;
+
+/// Warmest point of the daily curve, in seconds after midnight UTC.
+const PEAK: f64 = 17.0 * 3600.0;
+
+/// Chance of a tag being moved between two of its frames.
+const MOVEMENT_CHANCE: f64 = 0.02;
+
+/// Formats of the virtual tags, assigned in turn.
+const FORMATS: [Format; 3] = [Format::V5, Format::V6, Format::E1];
+
+/// How many virtual tags to simulate and how their frames arrive.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulateConfig {
+    /// Number of virtual tags
+    pub tags: u16,
+    /// Time between two frames of the same tag. Zero sends frames as fast as
+    /// they are consumed.
+    pub interval: Duration,
+    /// Share of frames that are dropped, from 0 to 1
+    pub loss: f64,
+    /// Share of frames that are cut short, from 0 to 1
+    pub corrupt: f64,
+    /// Seed for the made-up values, to repeat a simulation
+    pub seed: Option<u64>,
+}
+
+/// Parse a share of frames, either as a fraction (`0.05`) or as a
+/// percentage (`5%`).
+pub fn parse_share(src: &str) -> Result<f64, String> {
+    let src = src.trim();
+    let (number, scale) = match src.strip_suffix('%') {
+        Some(number) => (number, 100.0),
+        None => (src, 1.0),
+    };
+    let share = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("invalid share: {src}"))?
+        / scale;
+    if (0.0..=1.0).contains(&share) {
+        Ok(share)
+    } else {
+        Err(format!(
+            "share must be between 0 and 1 (or 0% and 100%): {src}"
+        ))
+    }
+}
+
+/// A made-up tag and the state its next frame is derived from.
+#[derive(Debug, Clone)]
+struct VirtualTag {
+    mac: MacAddress,
+    format: Format,
+    /// Typical RSSI in dBm
+    rssi: i16,
+    /// Mean temperature of the day in Celsius
+    temperature: f64,
+    /// Difference between the mean and the warmest temperature in Celsius
+    swing: f64,
+    /// Mean humidity of the day in percent
+    humidity: f64,
+    /// Current pressure in Pascals
+    pressure: f64,
+    /// Battery voltage in Volts
+    battery: f64,
+    /// Typical PM2.5 concentration in ug/m3
+    pm2_5: f64,
+    sequence: u32,
+    movement_counter: u32,
+}
+
+impl VirtualTag {
+    /// The `index`th virtual tag, counting from 1.
+    fn new(index: u16, rng: &mut fastrand::Rng) -> Self {
+        let [high, low] = index.to_be_bytes();
+        let format = FORMATS[usize::from(index.saturating_sub(1)) % FORMATS.len()];
+        Self {
+            mac: MacAddress([0xC0, 0xFF, 0xEE, 0x00, high, low]),
+            format,
+            rssi: rng.i16(-90..=-50),
+            temperature: 18.0 + 6.0 * rng.f64(),
+            swing: 2.0 + 4.0 * rng.f64(),
+            humidity: 35.0 + 20.0 * rng.f64(),
+            pressure: 99_000.0 + 4_000.0 * rng.f64(),
+            battery: 2.8 + 0.3 * rng.f64(),
+            pm2_5: 2.0 + 8.0 * rng.f64(),
+            sequence: rng.u32(..1000),
+            movement_counter: rng.u32(..255),
+        }
+    }
+
+    /// What the tag measures at `now`. Advances its counters.
+    fn measure(&mut self, now: SystemTime, rng: &mut fastrand::Rng) -> Measurement {
+        let day = daily_curve(now);
+        let daylight = day.max(0.0);
+        let moved = rng.f64() < MOVEMENT_CHANCE;
+        let mut noise = |amplitude: f64| amplitude * (2.0 * rng.f64() - 1.0);
+
+        self.pressure = (self.pressure + noise(10.0)).clamp(96_000.0, 105_000.0);
+        self.sequence = (self.sequence + 1) % sequence_limit(self.format);
+        if moved {
+            self.movement_counter = (self.movement_counter + 1) % 255;
+        }
+
+        let mut measurement = Measurement {
+            mac: self.mac,
+            format: self.format,
+            adapter: None,
+            phy: None,
+            timestamp: now,
+            temperature: Some(self.temperature + self.swing * day + noise(0.05)),
+            humidity: Some((self.humidity - 2.5 * self.swing * day + noise(0.2)).clamp(0.0, 100.0)),
+            pressure: Some(self.pressure.round()),
+            pressure_sea_level: None,
+            pressure_trend_3h: None,
+            pressure_tendency: None,
+            battery: None,
+            tx_power: None,
+            movement_counter: None,
+            measurement_sequence: Some(self.sequence),
+            acceleration: None,
+            pm1_0: None,
+            pm2_5: None,
+            pm4_0: None,
+            pm10_0: None,
+            co2: None,
+            voc_index: None,
+            nox_index: None,
+            luminosity: None,
+            tag_id: None,
+        };
+        match self.format {
+            // Formats 2 and 4 carry only the weather
+            Format::V2 | Format::V4 => {}
+            Format::V3 | Format::V5 => {
+                measurement.battery = Some(self.battery + noise(0.005));
+                measurement.tx_power = Some(4);
+                measurement.movement_counter = Some(self.movement_counter);
+                // At rest, the tag only feels gravity
+                let shake = if moved { 0.5 } else { 0.01 };
+                measurement.acceleration = Some((noise(shake), noise(shake), 1.0 + noise(shake)));
+            }
+            Format::V6 | Format::E1 => {
+                let pm2_5 = (self.pm2_5 + noise(1.0)).max(0.0);
+                measurement.pm2_5 = Some(pm2_5);
+                measurement.co2 = Some((450.0 + 600.0 * daylight + noise(20.0)).round());
+                measurement.voc_index = Some((100.0 + noise(20.0)).round());
+                measurement.nox_index = Some(1.0);
+                measurement.luminosity = Some(800.0 * daylight);
+                if self.format == Format::E1 {
+                    measurement.pm1_0 = Some(0.7 * pm2_5);
+                    measurement.pm4_0 = Some(1.1 * pm2_5);
+                    measurement.pm10_0 = Some(1.2 * pm2_5);
+                }
+            }
+        }
+        measurement
+    }
+}
+
+/// Where `now` is on the daily curve: 1 at the warmest point, -1 twelve
+/// hours later.
+fn daily_curve(now: SystemTime) -> f64 {
+    let seconds = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+        % DAY;
+    (TAU * (seconds - PEAK) / DAY).cos()
+}
+
+/// The value the measurement sequence of `format` wraps at.
+fn sequence_limit(format: Format) -> u32 {
+    match format {
+        // Formats 2 to 4 have no measurement sequence
+        Format::V2 | Format::V3 | Format::V4 | Format::V5 => 0xFFFF,
+        Format::V6 => 0x100,
+        Format::E1 => 0xFF_FFFF,
+    }
+}
+
+/// Start sending the frames of the virtual tags in `simulate`.
+///
+/// The frames of the tags are spread evenly over the interval. The simulation
+/// runs until the returned receiver is dropped.
+pub async fn start_simulation(
+    simulate: &SimulateConfig,
+    config: &ScanConfig,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let mut rng = simulate
+        .seed
+        .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+    let mut tags: Vec<_> = (1..=simulate.tags)
+        .map(|index| VirtualTag::new(index, &mut rng))
+        .collect();
+    let (loss, corrupt) = (simulate.loss, simulate.corrupt);
+    let period = simulate.interval / u32::from(simulate.tags.max(1));
+    let mut ticks = (!period.is_zero()).then(|| {
+        let mut ticks = tokio::time::interval(period);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticks
+    });
+
+    let decoder = FrameDecoder::new(None, config.recorder.clone());
+    let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+    config.spawn(|config| async move {
+        for index in (0..tags.len()).cycle() {
+            tokio::select! {
+                biased;
+                _ = tx.closed() => break,
+                _ = tick(&mut ticks) => {}
+            }
+            let tag = &mut tags[index];
+            let measurement = tag.measure(SystemTime::now(), &mut rng);
+            if rng.f64() < loss {
+                continue;
+            }
+            let mut frame = encode_ruuvi_data(&measurement);
+            if rng.f64() < corrupt {
+                frame.truncate(rng.usize(1..frame.len()));
+            }
+            let rssi = tag.rssi + rng.i16(-3..=3);
+
+            let result = decoder.decode(measurement.mac, Some(rssi), None, &frame);
+            if (result.is_ok() || config.verbose) && tx.send(result).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Wait for the next tick, or just let other tasks run if frames are not
+/// paced.
+async fn tick(ticks: &mut Option<Interval>) {
+    match ticks {
+        Some(ticks) => {
+            ticks.tick().await;
+        }
+        None => tokio::task::yield_now().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{DecodeError, decode_ruuvi_data};
+
+    fn simulate(tags: u16, interval: Duration) -> SimulateConfig {
+        SimulateConfig {
+            tags,
+            interval,
+            loss: 0.0,
+            corrupt: 0.0,
+            seed: Some(7),
+        }
+    }
+
+    fn at_hour(hour: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(20_000 * 86_400 + hour * 3600)
+    }
+
+    fn assert_close(actual: Option<f64>, expected: Option<f64>, tolerance: f64) {
+        let (actual, expected) = (actual.unwrap(), expected.unwrap());
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn test_parse_share() {
+        assert_eq!(parse_share("0.05"), Ok(0.05));
+        assert_eq!(parse_share("5%"), Ok(0.05));
+        assert_eq!(parse_share("0"), Ok(0.0));
+        assert!(parse_share("1.5").is_err());
+        assert!(parse_share("-1%").is_err());
+        assert!(parse_share("lots").is_err());
+    }
+
+    #[test]
+    fn test_frames_decode_to_simulated_values() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        for index in 1..=3 {
+            let mut tag = VirtualTag::new(index, &mut rng);
+            let simulated = tag.measure(at_hour(12), &mut rng);
+            let decoded = decode_ruuvi_data(tag.mac, &encode_ruuvi_data(&simulated)).unwrap();
+
+            assert_eq!(decoded.format, FORMATS[usize::from(index - 1)]);
+            assert_close(decoded.temperature, simulated.temperature, 0.0025);
+            assert_close(decoded.humidity, simulated.humidity, 0.00125);
+            assert_close(decoded.pressure, simulated.pressure, 1e-6);
+            assert_eq!(decoded.measurement_sequence, simulated.measurement_sequence);
+            if decoded.format == Format::V5 {
+                assert_close(decoded.battery, simulated.battery, 0.0005);
+                assert_eq!(decoded.tx_power, Some(4));
+                assert_eq!(decoded.movement_counter, simulated.movement_counter);
+                let (x, _, z) = decoded.acceleration.unwrap();
+                let (expected_x, _, expected_z) = simulated.acceleration.unwrap();
+                assert_close(Some(x), Some(expected_x), 0.0005);
+                assert_close(Some(z), Some(expected_z), 0.0005);
+            } else {
+                assert_close(decoded.pm2_5, simulated.pm2_5, 0.05);
+                assert_eq!(decoded.co2, simulated.co2);
+                assert_eq!(decoded.voc_index, simulated.voc_index);
+                assert_eq!(decoded.nox_index, simulated.nox_index);
+                // Format 6 has a logarithmic luminosity with a coarse resolution
+                let tolerance = if decoded.format == Format::V6 {
+                    30.0
+                } else {
+                    0.005
+                };
+                assert_close(decoded.luminosity, simulated.luminosity, tolerance);
+            }
+            if decoded.format == Format::E1 {
+                assert_close(decoded.pm10_0, simulated.pm10_0, 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn test_daily_curve() {
+        let mut rng = fastrand::Rng::with_seed(2);
+        let mut tag = VirtualTag::new(1, &mut rng);
+        let afternoon = tag.measure(at_hour(17), &mut rng);
+        let night = tag.measure(at_hour(5), &mut rng);
+        let morning = tag.measure(at_hour(11), &mut rng);
+
+        assert!(afternoon.temperature > morning.temperature);
+        assert!(morning.temperature > night.temperature);
+        assert!(afternoon.humidity < night.humidity);
+        assert_close(
+            afternoon.temperature,
+            Some(tag.temperature + tag.swing),
+            0.05,
+        );
+    }
+
+    #[test]
+    fn test_counters_increment_and_wrap() {
+        let mut rng = fastrand::Rng::with_seed(3);
+        let mut tag = VirtualTag::new(2, &mut rng);
+        assert_eq!(tag.format, Format::V6);
+        tag.sequence = 254;
+        let sequences: Vec<_> = (0..3)
+            .map(|_| tag.measure(at_hour(0), &mut rng).measurement_sequence)
+            .collect();
+        assert_eq!(sequences, [Some(255), Some(0), Some(1)]);
+
+        let mut tag = VirtualTag::new(1, &mut rng);
+        let first = tag.measure(at_hour(0), &mut rng).movement_counter.unwrap();
+        let moves = (0..1000)
+            .map(|_| tag.measure(at_hour(0), &mut rng).movement_counter.unwrap())
+            .fold((first, 0), |(last, moves), counter| {
+                (counter, moves + u32::from(counter != last))
+            })
+            .1;
+        assert!((1..100).contains(&moves), "{moves} moves");
+    }
+
+    #[tokio::test]
+    async fn test_tags_take_turns() {
+        let mut rx = start_simulation(
+            &simulate(3, Duration::from_millis(30)),
+            &ScanConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        let mut measurements = Vec::new();
+        for _ in 0..6 {
+            measurements.push(rx.recv().await.unwrap().unwrap());
+        }
+        let macs: Vec<_> = measurements.iter().map(|m| m.mac.to_string()).collect();
+        assert_eq!(
+            macs[..3],
+            [
+                "C0:FF:EE:00:00:01",
+                "C0:FF:EE:00:00:02",
+                "C0:FF:EE:00:00:03"
+            ]
+        );
+        assert_eq!(macs[..3], macs[3..]);
+        let formats: Vec<_> = measurements.iter().map(|m| m.format).collect();
+        assert_eq!(formats[..3], FORMATS);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_frames_fail_to_decode() {
+        let config = ScanConfig {
+            verbose: true,
+            ..ScanConfig::default()
+        };
+        let simulate = SimulateConfig {
+            corrupt: 1.0,
+            ..simulate(3, Duration::ZERO)
+        };
+        let mut rx = start_simulation(&simulate, &config).await.unwrap();
+        for _ in 0..30 {
+            let err = rx.recv().await.unwrap().unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    DecodeError::DecoderError(_) | DecodeError::InvalidData(_)
+                ),
+                "{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lost_frames_skip_sequence_numbers() {
+        let simulate = SimulateConfig {
+            loss: 0.5,
+            ..simulate(1, Duration::ZERO)
+        };
+        let mut rx = start_simulation(&simulate, &ScanConfig::default())
+            .await
+            .unwrap();
+        let mut gaps = 0;
+        let mut last = rx.recv().await.unwrap().unwrap().measurement_sequence;
+        for _ in 0..100 {
+            let sequence = rx.recv().await.unwrap().unwrap().measurement_sequence;
+            gaps += u32::from(sequence != last.map(|last| (last + 1) % 0xFFFF));
+            last = sequence;
+        }
+        assert!((20..80).contains(&gaps), "{gaps} gaps");
+    }
+}
@@ -0,0 +1,244 @@
+//! Supervision of scanner backends.
+//!
+//! A backend's scan task ends, closing its channel, when it loses the adapter:
+//! a USB dongle glitches, the adapter is reset, or bluetoothd restarts. The
+//! supervisor notices this, waits with exponential backoff, starts the backend
+//! again (re-opening the adapter and re-applying scan parameters and filters),
+//! and keeps forwarding into the same output channel.
+//!
+//! Restarts are reported on stderr as logfmt events, e.g.
+//!
+//! ```text
+//! event=scan_restart backend=hci adapter=hci1 attempt=1 delay_ms=1000
+//! event=scan_restart_failed backend=hci adapter=hci1 attempt=1 error="Bluetooth error: ..."
+//! event=scan_resumed backend=hci adapter=hci1 attempt=2
+//! event=scan_watchdog_restart backend=hci adapter=hci1 restart=1 idle_ms=120000
+//! ```
+
+use super::{
+    AdapterBackend, AdapterId, Backend, MEASUREMENT_CHANNEL_BUFFER_SIZE, MeasurementResult,
+    ScanConfig, ScanError, ScanTasks,
+};
+use crate::event::{Event, report};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Delay before the first restart attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound for the delay between restart attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Exponential backoff for restart attempts.
+///
+/// The delay doubles with each consecutive attempt, up to `MAX_BACKOFF`. A scan
+/// that ran for longer than `MAX_BACKOFF` before failing starts over from
+/// `INITIAL_BACKOFF`, so an occasional glitch is recovered from quickly while
+/// an adapter that fails right after every restart is not hammered.
+#[derive(Debug)]
+struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self { attempt: 0 }
+    }
+
+    /// Register a failure after the scan ran for `uptime`, returning the attempt
+    /// number and how long to wait before it.
+    fn next(&mut self, uptime: Duration) -> (u32, Duration) {
+        if uptime > MAX_BACKOFF {
+            self.attempt = 0;
+        }
+        self.attempt += 1;
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(1 << (self.attempt - 1).min(16))
+            .min(MAX_BACKOFF);
+        (self.attempt, delay)
+    }
+}
+
+/// Start a scan that is restarted whenever the backend stops.
+///
+/// The first start is not retried, so configuration errors such as a missing
+/// adapter or insufficient permissions are reported immediately.
+pub(super) async fn start_supervised(
+    backend: AdapterBackend,
+    config: &ScanConfig,
+    adapter: Option<AdapterId>,
+) -> Result<mpsc::Receiver<MeasurementResult>, ScanError> {
+    let first = super::start_adapter_scan(backend, config, adapter).await?;
+    let tasks = config.tasks.clone();
+    let config = config.clone();
+    let restart = move || {
+        let config = config.clone();
+        async move { super::start_adapter_scan(backend, &config, adapter).await }
+    };
+    Ok(supervise(first, backend.into(), adapter, restart, &tasks))
+}
+
+/// Forward measurements from `first`, and from the scans `restart` starts
+/// after it stops, into one channel, in a task spawned in `tasks`.
+fn supervise<F, Fut>(
+    first: mpsc::Receiver<MeasurementResult>,
+    backend: Backend,
+    adapter: Option<AdapterId>,
+    mut restart: F,
+    tasks: &ScanTasks,
+) -> mpsc::Receiver<MeasurementResult>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<mpsc::Receiver<MeasurementResult>, ScanError>> + Send,
+{
+    let (tx, rx) = mpsc::channel(MEASUREMENT_CHANNEL_BUFFER_SIZE);
+
+    tasks.spawn(async move {
+        let mut scan = first;
+        let mut started_at = Instant::now();
+        let mut backoff = Backoff::new();
+
+        loop {
+            loop {
+                // Return as soon as nobody is listening anymore, dropping
+                // `scan` so the backend stops scanning too
+                let result = tokio::select! {
+                    biased;
+                    _ = tx.closed() => return,
+                    result = scan.recv() => result,
+                };
+                let Some(result) = result else {
+                    break;
+                };
+                if tx.send(result).await.is_err() {
+                    return;
+                }
+            }
+
+            // The backend stopped; restart it until it comes back
+            let mut uptime = started_at.elapsed();
+            scan = loop {
+                let (attempt, delay) = backoff.next(uptime);
+                uptime = Duration::ZERO;
+                report(&Event::Restart {
+                    backend,
+                    adapter,
+                    attempt,
+                    delay,
+                });
+                tokio::select! {
+                    _ = tx.closed() => return,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+
+                match restart().await {
+                    Ok(scan) => {
+                        report(&Event::Resumed {
+                            backend,
+                            adapter,
+                            attempt,
+                        });
+                        break scan;
+                    }
+                    Err(e) => report(&Event::RestartFailed {
+                        backend,
+                        adapter,
+                        attempt,
+                        error: e.to_string(),
+                    }),
+                }
+            };
+            started_at = Instant::now();
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TEST_MAC, base_measurement};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    /// A channel that yields one measurement and then closes, like a backend
+    /// that lost its adapter.
+    fn short_lived_scan() -> mpsc::Receiver<MeasurementResult> {
+        let (tx, rx) = mpsc::channel(1);
+        tx.try_send(Ok(base_measurement(TEST_MAC, SystemTime::now())))
+            .unwrap();
+        rx
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervise_restarts_into_same_stream() {
+        let attempts = Arc::new(Mutex::new(VecDeque::from([
+            Err(ScanError::Bluetooth("adapter gone".to_string())),
+            Ok(short_lived_scan()),
+        ])));
+        let remaining = attempts.clone();
+        let restart = move || {
+            let next = remaining.lock().unwrap().pop_front();
+            async move {
+                // Once out of scripted attempts, keep failing
+                next.unwrap_or_else(|| Err(ScanError::Bluetooth("still gone".to_string())))
+            }
+        };
+
+        let mut rx = supervise(
+            short_lived_scan(),
+            Backend::Replay,
+            None,
+            restart,
+            &ScanTasks::default(),
+        );
+
+        assert!(rx.recv().await.unwrap().is_ok());
+        // Second measurement arrives after a failed and a successful restart
+        assert!(rx.recv().await.unwrap().is_ok());
+        assert!(attempts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervise_stops_scan_when_receiver_dropped() {
+        let (scan_tx, scan_rx) = mpsc::channel(1);
+        let restart = || async { Err(ScanError::Bluetooth("not restarted".to_string())) };
+
+        let rx = supervise(
+            scan_rx,
+            Backend::Replay,
+            None,
+            restart,
+            &ScanTasks::default(),
+        );
+        drop(rx);
+
+        // The backend sees its receiver go away, even without sending anything
+        scan_tx.closed().await;
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new();
+        let delays: Vec<u64> = (0..8)
+            .map(|_| backoff.next(Duration::ZERO).1.as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    #[test]
+    fn test_backoff_resets_after_long_uptime() {
+        let mut backoff = Backoff::new();
+        backoff.next(Duration::ZERO);
+        backoff.next(Duration::ZERO);
+        assert_eq!(backoff.next(Duration::ZERO), (3, Duration::from_secs(4)));
+
+        assert_eq!(
+            backoff.next(MAX_BACKOFF + Duration::from_secs(1)),
+            (1, INITIAL_BACKOFF)
+        );
+    }
+}
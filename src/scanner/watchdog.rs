@@ -0,0 +1,152 @@
+//! No-data watchdog for scanner backends.
+//!
+//! Some controllers keep scanning while delivering no advertisements at all
+//! until the scan is restarted. Backends use a [`Watchdog`] to notice when no
+//! RuuviTag frames have arrived for a while, restart scanning, and give up
+//! after a number of restarts that did not help.
+
+use super::{AdapterId, Backend, ScanConfig, ScanError};
+use crate::event::{self, Event};
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What a backend should do when the watchdog expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// Restart scanning; this is the given consecutive restart.
+    Restart(u32),
+    /// Restarting did not help; stop scanning with [`ScanError::NoData`].
+    GiveUp,
+}
+
+/// Tracks the time since the last RuuviTag frame.
+#[derive(Debug)]
+pub struct Watchdog {
+    timeout: Duration,
+    retries: u32,
+    restarts: u32,
+    deadline: Instant,
+}
+
+impl Watchdog {
+    /// Create a watchdog that expires after `timeout` without frames and gives
+    /// up after `retries` restarts that did not bring frames back.
+    pub fn new(timeout: Duration, retries: u32) -> Self {
+        Self {
+            timeout,
+            retries,
+            restarts: 0,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Create the watchdog configured for a scan, if any.
+    pub fn from_config(config: &ScanConfig) -> Option<Self> {
+        config
+            .watchdog
+            .map(|timeout| Self::new(timeout, config.watchdog_retries))
+    }
+
+    /// Record that a RuuviTag frame arrived.
+    #[inline]
+    pub fn feed(&mut self) {
+        self.restarts = 0;
+        self.deadline = Instant::now() + self.timeout;
+    }
+
+    /// Handle an expired deadline, deciding whether to restart or give up.
+    pub fn expire(&mut self) -> Expiry {
+        if self.restarts >= self.retries {
+            return Expiry::GiveUp;
+        }
+        self.restarts += 1;
+        self.deadline = Instant::now() + self.timeout;
+        Expiry::Restart(self.restarts)
+    }
+
+    /// Handle an expired deadline of a backend scanning on `adapter`.
+    ///
+    /// Reports restarts on stderr. Returns the error to end the scan with once
+    /// restarts no longer help.
+    pub fn expire_for(
+        &mut self,
+        backend: Backend,
+        adapter: Option<AdapterId>,
+        adapter_name: &str,
+    ) -> Result<(), ScanError> {
+        match self.expire() {
+            Expiry::Restart(restart) => {
+                event::report(&Event::WatchdogRestart {
+                    backend,
+                    adapter,
+                    restart,
+                    idle: self.timeout,
+                });
+                Ok(())
+            }
+            Expiry::GiveUp => Err(ScanError::NoData {
+                adapter: adapter_name.to_string(),
+                idle: self.timeout,
+                restarts: self.restarts,
+            }),
+        }
+    }
+}
+
+/// Await `fut`, or return `None` if the watchdog expires first.
+///
+/// Without a watchdog, this simply awaits `fut`.
+pub async fn watch<F: Future>(watchdog: Option<&Watchdog>, fut: F) -> Option<F::Output> {
+    match watchdog {
+        Some(watchdog) => tokio::time::timeout_at(watchdog.deadline, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gives_up_after_retries() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(120), 2);
+
+        assert_eq!(watchdog.expire(), Expiry::Restart(1));
+        assert_eq!(watchdog.expire(), Expiry::Restart(2));
+        assert_eq!(watchdog.expire(), Expiry::GiveUp);
+    }
+
+    #[test]
+    fn test_frames_reset_restarts() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(120), 1);
+
+        assert_eq!(watchdog.expire(), Expiry::Restart(1));
+        watchdog.feed();
+        assert_eq!(watchdog.expire(), Expiry::Restart(1));
+        assert_eq!(watchdog.expire(), Expiry::GiveUp);
+    }
+
+    #[test]
+    fn test_give_up_error() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(120), 0);
+        let err = watchdog.expire_for(Backend::Hci, None, "hci0").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "No RuuviTag advertisements received on hci0 for 120s after 0 scan restarts"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_times_out() {
+        let watchdog = Watchdog::new(Duration::from_secs(120), 1);
+
+        assert_eq!(watch(Some(&watchdog), async { 1 }).await, Some(1));
+        assert_eq!(
+            watch(Some(&watchdog), std::future::pending::<()>()).await,
+            None
+        );
+        assert_eq!(watch(None, async { 1 }).await, Some(1));
+    }
+}
@@ -1,6 +1,13 @@
 use crate::mac_address::MacAddress;
 use crate::measurement::{Format, Measurement};
+use crate::scanner::MeasurementResult;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
+use tokio::sync::mpsc;
 
 /// A stable MAC address for unit tests.
 pub const TEST_MAC: MacAddress = MacAddress([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
@@ -12,10 +19,15 @@ pub fn base_measurement(mac: MacAddress, timestamp: SystemTime) -> Measurement {
     Measurement {
         mac,
         format: Format::V5,
+        adapter: None,
+        phy: None,
         timestamp,
         temperature: None,
         humidity: None,
         pressure: None,
+        pressure_sea_level: None,
+        pressure_trend_3h: None,
+        pressure_tendency: None,
         battery: None,
         tx_power: None,
         movement_counter: None,
@@ -29,5 +41,74 @@ pub fn base_measurement(mac: MacAddress, timestamp: SystemTime) -> Measurement {
         voc_index: None,
         nox_index: None,
         luminosity: None,
+        tag_id: None,
     }
 }
+
+/// A writer whose output stays readable after it is handed over, e.g. to a
+/// recorder that needs an owned writer.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(pub Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// Everything written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A path in the temporary directory unique to the calling test, whose file is
+/// removed when this is dropped, also when the test fails.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    /// A path ending in `name`, e.g. `capture.jsonl`. The file is not created.
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        TempFile(std::env::temp_dir().join(format!(
+            "ruuvitag-listener-{}-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            name
+        )))
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Receive everything a backend sends until it closes the channel.
+pub async fn collect(mut rx: mpsc::Receiver<MeasurementResult>) -> Vec<MeasurementResult> {
+    let mut results = Vec::new();
+    while let Some(result) = rx.recv().await {
+        results.push(result);
+    }
+    results
+}
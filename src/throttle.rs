@@ -3,10 +3,13 @@
 //! This module provides per-device throttling to limit how often measurements
 //! are emitted for each individual RuuviTag. This is useful for reducing output
 //! volume when tags broadcast frequently but data changes slowly.
+//!
+//! Time is measured with the measurement timestamps, so replaying a capture
+//! as fast as possible throttles it as if it were received live.
 
 use crate::mac_address::MacAddress;
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::time::{Duration, SystemTime};
 
 /// A throttle that limits the rate of events per device (identified by MAC address).
 ///
@@ -23,7 +26,7 @@ pub struct Throttle {
     /// Minimum time between events for each device
     interval: Duration,
     /// Last event time for each MAC address (using efficient MacAddress keys)
-    last_seen: HashMap<MacAddress, Instant>,
+    last_seen: HashMap<MacAddress, SystemTime>,
     /// Counter for periodic cleanup
     check_count: usize,
 }
@@ -65,33 +68,36 @@ impl Throttle {
     ///
     /// Returns `true` if enough time has passed since the last event from this
     /// device (or if this is the first event). If `true` is returned, the
-    /// internal timer for this device is reset.
+    /// internal timer for this device is reset. An event older than the last
+    /// one, e.g. after the clock was set back, is allowed too.
     ///
     /// Periodically cleans up stale entries to prevent memory leaks.
     ///
     /// # Arguments
     /// * `mac` - The MAC address of the device (efficient 6-byte representation)
+    /// * `now` - When the event happened, i.e. the measurement timestamp
     ///
     /// # Returns
     /// `true` if the event should be emitted, `false` if it should be throttled
-    pub fn should_emit(&mut self, mac: MacAddress) -> bool {
+    pub fn should_emit(&mut self, mac: MacAddress, now: SystemTime) -> bool {
         // Periodically clean up stale entries, but only if we have enough
         // entries to make it worthwhile
         self.check_count += 1;
         if self.check_count >= CLEANUP_CHECK_INTERVAL {
             self.check_count = 0;
             if self.last_seen.len() > CLEANUP_SIZE_THRESHOLD {
-                self.cleanup_stale();
+                self.cleanup_stale(now);
             }
         }
 
-        let now = Instant::now();
-
         // Use entry API for zero-allocation updates on existing keys
         use std::collections::hash_map::Entry;
         match self.last_seen.entry(mac) {
             Entry::Occupied(mut entry) => {
-                if now.duration_since(*entry.get()) < self.interval {
+                if now
+                    .duration_since(*entry.get())
+                    .is_ok_and(|elapsed| elapsed < self.interval)
+                {
                     false
                 } else {
                     entry.insert(now);
@@ -108,19 +114,19 @@ impl Throttle {
     /// Remove stale entries from the throttle.
     ///
     /// Entries are considered stale if they haven't been updated in more than
-    /// `CLEANUP_THRESHOLD_MULTIPLIER * interval` time. This prevents memory
-    /// leaks when devices stop broadcasting or are removed.
-    fn cleanup_stale(&mut self) {
+    /// `CLEANUP_THRESHOLD_MULTIPLIER * interval` time before `now`. This
+    /// prevents memory leaks when devices stop broadcasting or are removed.
+    fn cleanup_stale(&mut self, now: SystemTime) {
         if self.interval == Duration::ZERO {
             // No cleanup needed for zero interval
             return;
         }
 
         let threshold = self.interval * CLEANUP_THRESHOLD_MULTIPLIER;
-        let now = Instant::now();
 
-        self.last_seen
-            .retain(|_mac, last_seen| now.duration_since(*last_seen) <= threshold);
+        self.last_seen.retain(
+            |_mac, last_seen| !matches!(now.duration_since(*last_seen), Ok(age) if age > threshold),
+        );
     }
 }
 
@@ -201,47 +207,49 @@ mod tests {
     const MAC1: MacAddress = MacAddress([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
     const MAC2: MacAddress = MacAddress([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
     const MAC_ZERO: MacAddress = MacAddress([0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    const T0: SystemTime = SystemTime::UNIX_EPOCH;
+
+    fn at(millis: u64) -> SystemTime {
+        T0 + Duration::from_millis(millis)
+    }
 
     #[test]
     fn test_throttle_first_event_allowed() {
         let mut throttle = Throttle::new(Duration::from_secs(1));
-        assert!(throttle.should_emit(MAC1));
+        assert!(throttle.should_emit(MAC1, T0));
     }
 
     #[test]
     fn test_throttle_immediate_second_event_blocked() {
         let mut throttle = Throttle::new(Duration::from_secs(1));
-        assert!(throttle.should_emit(MAC1));
-        assert!(!throttle.should_emit(MAC1));
+        assert!(throttle.should_emit(MAC1, T0));
+        assert!(!throttle.should_emit(MAC1, T0));
     }
 
     #[test]
     fn test_throttle_different_devices_independent() {
         let mut throttle = Throttle::new(Duration::from_secs(1));
-        assert!(throttle.should_emit(MAC1));
-        assert!(throttle.should_emit(MAC2));
-        assert!(!throttle.should_emit(MAC1));
-        assert!(!throttle.should_emit(MAC2));
+        assert!(throttle.should_emit(MAC1, T0));
+        assert!(throttle.should_emit(MAC2, T0));
+        assert!(!throttle.should_emit(MAC1, T0));
+        assert!(!throttle.should_emit(MAC2, T0));
     }
 
     #[test]
     fn test_throttle_zero_interval() {
         let mut throttle = Throttle::new(Duration::ZERO);
-        assert!(throttle.should_emit(MAC1));
-        assert!(throttle.should_emit(MAC1));
+        assert!(throttle.should_emit(MAC1, T0));
+        assert!(throttle.should_emit(MAC1, T0));
     }
 
     #[test]
     fn test_throttle_allowed_after_interval_passes() {
         let mut throttle = Throttle::new(Duration::from_millis(10));
-        assert!(throttle.should_emit(MAC1));
-        assert!(!throttle.should_emit(MAC1));
-
-        // Wait for the interval to pass
-        std::thread::sleep(Duration::from_millis(15));
+        assert!(throttle.should_emit(MAC1, T0));
+        assert!(!throttle.should_emit(MAC1, T0));
 
-        // Should now be allowed again
-        assert!(throttle.should_emit(MAC1));
+        // Should be allowed again once the interval has passed
+        assert!(throttle.should_emit(MAC1, at(15)));
     }
 
     #[test]
@@ -256,7 +264,7 @@ mod tests {
         // First event from each should be allowed
         for mac in &macs {
             assert!(
-                throttle.should_emit(*mac),
+                throttle.should_emit(*mac, T0),
                 "First event for {} should be allowed",
                 mac
             );
@@ -265,7 +273,7 @@ mod tests {
         // Second event from each should be blocked
         for mac in &macs {
             assert!(
-                !throttle.should_emit(*mac),
+                !throttle.should_emit(*mac, T0),
                 "Second event for {} should be blocked",
                 mac
             );
@@ -277,43 +285,58 @@ mod tests {
         let mut throttle = Throttle::new(Duration::from_secs(1));
 
         // Zero address is a valid key
-        assert!(throttle.should_emit(MAC_ZERO));
-        assert!(!throttle.should_emit(MAC_ZERO));
+        assert!(throttle.should_emit(MAC_ZERO, T0));
+        assert!(!throttle.should_emit(MAC_ZERO, T0));
     }
 
     #[test]
     fn test_throttle_timer_resets_on_emit() {
         let mut throttle = Throttle::new(Duration::from_millis(20));
 
-        assert!(throttle.should_emit(MAC1));
+        assert!(throttle.should_emit(MAC1, T0));
 
-        // Wait partial interval
-        std::thread::sleep(Duration::from_millis(15));
-        assert!(!throttle.should_emit(MAC1));
+        // Partial interval
+        assert!(!throttle.should_emit(MAC1, at(15)));
 
-        // Wait for full interval from first emit
-        std::thread::sleep(Duration::from_millis(10));
-        assert!(throttle.should_emit(MAC1)); // Allowed - timer reset here
+        // Full interval from first emit
+        assert!(throttle.should_emit(MAC1, at(25))); // Allowed - timer reset here
 
         // Immediately after, should be blocked again
-        assert!(!throttle.should_emit(MAC1));
+        assert!(!throttle.should_emit(MAC1, at(25)));
     }
 
     #[test]
     fn test_throttle_blocked_event_does_not_reset_timer() {
         let mut throttle = Throttle::new(Duration::from_millis(30));
 
-        assert!(throttle.should_emit(MAC1)); // t=0, timer starts
+        assert!(throttle.should_emit(MAC1, T0)); // t=0, timer starts
 
-        std::thread::sleep(Duration::from_millis(10));
-        assert!(!throttle.should_emit(MAC1)); // t=10, blocked, timer NOT reset
+        assert!(!throttle.should_emit(MAC1, at(10))); // t=10, blocked, timer NOT reset
 
-        std::thread::sleep(Duration::from_millis(10));
-        assert!(!throttle.should_emit(MAC1)); // t=20, still blocked
+        assert!(!throttle.should_emit(MAC1, at(20))); // t=20, still blocked
 
-        std::thread::sleep(Duration::from_millis(15));
         // t=35, now past the 30ms interval from t=0
-        assert!(throttle.should_emit(MAC1)); // Should be allowed
+        assert!(throttle.should_emit(MAC1, at(35))); // Should be allowed
+    }
+
+    #[test]
+    fn test_throttle_uses_event_time() {
+        let mut throttle = Throttle::new(Duration::from_secs(60));
+
+        // A replayed capture arrives all at once, but is throttled by its timestamps
+        assert!(throttle.should_emit(MAC1, T0));
+        assert!(!throttle.should_emit(MAC1, at(30_000)));
+        assert!(throttle.should_emit(MAC1, at(60_000)));
+    }
+
+    #[test]
+    fn test_throttle_allows_events_from_the_past() {
+        let mut throttle = Throttle::new(Duration::from_secs(60));
+
+        // E.g. after the clock was set back
+        assert!(throttle.should_emit(MAC1, at(60_000)));
+        assert!(throttle.should_emit(MAC1, at(1_000)));
+        assert!(!throttle.should_emit(MAC1, at(2_000)));
     }
 
     #[test]
@@ -367,18 +390,17 @@ mod tests {
         let mut throttle = Throttle::new(Duration::from_millis(10));
 
         // Add entries for two devices
-        assert!(throttle.should_emit(MAC1));
-        assert!(throttle.should_emit(MAC2));
+        assert!(throttle.should_emit(MAC1, at(200)));
+        assert!(throttle.should_emit(MAC2, at(200)));
 
         // Verify both are tracked
         assert_eq!(throttle.last_seen.len(), 2);
 
         // Manually set one entry to be very old (simulating stale device)
-        let old_time = Instant::now() - Duration::from_millis(200); // 20x the interval
-        throttle.last_seen.insert(MAC1, old_time);
+        throttle.last_seen.insert(MAC1, T0); // 20x the interval
 
         // Trigger cleanup
-        throttle.cleanup_stale();
+        throttle.cleanup_stale(at(200));
 
         // Stale entry should be removed, active entry should remain
         assert!(!throttle.last_seen.contains_key(&MAC1));
@@ -389,11 +411,11 @@ mod tests {
     fn test_throttle_cleanup_preserves_recent_entries() {
         let mut throttle = Throttle::new(Duration::from_millis(10));
 
-        assert!(throttle.should_emit(MAC1));
-        assert!(throttle.should_emit(MAC2));
+        assert!(throttle.should_emit(MAC1, T0));
+        assert!(throttle.should_emit(MAC2, T0));
 
         // Both entries are recent, cleanup should preserve both
-        throttle.cleanup_stale();
+        throttle.cleanup_stale(at(10));
 
         assert!(throttle.last_seen.contains_key(&MAC1));
         assert!(throttle.last_seen.contains_key(&MAC2));
@@ -403,11 +425,11 @@ mod tests {
     fn test_throttle_cleanup_zero_interval() {
         let mut throttle = Throttle::new(Duration::ZERO);
 
-        assert!(throttle.should_emit(MAC1));
+        assert!(throttle.should_emit(MAC1, T0));
         assert_eq!(throttle.last_seen.len(), 1);
 
         // Cleanup with zero interval should be a no-op
-        throttle.cleanup_stale();
+        throttle.cleanup_stale(at(200));
 
         // Entry should still be there
         assert!(throttle.last_seen.contains_key(&MAC1));
@@ -418,19 +440,18 @@ mod tests {
         let mut throttle = Throttle::new(Duration::from_millis(10));
 
         // Add a stale entry
-        let old_time = Instant::now() - Duration::from_millis(200);
-        throttle.last_seen.insert(MAC1, old_time);
+        throttle.last_seen.insert(MAC1, T0);
 
         // Add enough entries to exceed CLEANUP_SIZE_THRESHOLD
         for i in 0..(CLEANUP_SIZE_THRESHOLD + 10) as u8 {
             let mac = MacAddress([i, i.wrapping_add(1), 0x00, 0x00, 0x00, 0x00]);
-            throttle.should_emit(mac);
+            throttle.should_emit(mac, at(200));
         }
 
         let trigger_mac = MacAddress([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
         // Call should_emit enough times to trigger cleanup check
         for _ in 0..CLEANUP_CHECK_INTERVAL {
-            throttle.should_emit(trigger_mac);
+            throttle.should_emit(trigger_mac, at(200));
         }
 
         // Stale entry should be cleaned up
@@ -442,19 +463,18 @@ mod tests {
         let mut throttle = Throttle::new(Duration::from_millis(10));
 
         // Add a stale entry
-        let old_time = Instant::now() - Duration::from_millis(200);
-        throttle.last_seen.insert(MAC1, old_time);
+        throttle.last_seen.insert(MAC1, T0);
 
         // Add fewer entries than CLEANUP_SIZE_THRESHOLD
         for i in 0..10u8 {
             let mac = MacAddress([i, 0x00, 0x00, 0x00, 0x00, 0x00]);
-            throttle.should_emit(mac);
+            throttle.should_emit(mac, at(200));
         }
 
         let trigger_mac = MacAddress([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
         // Trigger check interval multiple times
         for _ in 0..CLEANUP_CHECK_INTERVAL * 2 {
-            throttle.should_emit(trigger_mac);
+            throttle.should_emit(trigger_mac, at(200));
         }
 
         // Stale entry should still exist (cleanup was skipped due to size threshold)
@@ -466,7 +486,7 @@ mod tests {
         let mut throttle = Throttle::new(Duration::from_secs(1));
 
         // Cleanup on empty map should not panic
-        throttle.cleanup_stale();
+        throttle.cleanup_stale(T0);
         assert_eq!(throttle.last_seen.len(), 0);
     }
 }